@@ -0,0 +1,48 @@
+//! Compares `search_case_insensitive`, which reuses one scratch buffer, against
+//! the previous implementation that lowercased every line into a new `String`.
+//!
+//! Run with `cargo bench --bench case_insensitive`.
+
+use std::{hint::black_box, time::Instant};
+
+use lilgrep::search_case_insensitive;
+
+fn allocating<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+
+    contents
+        .lines()
+        .filter(|line| line.to_lowercase().contains(&query))
+        .collect()
+}
+
+fn time<'a>(name: &str, f: impl Fn() -> Vec<&'a str>) -> usize {
+    const RUNS: u32 = 10;
+
+    let start = Instant::now();
+    let mut matches = 0;
+    for _ in 0..RUNS {
+        matches = black_box(f()).len();
+    }
+    println!("{name:>12}: {:?} per run", start.elapsed() / RUNS);
+
+    matches
+}
+
+fn main() {
+    let contents: String = (0..200_000)
+        .map(|i| {
+            format!(
+                "2024-01-01 12:00:{:02} INFO request {i} served in {}ms\n",
+                i % 60,
+                i % 97
+            )
+        })
+        .chain((0..100).map(|i| format!("2024-01-01 12:01:00 ERROR Timeout on request {i}\n")))
+        .collect();
+    println!("haystack: {} bytes", contents.len());
+
+    let expected = time("allocating", || allocating("timeout", &contents));
+    let actual = time("scratch", || search_case_insensitive("timeout", &contents));
+    assert_eq!(expected, actual);
+}
@@ -0,0 +1,90 @@
+//! Compares `search`, which scans the whole text for the query, against the
+//! previous implementation that checked every line with `str::contains`.
+//!
+//! The whole-text scan wins when matches are sparse, which is the usual case.
+//! A query matching every line (`request` below) is its worst case, since each
+//! hit still pays for locating its line boundaries.
+//!
+//! Queries longer than 32 bytes are found with Boyer-Moore-Horspool rather
+//! than `str::match_indices`, whose vectorised search is faster for shorter
+//! ones; `match indices` below is the whole-text scan as it was before, to
+//! compare the two on the longer queries.
+//!
+//! Run with `cargo bench --bench search`.
+
+use std::{hint::black_box, time::Instant};
+
+use lilgrep::search;
+
+fn line_by_line<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    contents
+        .lines()
+        .filter(|line| line.contains(query))
+        .collect()
+}
+
+fn match_indices<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    let mut lines = Vec::new();
+    let mut next_line = 0;
+    for (hit, _) in contents.match_indices(query) {
+        if hit < next_line {
+            continue;
+        }
+        let start = contents[..hit].rfind('\n').map_or(0, |i| i + 1);
+        let end = contents[hit..]
+            .find('\n')
+            .map_or(contents.len(), |i| hit + i);
+        lines.push(&contents[start..end]);
+        next_line = end + 1;
+    }
+    lines
+}
+
+fn time<'a>(name: &str, f: impl Fn() -> Vec<&'a str>) -> Vec<&'a str> {
+    const RUNS: u32 = 10;
+
+    let start = Instant::now();
+    let mut matches = Vec::new();
+    for _ in 0..RUNS {
+        matches = black_box(f());
+    }
+    println!("{name:>12}: {:?} per run", start.elapsed() / RUNS);
+
+    matches
+}
+
+fn main() {
+    let contents: String = (0..200_000)
+        .map(|i| {
+            if i % 2_000 == 0 {
+                format!("2024-01-01 12:01:00 ERROR timeout on request {i}, timeout again\n")
+            } else {
+                format!(
+                    "2024-01-01 12:00:00 INFO request {i} served in {}ms\n",
+                    i % 97
+                )
+            }
+        })
+        .collect();
+    println!("haystack: {} bytes", contents.len());
+
+    for query in [
+        "timeout",
+        "ERROR",
+        "request",
+        "timeout again",
+        "served in 42ms",
+        "connection refused by upstream",
+        "2024-01-01 12:00:00 INFO request 199999 served in",
+        "ERROR timeout on request 198000, timeout again",
+    ] {
+        println!("query {query:?}:");
+        let expected = time("line by line", || line_by_line(query, &contents));
+        let actual = time("whole text", || search(query, &contents));
+        assert_eq!(expected, actual);
+        if query.len() > 32 {
+            let before = time("match indices", || match_indices(query, &contents));
+            assert_eq!(expected, before);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+/* C declarations for lilgrep's `ffi` feature; see src/ffi.rs. */
+
+#ifndef LILGREP_H
+#define LILGREP_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The search succeeded. */
+#define LILGREP_OK 0
+/* A pointer argument was NULL. */
+#define LILGREP_NULL_POINTER 1
+/* The query or the contents weren't valid UTF-8. */
+#define LILGREP_INVALID_UTF8 2
+/* The search panicked; this is a bug in lilgrep. */
+#define LILGREP_PANICKED 3
+
+/* One occurrence of the query. */
+typedef struct LilgrepMatch {
+    /* The 1-based number of the line the occurrence is on. */
+    size_t line_number;
+    /* The byte offset of the occurrence in the contents searched. */
+    size_t start;
+    /* The byte offset just past the occurrence. */
+    size_t end;
+} LilgrepMatch;
+
+/*
+ * Finds every occurrence of `query` in `contents`, both NUL-terminated UTF-8
+ * strings, storing them in a new array at `*out_matches` and their number at
+ * `*out_len`. When nothing matches, `*out_matches` is set to NULL; otherwise
+ * the array must be released with `lilgrep_matches_free`. On any error both
+ * outputs are left untouched and a code other than LILGREP_OK is returned.
+ */
+int lilgrep_search(const char *query, const char *contents, bool ignore_case,
+                   LilgrepMatch **out_matches, size_t *out_len);
+
+/* Releases an array returned by `lilgrep_search`. NULL is ignored. */
+void lilgrep_matches_free(LilgrepMatch *matches, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* LILGREP_H */
@@ -0,0 +1,255 @@
+//! Splitting command line arguments into flags, flag values and positionals.
+
+use std::ffi::OsString;
+
+/// One argument as seen by `Parser::next`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Arg {
+    /// A flag, spelled `-x` for a short flag or `--name` for a long one.
+    Flag(String),
+    /// Anything that isn't a flag, which needn't be valid UTF-8.
+    Positional(OsString),
+}
+
+/// A hand-rolled command line parser.
+///
+/// Long flags are `--name`, with a value either attached as `--name=value` or
+/// given as the next argument. Short flags are `-x` and can be bundled, so `-ic`
+/// is `-i -c`; a short flag that takes a value uses the rest of the bundle if
+/// there is any, so `-j4` is `-j 4`. A lone `-` is a positional, and so is
+/// every argument after a lone `--`.
+///
+/// The caller decides which flags take a value by calling `value` after `next`
+/// returned them.
+///
+/// Flags must be valid UTF-8, and so must their values unless they are read
+/// with `value_os` and given as a separate argument. Positionals can be any
+/// `OsString`, so that paths the system allows but that aren't UTF-8 can
+/// still be named.
+pub(crate) struct Parser<I> {
+    args: I,
+    /// The unread characters of a bundle of short flags.
+    shorts: Option<(String, usize)>,
+    /// A value attached to the last long flag with `=`, and that flag.
+    attached: Option<(String, String)>,
+    /// Whether `--` has been seen.
+    terminated: bool,
+}
+
+impl<I: Iterator<Item = OsString>> Parser<I> {
+    /// Parses `args`, which must not include the program name.
+    pub(crate) fn new(args: I) -> Parser<I> {
+        Parser {
+            args,
+            shorts: None,
+            attached: None,
+            terminated: false,
+        }
+    }
+
+    /// Returns the next flag or positional.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the previous flag was given a value with `=` that
+    /// wasn't asked for with `value`, or if an argument starting with `-`
+    /// isn't valid UTF-8.
+    pub(crate) fn next(&mut self) -> Result<Option<Arg>, String> {
+        if let Some((flag, _)) = self.attached.take() {
+            return Err(format!("{flag} doesn't take a value"));
+        }
+
+        if let Some((bundle, pos)) = &mut self.shorts {
+            if let Some(short) = bundle[*pos..].chars().next() {
+                *pos += short.len_utf8();
+                return Ok(Some(Arg::Flag(format!("-{short}"))));
+            }
+            self.shorts = None;
+        }
+
+        let Some(arg) = self.args.next() else {
+            return Ok(None);
+        };
+        if self.terminated {
+            return Ok(Some(Arg::Positional(arg)));
+        }
+        let arg = match arg.into_string() {
+            Ok(arg) => arg,
+            Err(arg) if arg.as_encoded_bytes().starts_with(b"-") => {
+                return Err(format!("{} isn't valid UTF-8", arg.to_string_lossy()));
+            }
+            Err(arg) => return Ok(Some(Arg::Positional(arg))),
+        };
+        if arg == "--" {
+            self.terminated = true;
+            return self.next();
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            if let Some((name, value)) = long.split_once('=') {
+                let flag = format!("--{name}");
+                self.attached = Some((flag.clone(), value.to_string()));
+                return Ok(Some(Arg::Flag(flag)));
+            }
+            return Ok(Some(Arg::Flag(arg)));
+        }
+
+        if let Some(bundle) = arg.strip_prefix('-')
+            && !bundle.is_empty()
+        {
+            self.shorts = Some((bundle.to_string(), 0));
+            return self.next();
+        }
+
+        Ok(Some(Arg::Positional(arg.into())))
+    }
+
+    /// Returns the value of `flag`, the flag `next` just returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming `flag` if there is no value left, or if the value
+    /// isn't valid UTF-8.
+    pub(crate) fn value(&mut self, flag: &str) -> Result<String, String> {
+        self.value_os(flag)?.into_string().map_err(|value| {
+            format!(
+                "The value of {flag} isn't valid UTF-8: {}",
+                value.to_string_lossy()
+            )
+        })
+    }
+
+    /// Returns the value of `flag` like `value`, but as it was given, for
+    /// values such as paths that needn't be valid UTF-8.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming `flag` if there is no value left.
+    pub(crate) fn value_os(&mut self, flag: &str) -> Result<OsString, String> {
+        if let Some((_, value)) = self.attached.take() {
+            return Ok(value.into());
+        }
+
+        if let Some((bundle, pos)) = self.shorts.take()
+            && pos < bundle.len()
+        {
+            return Ok(bundle[pos..].into());
+        }
+
+        self.args
+            .next()
+            .ok_or_else(|| format!("Didn't get a value for {flag}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUE_FLAGS: &[&str] = &["-j", "--threads"];
+
+    /// Parses `args`, rendering flags as `-x`/`--name`, values as `=value` and
+    /// positionals as themselves.
+    fn parse(args: &[&str]) -> Result<Vec<String>, String> {
+        let mut parser = Parser::new(args.iter().map(OsString::from));
+        let mut parsed = Vec::new();
+        while let Some(arg) = parser.next()? {
+            match arg {
+                Arg::Flag(flag) if VALUE_FLAGS.contains(&flag.as_str()) => {
+                    let value = parser.value(&flag)?;
+                    parsed.push(flag);
+                    parsed.push(format!("={value}"));
+                }
+                Arg::Flag(flag) => parsed.push(flag),
+                Arg::Positional(positional) => parsed.push(positional.into_string().unwrap()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    #[test]
+    fn flags_and_positionals() {
+        let cases: &[(&[&str], &[&str])] = &[
+            (&[], &[]),
+            (&["needle"], &["needle"]),
+            (&["-"], &["-"]),
+            (&["-i"], &["-i"]),
+            (&["--word"], &["--word"]),
+            (&["-ic"], &["-i", "-c"]),
+            (&["-i", "-c", "needle"], &["-i", "-c", "needle"]),
+            (&["-é"], &["-é"]),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(parse(args).unwrap(), *expected, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn flag_values() {
+        let cases: &[(&[&str], &[&str])] = &[
+            (&["--threads", "4"], &["--threads", "=4"]),
+            (&["--threads=4"], &["--threads", "=4"]),
+            (&["--threads="], &["--threads", "="]),
+            (&["--threads=a=b"], &["--threads", "=a=b"]),
+            (&["--threads", "-i"], &["--threads", "=-i"]),
+            (&["-j", "4"], &["-j", "=4"]),
+            (&["-j4"], &["-j", "=4"]),
+            (&["-ij4"], &["-i", "-j", "=4"]),
+            (&["-ij", "4", "-c"], &["-i", "-j", "=4", "-c"]),
+            (&["-j=4"], &["-j", "==4"]),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(parse(args).unwrap(), *expected, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn errors_name_the_flag() {
+        let cases: &[(&[&str], &str)] = &[
+            (&["--threads"], "Didn't get a value for --threads"),
+            (&["-j"], "Didn't get a value for -j"),
+            (&["-ij"], "Didn't get a value for -j"),
+            (&["--word=yes"], "--word doesn't take a value"),
+            (&["--word=", "needle"], "--word doesn't take a value"),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(parse(args), Err(expected.to_string()), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn double_dash_ends_flags() {
+        let cases: &[(&[&str], &[&str])] = &[
+            (&["--"], &[]),
+            (&["-i", "--", "-c", "--word"], &["-i", "-c", "--word"]),
+            (&["--", "--"], &["--"]),
+            (&["--threads", "--", "-c"], &["--threads", "=--", "-c"]),
+            (&["needle", "--", "-"], &["needle", "-"]),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(parse(args).unwrap(), *expected, "{args:?}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn positionals_and_os_values_may_be_invalid_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let invalid = || OsString::from_vec(b"caf\xe9.txt".to_vec());
+        let mut parser = Parser::new(["--path".into(), invalid(), invalid()].into_iter());
+        assert_eq!(parser.next(), Ok(Some(Arg::Flag("--path".into()))));
+        assert_eq!(parser.value_os("--path"), Ok(invalid()));
+        assert_eq!(parser.next(), Ok(Some(Arg::Positional(invalid()))));
+
+        let mut parser = Parser::new(["-j".into(), invalid()].into_iter());
+        parser.next().unwrap();
+        assert_eq!(
+            parser.value("-j"),
+            Err("The value of -j isn't valid UTF-8: caf\u{fffd}.txt".into())
+        );
+
+        let mut parser = Parser::new([OsString::from_vec(b"--\xe9".to_vec())].into_iter());
+        assert_eq!(parser.next(), Err("--\u{fffd} isn't valid UTF-8".into()));
+    }
+}
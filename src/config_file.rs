@@ -0,0 +1,332 @@
+//! Default option values read from a configuration file.
+//!
+//! The file uses a small subset of TOML: one `key = value` pair per line, where
+//! the value is `true`, `false`, a non-negative integer or a string, either
+//! double-quoted or a bare word such as `always`. Blank lines and `#` comments
+//! are ignored.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Config, EnvProvider, Origin, ProcessEnv, parse_buffering, parse_color, parse_file_sort,
+};
+
+/// The name of the configuration file looked for in the current directory and
+/// in `$HOME`.
+const RC_NAME: &str = ".lilgreprc";
+
+/// Returns the configuration file to read, if any.
+///
+/// `LILGREP_CONFIG` names the file explicitly. Otherwise the first of these
+/// that exists is used: `.lilgreprc` in the current directory,
+/// `lilgrep/config.toml` under `$XDG_CONFIG_HOME` (or under `$HOME/.config`
+/// when that isn't set), and `.lilgreprc` in `$HOME`. Only one file is read.
+pub fn config_file_path() -> Option<PathBuf> {
+    config_file_path_in(&ProcessEnv, &env::current_dir().unwrap_or_default())
+}
+
+/// Returns the configuration file to read like `config_file_path`, looking
+/// the variables up in `env` and taking `dir` as the current directory.
+pub(crate) fn config_file_path_in(env: &impl EnvProvider, dir: &Path) -> Option<PathBuf> {
+    if let Some(path) = env.get("LILGREP_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+
+    let home = env.get("HOME").map(PathBuf::from);
+    let config_dir = match env.get("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => home.as_ref().map(|home| home.join(".config")),
+    };
+    [
+        Some(dir.join(RC_NAME)),
+        config_dir.map(|base| base.join("lilgrep").join("config.toml")),
+        home.map(|home| home.join(RC_NAME)),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|path| path.is_file())
+}
+
+/// Option defaults parsed from a configuration file.
+#[derive(Debug, Default)]
+pub struct FileDefaults {
+    path: PathBuf,
+    settings: Vec<Setting>,
+    warnings: Vec<String>,
+}
+
+/// A single `key = value` line.
+#[derive(Debug)]
+struct Setting {
+    key: &'static str,
+    value: Value,
+    line: usize,
+}
+
+#[derive(Debug)]
+enum Value {
+    Bool(bool),
+    Int(usize),
+    Str(String),
+}
+
+impl FileDefaults {
+    /// Reads and parses the configuration file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the file if it can't be read or parsed.
+    pub fn load(path: &Path) -> Result<FileDefaults, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("could not read config file {}: {err}", path.display()))?;
+        FileDefaults::parse(path, &text)
+    }
+
+    /// Parses the contents of a configuration file; `path` is only used in
+    /// error messages.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the file, line and key for a malformed line. A
+    /// key that isn't a known option is skipped, with a warning kept for
+    /// `warnings`, so a file written for a newer version still works.
+    pub fn parse(path: &Path, text: &str) -> Result<FileDefaults, String> {
+        let mut settings = Vec::new();
+        let mut warnings = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let error = |message: String| format!("{}:{line_number}: {message}", path.display());
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected `key = value`, found `{line}`")))?;
+            let key = key.trim();
+            let Some(&key) = OPTIONS.iter().find(|option| **option == key) else {
+                warnings.push(error(format!("unknown option `{key}`, ignored")));
+                continue;
+            };
+            let value = parse_value(value.trim())
+                .ok_or_else(|| error(format!("invalid value for `{key}`")))?;
+
+            settings.push(Setting {
+                key,
+                value,
+                line: line_number,
+            });
+        }
+
+        Ok(FileDefaults {
+            path: path.to_path_buf(),
+            settings,
+            warnings,
+        })
+    }
+
+    /// Returns a message for each line of the file that was skipped, naming
+    /// the file and line.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Sets each option on `config`, recording that it came from the file.
+    pub(crate) fn apply(&self, config: &mut Config) -> Result<(), String> {
+        for setting in &self.settings {
+            self.apply_setting(setting, config).map_err(|message| {
+                format!(
+                    "{}:{}: `{}`: {message}",
+                    self.path.display(),
+                    setting.line,
+                    setting.key
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn apply_setting(&self, setting: &Setting, config: &mut Config) -> Result<(), String> {
+        match setting.key {
+            "ignore_case" => config.ignore_case = setting.value.as_bool()?,
+            "whole_word" => config.whole_word = setting.value.as_bool()?,
+            "recursive" => config.recursive = setting.value.as_bool()?,
+            "follow" => config.follow = setting.value.as_bool()?,
+            "max_depth" => config.max_depth = Some(setting.value.as_int()?),
+            "threads" => config.threads = setting.value.as_int()?,
+            "file_sort" => config.file_sort = parse_file_sort(setting.value.as_str()?)?,
+            "buffering" => config.buffering = Some(parse_buffering(setting.value.as_str()?)?),
+            "color" => {
+                config.color = match &setting.value {
+                    Value::Bool(value) => Some(*value),
+                    value => parse_color(value.as_str()?)?,
+                }
+            }
+            "heading" => config.heading = Some(setting.value.as_bool()?),
+            "invert" => config.invert = setting.value.as_bool()?,
+            "trim" => config.trim = setting.value.as_bool()?,
+            "tab_width" => config.tab_width = setting.value.as_int()?,
+            "max_columns" => config.max_columns = setting.value.as_int()?,
+            _ => unreachable!("keys are checked against OPTIONS when parsing"),
+        }
+        config.origins.insert(setting.key, Origin::File);
+        Ok(())
+    }
+}
+
+impl Value {
+    fn as_bool(&self) -> Result<bool, &'static str> {
+        match self {
+            Value::Bool(value) => Ok(*value),
+            _ => Err("expected true or false"),
+        }
+    }
+
+    fn as_int(&self) -> Result<usize, &'static str> {
+        match self {
+            Value::Int(value) => Ok(*value),
+            _ => Err("expected a non-negative integer"),
+        }
+    }
+
+    fn as_str(&self) -> Result<&str, &'static str> {
+        match self {
+            Value::Str(value) => Ok(value),
+            _ => Err("expected a string"),
+        }
+    }
+}
+
+/// The keys a configuration file may set.
+const OPTIONS: &[&str] = &[
+    "ignore_case",
+    "whole_word",
+    "recursive",
+    "follow",
+    "max_depth",
+    "threads",
+    "file_sort",
+    "buffering",
+    "color",
+    "heading",
+    "invert",
+    "trim",
+    "tab_width",
+    "max_columns",
+];
+
+/// Parses a value, with any trailing comment removed.
+fn parse_value(value: &str) -> Option<Value> {
+    if let Some(rest) = value.strip_prefix('"') {
+        let (text, rest) = rest.split_once('"')?;
+        let rest = rest.trim();
+        return (rest.is_empty() || rest.starts_with('#')).then(|| Value::Str(text.to_string()));
+    }
+
+    let value = value.split('#').next().unwrap_or_default().trim();
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ if value.bytes().all(|byte| byte.is_ascii_digit()) => value.parse().ok().map(Value::Int),
+        _ if value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-') =>
+        {
+            Some(Value::Str(value.to_string()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<FileDefaults, String> {
+        FileDefaults::parse(Path::new("config.toml"), text)
+    }
+
+    fn build(defaults: &FileDefaults, args: &[&str]) -> Result<Config, String> {
+        let args = ["lilgrep"].iter().chain(args).map(|arg| arg.to_string());
+        Config::build_with_defaults(defaults, args)
+    }
+
+    #[test]
+    fn file_defaults_apply_and_argv_overrides() {
+        let defaults = parse(
+            "# defaults\n\
+             whole_word = true\n\
+             threads = 4 # enough\n\
+             file_sort = \"size\"\n",
+        )
+        .unwrap();
+
+        let config = build(&defaults, &["--threads", "2", "needle", "a.txt"]).unwrap();
+        assert!(config.whole_word);
+        assert_eq!(config.threads, 2);
+        assert_eq!(config.file_sort, crate::FileSort::Size);
+        assert_eq!(config.origin("whole_word"), Origin::File);
+        assert_eq!(config.origin("threads"), Origin::Argv);
+        assert_eq!(config.origin("recursive"), Origin::Default);
+    }
+
+    #[test]
+    fn errors_name_the_file_and_key() {
+        let err = parse("threads = 4.5\n").unwrap_err();
+        assert_eq!(err, "config.toml:1: invalid value for `threads`");
+
+        let defaults = parse("threads = many\n").unwrap();
+        let err = build(&defaults, &["needle", "a.txt"]).err().unwrap();
+        assert_eq!(
+            err,
+            "config.toml:1: `threads`: expected a non-negative integer"
+        );
+
+        let defaults = parse("threads = true\n").unwrap();
+        let err = build(&defaults, &["needle", "a.txt"]).err().unwrap();
+        assert_eq!(
+            err,
+            "config.toml:1: `threads`: expected a non-negative integer"
+        );
+    }
+
+    #[test]
+    fn unknown_keys_warn_and_bare_words_are_strings() {
+        let defaults = parse("ignore_case = true\ncolour = always\ncolor = always\n").unwrap();
+        assert_eq!(
+            defaults.warnings(),
+            ["config.toml:2: unknown option `colour`, ignored"]
+        );
+        let config = build(&defaults, &["needle", "a.txt"]).unwrap();
+        assert!(config.ignore_case);
+        assert_eq!(config.color, Some(true));
+        assert_eq!(config.origin("color"), Origin::File);
+
+        let config = build(&defaults, &["--color", "never", "needle", "a.txt"]).unwrap();
+        assert_eq!(config.color, Some(false));
+    }
+
+    #[test]
+    fn lilgreprc_is_found_in_the_directory_then_home() {
+        let root = env::temp_dir().join(format!("lilgrep-{}-rc", std::process::id()));
+        let (dir, home) = (root.join("project"), root.join("home"));
+        fs::create_dir_all(&dir).unwrap();
+        fs::create_dir_all(&home).unwrap();
+        let env =
+            std::collections::HashMap::from([("HOME".to_string(), home.display().to_string())]);
+
+        assert_eq!(config_file_path_in(&env, &dir), None);
+        fs::write(home.join(RC_NAME), "trim = true\n").unwrap();
+        assert_eq!(config_file_path_in(&env, &dir), Some(home.join(RC_NAME)));
+        fs::write(dir.join(RC_NAME), "trim = false\n").unwrap();
+        assert_eq!(config_file_path_in(&env, &dir), Some(dir.join(RC_NAME)));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,36 @@
+//! Looking up environment variables, so that a `Config` can be built without
+//! reading the environment of the running process.
+
+use std::{collections::HashMap, env};
+
+/// Where `Config::build` looks up environment variables.
+///
+/// `ProcessEnv` reads the real environment; a `HashMap` stands in for it in
+/// tests, or when embedding lilgrep in a tool that owns the environment.
+pub trait EnvProvider {
+    /// Returns the value of `key`, or `None` when it isn't set.
+    fn get(&self, key: &str) -> Option<String>;
+}
+
+/// The environment of the running process.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessEnv;
+
+impl EnvProvider for ProcessEnv {
+    fn get(&self, key: &str) -> Option<String> {
+        // A value that isn't valid Unicode is still set.
+        env::var_os(key).map(|value| value.to_string_lossy().into_owned())
+    }
+}
+
+impl EnvProvider for HashMap<String, String> {
+    fn get(&self, key: &str) -> Option<String> {
+        HashMap::get(self, key).cloned()
+    }
+}
+
+impl<E: EnvProvider + ?Sized> EnvProvider for &E {
+    fn get(&self, key: &str) -> Option<String> {
+        (**self).get(key)
+    }
+}
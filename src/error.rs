@@ -0,0 +1,66 @@
+//! Errors that name the file they concern.
+
+use std::{
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Something that went wrong with one of the files searched.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SearchError {
+    /// A filesystem operation on `path` failed.
+    Io {
+        /// What was being done, such as `read` or `list`.
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+impl SearchError {
+    /// Wraps `source` in a `SearchError::Io` for `action` on `path`, keeping
+    /// its kind, so it can still travel as an `io::Error`.
+    ///
+    /// An error that already names its path is returned as it is, so the
+    /// innermost, most specific action wins.
+    pub(crate) fn io(action: &'static str, path: &Path, source: io::Error) -> io::Error {
+        if source
+            .get_ref()
+            .is_some_and(|inner| inner.is::<SearchError>())
+        {
+            return source;
+        }
+        let kind = source.kind();
+        let path = path.to_path_buf();
+        io::Error::new(
+            kind,
+            SearchError::Io {
+                action,
+                path,
+                source,
+            },
+        )
+    }
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Io {
+                action,
+                path,
+                source,
+            } => write!(f, "failed to {action} '{}': {source}", path.display()),
+        }
+    }
+}
+
+impl Error for SearchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SearchError::Io { source, .. } => Some(source),
+        }
+    }
+}
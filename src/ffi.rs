@@ -0,0 +1,216 @@
+//! A C interface to searching, behind the `ffi` feature.
+//!
+//! Build it as a shared library with
+//! `cargo rustc --release --features ffi --crate-type cdylib`; the matching
+//! declarations are in `include/lilgrep.h`. No call ever unwinds into C: a
+//! panic inside the search is caught and reported as `LILGREP_PANICKED`.
+
+use std::{
+    ffi::{CStr, c_char, c_int},
+    panic, ptr,
+};
+
+use crate::{CaseMode, Searcher};
+
+/// The search succeeded.
+pub const LILGREP_OK: c_int = 0;
+/// A pointer argument was NULL.
+pub const LILGREP_NULL_POINTER: c_int = 1;
+/// The query or the contents weren't valid UTF-8.
+pub const LILGREP_INVALID_UTF8: c_int = 2;
+/// The search panicked; this is a bug in lilgrep.
+pub const LILGREP_PANICKED: c_int = 3;
+
+/// One occurrence of the query.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LilgrepMatch {
+    /// The 1-based number of the line the occurrence is on.
+    pub line_number: usize,
+    /// The byte offset of the occurrence in the contents searched.
+    pub start: usize,
+    /// The byte offset just past the occurrence.
+    pub end: usize,
+}
+
+/// Finds every occurrence of `query` in `contents`, both NUL-terminated UTF-8
+/// strings, storing them in a new array at `*out_matches` and their number at
+/// `*out_len`.
+///
+/// When nothing matches, `*out_matches` is set to NULL. Otherwise the array
+/// must be released with `lilgrep_matches_free`. On any error both outputs
+/// are left untouched and a `LILGREP_*` code other than `LILGREP_OK` is
+/// returned.
+///
+/// # Safety
+///
+/// Every pointer must be NULL or valid: `query` and `contents` must point to
+/// NUL-terminated strings, and `out_matches` and `out_len` to writable
+/// storage.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lilgrep_search(
+    query: *const c_char,
+    contents: *const c_char,
+    ignore_case: bool,
+    out_matches: *mut *mut LilgrepMatch,
+    out_len: *mut usize,
+) -> c_int {
+    if query.is_null() || contents.is_null() || out_matches.is_null() || out_len.is_null() {
+        return LILGREP_NULL_POINTER;
+    }
+    // SAFETY: both are non-NULL, and the caller promises they are
+    // NUL-terminated.
+    let (query, contents) = unsafe { (CStr::from_ptr(query), CStr::from_ptr(contents)) };
+    let (Ok(query), Ok(contents)) = (query.to_str(), contents.to_str()) else {
+        return LILGREP_INVALID_UTF8;
+    };
+
+    let Ok(matches) = panic::catch_unwind(|| find_matches(query, contents, ignore_case)) else {
+        return LILGREP_PANICKED;
+    };
+    let len = matches.len();
+    let array = if matches.is_empty() {
+        ptr::null_mut()
+    } else {
+        Box::into_raw(matches.into_boxed_slice()).cast::<LilgrepMatch>()
+    };
+    // SAFETY: both are non-NULL, and the caller promises they are writable.
+    unsafe {
+        *out_matches = array;
+        *out_len = len;
+    }
+
+    LILGREP_OK
+}
+
+/// Releases an array returned by `lilgrep_search`. NULL is ignored.
+///
+/// # Safety
+///
+/// `matches` must be NULL or an array from `lilgrep_search` that hasn't been
+/// released yet, and `len` the length returned with it.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lilgrep_matches_free(matches: *mut LilgrepMatch, len: usize) {
+    if matches.is_null() {
+        return;
+    }
+    // SAFETY: the caller promises this is the boxed slice made by
+    // `lilgrep_search`, with its length.
+    drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(matches, len)) });
+}
+
+/// Returns every occurrence of `query` in `contents`, line by line.
+fn find_matches(query: &str, contents: &str, ignore_case: bool) -> Vec<LilgrepMatch> {
+    let case = if ignore_case {
+        CaseMode::Insensitive
+    } else {
+        CaseMode::Sensitive
+    };
+    let searcher = Searcher::new(query, case, false);
+
+    let mut matches = Vec::new();
+    let mut offset = 0;
+    for (line_number, line) in (1..).zip(contents.split_inclusive('\n')) {
+        let text = line.strip_suffix('\n').unwrap_or(line);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        matches.extend(
+            searcher
+                .find_ranges(text)
+                .into_iter()
+                .map(|range| LilgrepMatch {
+                    line_number,
+                    start: offset + range.start,
+                    end: offset + range.end,
+                }),
+        );
+        offset += line.len();
+    }
+
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls `lilgrep_search` through raw pointers, as C would, copying the
+    /// matches out before freeing them.
+    fn search(
+        query: &[u8],
+        contents: &[u8],
+        ignore_case: bool,
+    ) -> Result<Vec<LilgrepMatch>, c_int> {
+        let mut matches = ptr::null_mut();
+        let mut len = usize::MAX;
+        let status = unsafe {
+            lilgrep_search(
+                query.as_ptr().cast(),
+                contents.as_ptr().cast(),
+                ignore_case,
+                &mut matches,
+                &mut len,
+            )
+        };
+        if status != LILGREP_OK {
+            return Err(status);
+        }
+        let copied = if matches.is_null() {
+            assert_eq!(len, 0);
+            Vec::new()
+        } else {
+            unsafe { std::slice::from_raw_parts(matches, len) }.to_vec()
+        };
+        unsafe { lilgrep_matches_free(matches, len) };
+        Ok(copied)
+    }
+
+    #[test]
+    fn matches_carry_line_numbers_and_offsets() {
+        let contents = b"Rust is fun\r\nnothing\ntrust rust\0";
+        let at = |line_number, start, end| LilgrepMatch {
+            line_number,
+            start,
+            end,
+        };
+
+        assert_eq!(
+            search(b"rust\0", contents, false),
+            Ok(vec![at(3, 22, 26), at(3, 27, 31)])
+        );
+        assert_eq!(
+            search(b"RUST\0", contents, true),
+            Ok(vec![at(1, 0, 4), at(3, 22, 26), at(3, 27, 31)])
+        );
+        assert_eq!(search(b"absent\0", contents, false), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn bad_input_is_reported() {
+        assert_eq!(
+            search(b"\xff\0", b"text\0", false),
+            Err(LILGREP_INVALID_UTF8)
+        );
+        assert_eq!(
+            search(b"text\0", b"\xc3\0", false),
+            Err(LILGREP_INVALID_UTF8)
+        );
+
+        let mut matches = ptr::null_mut();
+        let mut len = 0;
+        let status =
+            unsafe { lilgrep_search(ptr::null(), c"text".as_ptr(), false, &mut matches, &mut len) };
+        assert_eq!(status, LILGREP_NULL_POINTER);
+        let status = unsafe {
+            lilgrep_search(
+                c"t".as_ptr(),
+                c"text".as_ptr(),
+                false,
+                ptr::null_mut(),
+                &mut len,
+            )
+        };
+        assert_eq!(status, LILGREP_NULL_POINTER);
+
+        unsafe { lilgrep_matches_free(ptr::null_mut(), 0) };
+    }
+}
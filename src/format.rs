@@ -0,0 +1,321 @@
+//! Writing matching lines in the configured output format.
+
+use std::{fmt::Write, ops::Range};
+
+/// How matching lines are written.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The line itself, after a `path:` prefix when searching several files.
+    #[default]
+    Text,
+    /// Comma-separated values, quoted where needed.
+    Csv,
+    /// Tab-separated values, with tabs, newlines and backslashes escaped.
+    Tsv,
+    /// `path:line:column:text` for every match, as Vim's `grepformat`
+    /// expects, with 1-based byte columns. A line matching several times is
+    /// written once for each match.
+    Vimgrep,
+}
+
+/// The columns of the CSV and TSV formats.
+const COLUMNS: [&str; 5] = ["path", "line_number", "column", "match_count", "line"];
+
+/// One matching line, as handed to `OutputFormat::write`.
+pub(crate) struct Record<'a> {
+    /// The path the line came from, as displayed.
+    pub(crate) path: &'a str,
+    /// Whether the text format shows the path.
+    pub(crate) with_path: bool,
+    /// Whether the text format shows the line number.
+    pub(crate) numbered: bool,
+    /// The 1-based line number.
+    pub(crate) line_number: usize,
+    /// The byte ranges matched in the line.
+    pub(crate) ranges: &'a [Range<usize>],
+    /// The text to print for the line.
+    pub(crate) text: &'a str,
+    /// What ends each printed line: a newline, or NUL for `--null-data`.
+    pub(crate) terminator: char,
+}
+
+impl OutputFormat {
+    /// Parses the value of `--format`.
+    pub(crate) fn parse(value: &str) -> Result<OutputFormat, &'static str> {
+        match value {
+            "text" => Ok(OutputFormat::Text),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err("Format must be one of text, csv or tsv"),
+        }
+    }
+
+    /// Whether records need their line number and match ranges filled in.
+    pub(crate) fn is_structured(self) -> bool {
+        self != OutputFormat::Text
+    }
+
+    /// Returns the header row to print before any record, if the format has
+    /// one.
+    pub(crate) fn header(self) -> Option<String> {
+        let mut header = String::new();
+        match self {
+            OutputFormat::Text | OutputFormat::Vimgrep => return None,
+            OutputFormat::Csv => writeln!(header, "{}", COLUMNS.join(",")).unwrap(),
+            OutputFormat::Tsv => writeln!(header, "{}", COLUMNS.join("\t")).unwrap(),
+        }
+        Some(header)
+    }
+
+    /// Appends `record` to `out`.
+    pub(crate) fn write(self, out: &mut String, record: &Record) {
+        let (separator, escape): (char, fn(&str, &mut String)) = match self {
+            OutputFormat::Text => {
+                // A multiline match prints several lines, each with the prefix.
+                for (i, line) in record.text.split(record.terminator).enumerate() {
+                    if record.with_path {
+                        write!(out, "{}:", record.path).unwrap();
+                    }
+                    if record.numbered {
+                        write!(out, "{}:", record.line_number + i).unwrap();
+                    }
+                    write!(out, "{line}{}", record.terminator).unwrap();
+                }
+                return;
+            }
+            OutputFormat::Vimgrep => {
+                // Only the first line of a multiline match fits the format.
+                let text = record.text.split(record.terminator).next().unwrap_or("");
+                let starts = record.ranges.iter().map(|range| range.start);
+                // A line matching without any match to point at, as an
+                // inverted search finds, is written once, at its start.
+                let starts = starts.chain(record.ranges.is_empty().then_some(0));
+                for start in starts {
+                    write!(
+                        out,
+                        "{}:{}:{}:{text}{}",
+                        record.path,
+                        record.line_number,
+                        start + 1,
+                        record.terminator
+                    )
+                    .unwrap();
+                }
+                return;
+            }
+            OutputFormat::Csv => (',', csv_escape),
+            OutputFormat::Tsv => ('\t', tsv_escape),
+        };
+
+        let column = record
+            .ranges
+            .first()
+            .map(|range| (range.start + 1).to_string())
+            .unwrap_or_default();
+        let fields = [
+            record.path,
+            &record.line_number.to_string(),
+            &column,
+            &record.ranges.len().to_string(),
+            record.text,
+        ];
+
+        for (i, field) in fields.into_iter().enumerate() {
+            if i > 0 {
+                out.push(separator);
+            }
+            escape(field, out);
+        }
+        out.push(record.terminator);
+    }
+}
+
+/// Expands each tab in `line` to the spaces reaching the next multiple of
+/// `width` characters, moving `ranges` along so they still cover the same
+/// text.
+pub(crate) fn expand_tabs(
+    line: &str,
+    width: usize,
+    ranges: &[Range<usize>],
+) -> (String, Vec<Range<usize>>) {
+    let mut expanded = String::with_capacity(line.len());
+    // Where each byte of `line`, and its end, ends up in `expanded`.
+    let mut moved = Vec::with_capacity(line.len() + 1);
+    let mut column = 0;
+
+    for c in line.chars() {
+        moved.extend(std::iter::repeat_n(expanded.len(), c.len_utf8()));
+        if c == '\t' {
+            let spaces = width - column % width;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+    moved.push(expanded.len());
+
+    let ranges = ranges
+        .iter()
+        .map(|range| moved[range.start]..moved[range.end])
+        .collect();
+    (expanded, ranges)
+}
+
+/// Returns the byte offset in `line` after its first `max_columns`
+/// characters along with how many characters follow it, or `None` if the
+/// line has no more than that.
+///
+/// Columns are counted in `char`s, so a cut never splits one, though a wide
+/// or combining character still takes up the one column.
+pub(crate) fn column_cut(line: &str, max_columns: usize) -> Option<(usize, usize)> {
+    let (cut, _) = line.char_indices().nth(max_columns)?;
+    Some((cut, line[cut..].chars().count()))
+}
+
+/// Returns the 1-based `field` of `line`, split on every `delimiter`, along
+/// with where in `line` it starts, or `None` if the line has fewer fields.
+pub(crate) fn nth_field(line: &str, field: usize, delimiter: char) -> Option<(usize, &str)> {
+    let part = line.split(delimiter).nth(field - 1)?;
+    Some((part.as_ptr() as usize - line.as_ptr() as usize, part))
+}
+
+/// Returns where `replacement` ended up in `replaced`, the line whose `ranges`
+/// were each replaced by it, so highlights and columns refer to the text that
+/// is printed.
+///
+/// No ranges are returned if `replaced` isn't what replacing them would give,
+/// as from a `Matcher` replacing in some other way.
+pub(crate) fn replaced_ranges(
+    ranges: &[Range<usize>],
+    replacement: &str,
+    replaced: &str,
+) -> Vec<Range<usize>> {
+    let mut moved = Vec::with_capacity(ranges.len());
+    let mut shift = 0isize;
+    for range in ranges {
+        let start = range.start.checked_add_signed(shift);
+        let Some(new) = start.map(|start| start..start + replacement.len()) else {
+            return Vec::new();
+        };
+        if replaced.get(new.clone()) != Some(replacement) {
+            return Vec::new();
+        }
+        shift += replacement.len() as isize - range.len() as isize;
+        moved.push(new);
+    }
+    moved
+}
+
+/// Moves `ranges` back by the `cut` bytes taken off the start of their line,
+/// leaving out whatever was within them.
+pub(crate) fn shift_ranges(ranges: Vec<Range<usize>>, cut: usize) -> Vec<Range<usize>> {
+    ranges
+        .into_iter()
+        .filter(|range| range.end > cut)
+        .map(|range| range.start.saturating_sub(cut)..range.end - cut)
+        .collect()
+}
+
+/// Quotes `field` if it contains a comma, quote or line break, doubling any
+/// quotes, as RFC 4180 describes.
+fn csv_escape(field: &str, out: &mut String) {
+    if field.contains([',', '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
+
+/// Escapes tabs, line breaks and backslashes in `field` with backslashes.
+fn tsv_escape(field: &str, out: &mut String) {
+    for c in field.chars() {
+        match c {
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\\' => out.push_str("\\\\"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(format: OutputFormat, text: &str) -> String {
+        let mut out = String::new();
+        let ranges = [4..6, 10..12];
+        format.write(
+            &mut out,
+            &Record {
+                path: "dir/a, b.txt",
+                with_path: true,
+                numbered: false,
+                line_number: 7,
+                ranges: &ranges,
+                text,
+                terminator: '\n',
+            },
+        );
+        out
+    }
+
+    #[test]
+    fn fields_are_escaped() {
+        let line = "say \"hi\",\tthen\\leave";
+        assert_eq!(
+            row(OutputFormat::Csv, line),
+            "\"dir/a, b.txt\",7,5,2,\"say \"\"hi\"\",\tthen\\leave\"\n"
+        );
+        assert_eq!(
+            row(OutputFormat::Tsv, line),
+            "dir/a, b.txt\t7\t5\t2\tsay \"hi\",\\tthen\\\\leave\n"
+        );
+        assert_eq!(
+            row(OutputFormat::Csv, "plain"),
+            "\"dir/a, b.txt\",7,5,2,plain\n"
+        );
+        assert_eq!(
+            row(OutputFormat::Text, line),
+            format!("dir/a, b.txt:{line}\n")
+        );
+    }
+
+    #[test]
+    fn headers() {
+        assert_eq!(
+            OutputFormat::Csv.header().unwrap(),
+            "path,line_number,column,match_count,line\n"
+        );
+        assert_eq!(
+            OutputFormat::Tsv.header().unwrap(),
+            "path\tline_number\tcolumn\tmatch_count\tline\n"
+        );
+        assert_eq!(OutputFormat::Text.header(), None);
+    }
+
+    #[test]
+    fn tabs_expand_to_the_next_stop() {
+        let (expanded, ranges) = expand_tabs("a\tbc\t\tneedle", 4, &[0..1, 6..12]);
+        assert_eq!(expanded, "a   bc      needle");
+        assert_eq!(&expanded[ranges[1].clone()], "needle");
+
+        let (expanded, ranges) = expand_tabs("\té\tx", 2, &[0..1, 4..5]);
+        assert_eq!(expanded, "  é x");
+        assert_eq!(ranges, [0..2, 5..6]);
+    }
+
+    #[test]
+    fn vimgrep_writes_every_match() {
+        assert_eq!(
+            row(OutputFormat::Vimgrep, "an xyxy line, xy"),
+            "dir/a, b.txt:7:5:an xyxy line, xy\ndir/a, b.txt:7:11:an xyxy line, xy\n"
+        );
+        assert_eq!(OutputFormat::Vimgrep.header(), None);
+    }
+}
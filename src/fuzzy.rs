@@ -0,0 +1,247 @@
+//! Approximate matching within a bounded edit distance.
+
+use std::{collections::HashMap, ops::Range};
+
+use crate::CaseMode;
+
+/// The longest query `Fuzzy` can look for, one bit of a `u64` per character.
+pub(crate) const MAX_LEN: usize = 64;
+
+/// A query that matches any substring within `max_edits` insertions,
+/// deletions or substitutions of it.
+///
+/// Lines are scanned with the bit-parallel algorithm of Wu and Manber, which
+/// keeps one bit per query character for each number of edits allowed, so a
+/// line costs one pass of `max_edits + 1` word operations per character.
+/// Characters are compared one by one, case-folded to their lowercase form for
+/// `CaseMode::Insensitive`.
+#[derive(Debug, Clone)]
+pub(crate) struct Fuzzy {
+    query: Vec<char>,
+    /// The bits of the query positions holding each character.
+    masks: HashMap<char, u64>,
+    max_edits: usize,
+    case: CaseMode,
+}
+
+impl Fuzzy {
+    /// Prepares `query`, or returns `None` if it is longer than `MAX_LEN`
+    /// characters.
+    pub(crate) fn new(query: &str, case: CaseMode, max_edits: usize) -> Option<Fuzzy> {
+        let query: Vec<char> = query.chars().map(|c| fold(c, case)).collect();
+        if query.len() > MAX_LEN {
+            return None;
+        }
+
+        let mut masks = HashMap::new();
+        for (i, &c) in query.iter().enumerate() {
+            *masks.entry(c).or_insert(0) |= 1 << i;
+        }
+
+        Some(Fuzzy {
+            query,
+            masks,
+            max_edits,
+            case,
+        })
+    }
+
+    /// The number of characters in the query.
+    pub(crate) fn len(&self) -> usize {
+        self.query.len()
+    }
+
+    /// Checks whether some substring of `line` is close enough to the query.
+    pub(crate) fn is_match(&self, line: &str) -> bool {
+        if self.query.len() <= self.max_edits {
+            // Deleting every character of the query reaches the empty string.
+            return true;
+        }
+
+        let mut scan = Scan::new(self);
+        line.chars()
+            .any(|c| scan.step(self, fold(c, self.case)).is_some())
+    }
+
+    /// Returns the byte ranges of the approximate matches in `line`, leftmost
+    /// first and never overlapping.
+    ///
+    /// A match ends where the scan first finds the query within reach, or
+    /// further on while each following character keeps it at least as close. It starts
+    /// wherever makes the substring closest to the query, preferring the longer
+    /// substring on a tie. A query that even the empty string is close enough
+    /// to matches every line, but has no ranges.
+    pub(crate) fn find_ranges(&self, line: &str) -> Vec<Range<usize>> {
+        if self.query.len() <= self.max_edits {
+            return Vec::new();
+        }
+
+        let chars: Vec<(usize, char)> = line
+            .char_indices()
+            .map(|(i, c)| (i, fold(c, self.case)))
+            .collect();
+        let byte_at = |i: usize| chars.get(i).map_or(line.len(), |&(at, _)| at);
+
+        let mut ranges = Vec::new();
+        let mut from = 0;
+        let mut scan = Scan::new(self);
+        let mut i = 0;
+
+        while i < chars.len() {
+            let Some(mut edits) = scan.step(self, chars[i].1) else {
+                i += 1;
+                continue;
+            };
+
+            let mut end = i;
+            while let Some(&(_, next)) = chars.get(end + 1) {
+                match scan.step(self, next) {
+                    Some(closer) if closer <= edits => {
+                        edits = closer;
+                        end += 1;
+                    }
+                    _ => break,
+                }
+            }
+
+            let start = from + self.best_start(&chars[from..=end]);
+            ranges.push(byte_at(start)..byte_at(end + 1));
+            from = end + 1;
+            i = from;
+            scan = Scan::new(self);
+        }
+
+        ranges
+    }
+
+    /// Returns where the substring of `window` ending at its last character
+    /// that is closest to the query starts.
+    fn best_start(&self, window: &[(usize, char)]) -> usize {
+        let longest = (self.query.len() + self.max_edits).min(window.len());
+        let mut best = (usize::MAX, window.len());
+
+        for start in window.len() - longest..window.len() {
+            let distance = edit_distance(&self.query, &window[start..]);
+            if distance < best.0 {
+                best = (distance, start);
+            }
+        }
+
+        best.1
+    }
+}
+
+/// The state of a scan over a line, one bit per query character for each
+/// number of edits.
+struct Scan {
+    /// `states[d]` has bit `i` set when the first `i + 1` query characters
+    /// match, with at most `d` edits, a substring ending at the last character.
+    states: Vec<u64>,
+}
+
+impl Scan {
+    fn new(fuzzy: &Fuzzy) -> Scan {
+        Scan {
+            // With `d` edits, up to `d` query characters can be deleted
+            // before the line even starts.
+            states: (0..=fuzzy.max_edits).map(|d| (1 << d) - 1).collect(),
+        }
+    }
+
+    /// Advances past `c`, returning the fewest edits with which the whole
+    /// query matches a substring ending at `c`, if it is within the bound.
+    fn step(&mut self, fuzzy: &Fuzzy, c: char) -> Option<usize> {
+        let mask = fuzzy.masks.get(&c).copied().unwrap_or(0);
+
+        let mut previous_old = self.states[0];
+        self.states[0] = ((self.states[0] << 1) | 1) & mask;
+        for d in 1..self.states.len() {
+            let old = self.states[d];
+            self.states[d] = (((old << 1) | 1) & mask) // c matches
+                | ((previous_old << 1) | 1) // c replaces a query character
+                | previous_old // c is inserted
+                | ((self.states[d - 1] << 1) | 1); // a query character is deleted
+            previous_old = old;
+        }
+
+        let found = 1 << (fuzzy.query.len() - 1);
+        self.states.iter().position(|state| state & found != 0)
+    }
+}
+
+/// Returns the Levenshtein distance between `query` and the characters of
+/// `text`.
+fn edit_distance(query: &[char], text: &[(usize, char)]) -> usize {
+    let mut row: Vec<usize> = (0..=query.len()).collect();
+
+    for &(_, c) in text {
+        let mut diagonal = row[0];
+        row[0] += 1;
+        for (j, &q) in query.iter().enumerate() {
+            let substituted = diagonal + usize::from(q != c);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[query.len()]
+}
+
+fn fold(c: char, case: CaseMode) -> char {
+    match case {
+        CaseMode::Sensitive => c,
+        CaseMode::Insensitive => c.to_lowercase().next().unwrap_or(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn ranges<'a>(query: &str, max_edits: usize, line: &'a str) -> Vec<&'a str> {
+        Fuzzy::new(query, CaseMode::Sensitive, max_edits)
+            .unwrap()
+            .find_ranges(line)
+            .into_iter()
+            .map(|range| &line[range])
+            .collect()
+    }
+
+    #[test]
+    fn edits_within_the_bound_match() {
+        // Substitution, insertion and deletion.
+        assert_eq!(ranges("timeout", 1, "a timeuot here"), Vec::<&str>::new());
+        assert_eq!(ranges("timeout", 2, "a timeuot here"), ["timeuot"]);
+        assert_eq!(ranges("timeout", 1, "connection timeoutt"), ["timeout"]);
+        assert_eq!(ranges("timeout", 1, "connection timout"), ["timout"]);
+        assert_eq!(ranges("timeout", 1, "connection timeozt"), ["timeozt"]);
+        assert_eq!(ranges("retry", 1, "retries, retry"), ["retri", "retry"]);
+        assert_eq!(ranges("timeout", 1, "time out"), ["time out"]);
+        assert!(ranges("timeout", 1, "nothing here").is_empty());
+    }
+
+    #[test]
+    fn zero_edits_is_an_exact_search() {
+        assert_eq!(ranges("ab", 0, "ab abab a b"), ["ab", "ab", "ab"]);
+        assert!(ranges("ab", 0, "a b").is_empty());
+
+        let line = "naïve ÉCOLE";
+        let fuzzy = Fuzzy::new("école", CaseMode::Insensitive, 0).unwrap();
+        assert_eq!(fuzzy.find_ranges(line), vec![7..13]);
+        assert!(fuzzy.is_match(line));
+    }
+
+    #[test]
+    fn large_input_stays_fast() {
+        let line = "the quick brown fox jumps over the lazy dog ".repeat(20);
+        let contents = format!("{line}\n").repeat(2_000);
+        let fuzzy = Fuzzy::new("lazzy dgo", CaseMode::Insensitive, 3).unwrap();
+
+        let started = Instant::now();
+        let matching = contents.lines().filter(|line| fuzzy.is_match(line)).count();
+        assert_eq!(matching, 2_000);
+        assert!(started.elapsed().as_secs() < 10);
+    }
+}
@@ -0,0 +1,380 @@
+//! Decompressing gzip files, for `--search-compressed`.
+//!
+//! This is a small inflate (RFC 1951) inside a gzip container (RFC 1952),
+//! written here so the crate keeps having no dependencies. It decodes the whole
+//! input at once and checks the trailing CRC-32 and length, which is all a
+//! searcher needs; it makes no attempt to be fast.
+
+use std::io;
+
+/// The first two bytes of every gzip file.
+pub(crate) const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Decompresses a gzip file, including files of several concatenated members.
+///
+/// # Errors
+///
+/// Returns an `InvalidData` error if `data` isn't valid gzip data.
+pub(crate) fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut rest = data;
+
+    loop {
+        rest = member(rest, &mut output).map_err(invalid)?;
+        if rest.is_empty() {
+            return Ok(output);
+        }
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid gzip data: {message}"),
+    )
+}
+
+/// Decompresses the gzip member at the start of `data` onto `output`,
+/// returning what follows it.
+fn member<'a>(data: &'a [u8], output: &mut Vec<u8>) -> Result<&'a [u8], &'static str> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    if data.len() < 18 || data[..2] != MAGIC {
+        return Err("not a gzip file");
+    }
+    if data[2] != 8 {
+        return Err("unknown compression method");
+    }
+    let flags = data[3];
+    let mut pos = 10;
+
+    if flags & FEXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or("truncated header")?;
+        pos += 2 + usize::from(u16::from_le_bytes([len[0], len[1]]));
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or("truncated header")?;
+            pos += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    let body = data.get(pos..).ok_or("truncated header")?;
+
+    let start = output.len();
+    let used = inflate(body, output, usize::MAX)?;
+    let trailer = body.get(used..used + 8).ok_or("truncated trailer")?;
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let len = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+
+    if crc32(&output[start..]) != crc {
+        return Err("checksum mismatch");
+    }
+    if (output.len() - start) as u32 != len {
+        return Err("length mismatch");
+    }
+
+    Ok(&body[used + 8..])
+}
+
+/// Reads a deflate stream bit by bit, least significant bit first.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> Result<u32, &'static str> {
+        let byte = *self.data.get(self.pos).ok_or("unexpected end of data")?;
+        let bit = u32::from(byte >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(bit)
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, &'static str> {
+        let mut value = 0;
+        for i in 0..count {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+
+    /// Skips to the next byte boundary.
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// A canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    /// How many codes there are of each length.
+    counts: [u16; 16],
+    /// The symbols, ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, &'static str> {
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+
+        for len in 1..16 {
+            code |= bits.bit()? as i32;
+            let count = i32::from(self.counts[len]);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err("invalid Huffman code")
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Inflates the deflate stream at the start of `data` onto `output`, returning
+/// how many bytes of `data` it took up.
+///
+/// Fails as soon as `output` holds more than `limit` bytes.
+pub(crate) fn inflate(
+    data: &[u8],
+    output: &mut Vec<u8>,
+    limit: usize,
+) -> Result<usize, &'static str> {
+    let mut bits = Bits {
+        data,
+        pos: 0,
+        bit: 0,
+    };
+
+    loop {
+        let last = bits.bit()? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = data.get(bits.pos..bits.pos + 4).ok_or("truncated block")?;
+                let len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len as u16 != !nlen {
+                    return Err("corrupt stored block");
+                }
+                let start = bits.pos + 4;
+                output.extend_from_slice(data.get(start..start + len).ok_or("truncated block")?);
+                bits.pos = start + len;
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                block(&mut bits, &literals, &distances, output, limit)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                block(&mut bits, &literals, &distances, output, limit)?;
+            }
+            _ => return Err("invalid block type"),
+        }
+        if output.len() > limit {
+            return Err(TOO_LONG);
+        }
+
+        if last {
+            bits.align();
+            return Ok(bits.pos);
+        }
+    }
+}
+
+/// Reads the code lengths of a dynamic block and builds its two codes.
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), &'static str> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let length_count = bits.bits(4)? as usize + 4;
+
+    let mut length_lengths = [0; 19];
+    for &i in &ORDER[..length_count] {
+        length_lengths[i] = bits.bits(3)? as u8;
+    }
+    let length_code = Huffman::new(&length_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match length_code.decode(bits)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => (
+                *lengths.last().ok_or("repeat with no length")?,
+                3 + bits.bits(2)?,
+            ),
+            17 => (0, 3 + bits.bits(3)?),
+            18 => (0, 11 + bits.bits(7)?),
+            _ => return Err("invalid code length"),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() != literal_count + distance_count {
+        return Err("too many code lengths");
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+/// What `inflate` fails with when the output outgrows its limit.
+const TOO_LONG: &str = "more data than expected";
+
+/// Decodes one compressed block onto `output`, up to `limit` bytes of it.
+fn block(
+    bits: &mut Bits,
+    literals: &Huffman,
+    distances: &Huffman,
+    output: &mut Vec<u8>,
+    limit: usize,
+) -> Result<(), &'static str> {
+    loop {
+        if output.len() > limit {
+            return Err(TOO_LONG);
+        }
+        let symbol = usize::from(literals.decode(bits)?);
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let i = symbol - 257;
+                let len = usize::from(LENGTH_BASE[i]) + bits.bits(LENGTH_EXTRA[i].into())? as usize;
+                let i = usize::from(distances.decode(bits)?);
+                if i >= DISTANCE_BASE.len() {
+                    return Err("invalid distance code");
+                }
+                let distance =
+                    usize::from(DISTANCE_BASE[i]) + bits.bits(DISTANCE_EXTRA[i].into())? as usize;
+                if distance > output.len() {
+                    return Err("distance too far back");
+                }
+                let from = output.len() - distance;
+                for k in 0..len {
+                    output.push(output[from + k]);
+                }
+            }
+            _ => return Err("invalid literal/length code"),
+        }
+    }
+}
+
+/// The CRC-32 gzip stores for its contents.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POEM: &str = include_str!("../poem.txt");
+
+    #[test]
+    fn decompresses_dynamic_codes() {
+        // `gzip -9 poem.txt`, which also stores the file name.
+        let data = include_bytes!("../tests/fixtures/poem.txt.gz");
+        assert_eq!(decompress(data).unwrap(), POEM.as_bytes());
+    }
+
+    #[test]
+    fn decompresses_stored_and_fixed_members() {
+        // A stored block in one member followed by fixed codes in another.
+        let data = include_bytes!("../tests/fixtures/poem-members.txt.gz");
+        assert_eq!(decompress(data).unwrap(), POEM.as_bytes());
+    }
+
+    #[test]
+    fn crc32_matches_the_reference() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        assert!(decompress(b"plain text, not gzip").is_err());
+        let data = include_bytes!("../tests/fixtures/poem.txt.gz");
+        assert!(decompress(&data[..data.len() - 1]).is_err());
+
+        let mut corrupt = data.to_vec();
+        corrupt[40] ^= 0x10;
+        assert!(decompress(&corrupt).is_err());
+    }
+}
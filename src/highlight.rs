@@ -0,0 +1,100 @@
+//! Decorating the matches in a line, for terminals or any other display.
+
+use std::ops::Range;
+
+use crate::find_all_ranges;
+
+/// What to put around each match when highlighting a line.
+///
+/// `Style::ansi` is what `--color` uses in a terminal; any other pair of
+/// strings, such as an HTML `<mark>` and `</mark>`, works the same way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Style {
+    /// Written just before each match.
+    pub before: String,
+    /// Written just after each match.
+    pub after: String,
+}
+
+impl Style {
+    /// A style putting `before` and `after` around each match.
+    pub fn new(before: &str, after: &str) -> Style {
+        Style {
+            before: before.to_owned(),
+            after: after.to_owned(),
+        }
+    }
+
+    /// Bold red, reset after each match, as ANSI escape codes.
+    pub fn ansi() -> Style {
+        Style::new("\x1b[1;31m", "\x1b[0m")
+    }
+
+    /// Returns `line` with every one of `ranges`, which must be in order and
+    /// not overlap, wrapped in the style.
+    pub fn apply(&self, line: &str, ranges: &[Range<usize>]) -> String {
+        let extra = ranges.len() * (self.before.len() + self.after.len());
+        let mut decorated = String::with_capacity(line.len() + extra);
+        let mut last = 0;
+
+        for range in ranges {
+            decorated.push_str(&line[last..range.start]);
+            decorated.push_str(&self.before);
+            decorated.push_str(&line[range.clone()]);
+            decorated.push_str(&self.after);
+            last = range.end;
+        }
+        decorated.push_str(&line[last..]);
+
+        decorated
+    }
+}
+
+/// Returns `line` with every occurrence of `query`, as `find_all_ranges`
+/// finds them, wrapped in `style`.
+///
+/// Nothing is printed, so this works as well for a TUI or a web page as for
+/// a terminal.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::{Style, highlight_line};
+///
+/// let style = Style::new("<mark>", "</mark>");
+/// assert_eq!(
+///     highlight_line("Error: disk error", "error", true, &style),
+///     "<mark>Error</mark>: disk <mark>error</mark>"
+/// );
+/// ```
+pub fn highlight_line(line: &str, query: &str, ignore_case: bool, style: &Style) -> String {
+    style.apply(line, &find_all_ranges(query, line, ignore_case))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_and_multiple_matches() {
+        let style = Style::new("[", "]");
+        assert_eq!(
+            highlight_line("a needle here", "needle", false, &style),
+            "a [needle] here"
+        );
+        assert_eq!(highlight_line("aaaaa", "aa", false, &style), "[aa][aa]a");
+        assert_eq!(
+            highlight_line("Straße, STRASSE", "strasse", true, &style),
+            "Straße, [STRASSE]"
+        );
+        assert_eq!(
+            highlight_line("nothing", "needle", false, &style),
+            "nothing"
+        );
+
+        assert_eq!(
+            highlight_line("x y x", "x", false, &Style::ansi()),
+            "\x1b[1;31mx\x1b[0m y \x1b[1;31mx\x1b[0m"
+        );
+    }
+}
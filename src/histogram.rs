@@ -0,0 +1,62 @@
+//! Where in a file its matches fell, for `--histogram`.
+
+/// How many equal parts of a file matches are counted in.
+const BUCKETS: usize = 10;
+
+/// Bars of rising height, for one to eight eighths of the largest bucket.
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// The number of matching lines in each tenth of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Histogram {
+    buckets: [usize; BUCKETS],
+}
+
+impl Histogram {
+    /// Counts the 1-based `line_numbers` of a file with `total_lines` lines
+    /// into the tenth of the file each falls in.
+    pub(crate) fn new(
+        line_numbers: impl IntoIterator<Item = usize>,
+        total_lines: usize,
+    ) -> Histogram {
+        let mut buckets = [0; BUCKETS];
+        for number in line_numbers {
+            let bucket = number.saturating_sub(1) * BUCKETS / total_lines.max(1);
+            buckets[bucket.min(BUCKETS - 1)] += 1;
+        }
+        Histogram { buckets }
+    }
+
+    /// Draws one character per bucket, scaled so the largest is a full block;
+    /// an empty bucket is a space.
+    pub(crate) fn sparkline(&self) -> String {
+        let max = self.buckets.iter().copied().max().unwrap_or(0);
+        self.buckets
+            .iter()
+            .map(|&count| match count {
+                0 => ' ',
+                _ => BARS[(count * BARS.len()).div_ceil(max) - 1],
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_are_bucketed_by_position() {
+        // 100 lines: three matches in the first tenth, one in the fifth and
+        // eight in the last.
+        let lines = [1, 5, 10, 45].into_iter().chain(93..=100);
+        let histogram = Histogram::new(lines, 100);
+        assert_eq!(histogram.buckets, [3, 0, 0, 0, 1, 0, 0, 0, 0, 8]);
+        assert_eq!(histogram.sparkline(), "▃   ▁    █");
+
+        // With fewer lines than buckets, some buckets can never be filled.
+        let short = Histogram::new([1, 3], 3);
+        assert_eq!(short.buckets, [1, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(short.sparkline(), "█     █   ");
+    }
+}
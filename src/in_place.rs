@@ -0,0 +1,89 @@
+//! Writing replacements back to the files searched, for `--in-place`.
+
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{Config, DiffHeaders, Matcher, SearchError};
+
+/// Replaces every match in the matching lines of `path` with `replacement`,
+/// returning how many lines matched and how many matches were replaced.
+///
+/// Every other byte of the file, line endings included, is left as it is. The
+/// new contents are written to a temporary file next to `path`, which is then
+/// renamed over it, so a crash leaves either the old file or the new one. With
+/// `backup`, the original is first copied to `path` with `.bak` appended. A
+/// file with nothing to replace, such as one whose lines only match because
+/// of `invert`, or one the replacements leave as it was, isn't touched at all,
+/// and no replacements are counted for it.
+pub(crate) fn replace(
+    matcher: &dyn Matcher,
+    config: &Config,
+    path: &Path,
+    replacement: &str,
+) -> io::Result<(usize, usize)> {
+    let contents = fs::read_to_string(path).map_err(|err| SearchError::io("read", path, err))?;
+    let range = config.line_range.clone().unwrap_or(1..=usize::MAX);
+
+    let mut replaced = String::with_capacity(contents.len());
+    let mut matched = 0;
+    let mut replacements = 0;
+    let mut diff_headers = config.diff.map(|_| DiffHeaders::default());
+
+    for (number, line) in (1..).zip(contents.split_inclusive('\n')) {
+        let text = line.strip_suffix('\n').unwrap_or(line);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let header = diff_headers
+            .as_mut()
+            .is_some_and(|headers| headers.is_header(text));
+        if range.contains(&number) && !header && matcher.is_match(text) {
+            replaced.push_str(&matcher.replace(text, replacement));
+            replaced.push_str(&line[text.len()..]);
+            matched += 1;
+            replacements += matcher.find_all(text).len();
+        } else {
+            replaced.push_str(line);
+        }
+    }
+
+    if replacements == 0 || replaced == contents {
+        return Ok((matched, 0));
+    }
+    if config.backup {
+        let backup = with_suffix(path, ".bak");
+        fs::copy(path, &backup).map_err(|err| SearchError::io("back up to", &backup, err))?;
+    }
+
+    let temp = with_suffix(path, &format!(".lilgrep-{}.tmp", process::id()));
+    let written = write_like(&temp, path, replaced.as_bytes())
+        .map_err(|err| SearchError::io("write", &temp, err))
+        .and_then(|()| {
+            fs::rename(&temp, path).map_err(|err| SearchError::io("replace", path, err))
+        });
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written?;
+
+    Ok((matched, replacements))
+}
+
+/// Writes `contents` to a new file at `temp`, with the permissions of
+/// `original`, and makes sure it reaches the disk.
+fn write_like(temp: &Path, original: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = fs::File::create_new(temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::set_permissions(temp, fs::metadata(original)?.permissions())
+}
+
+/// Returns `path` with `suffix` added to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
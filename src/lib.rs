@@ -2,173 +2,5705 @@
 //!
 //! `lilgrep` is a collection of basic utilities for searching text within files.
 
-use std::{env, error::Error, fs};
+mod args;
+mod config_file;
+mod environment;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod format;
+mod fuzzy;
+mod gzip;
+mod highlight;
+mod histogram;
+mod in_place;
+mod matcher;
+mod normalize;
+mod owned;
+mod phonetic;
+mod pre;
+mod progress;
+mod search;
+mod walk;
+mod watch;
+#[cfg(feature = "zip")]
+mod zip;
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    ffi::{OsStr, OsString},
+    fmt::{self, Write as _},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write},
+    num::NonZeroUsize,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use args::{Arg, Parser};
+pub use config_file::{FileDefaults, config_file_path};
+pub use environment::{EnvProvider, ProcessEnv};
+pub use error::SearchError;
+pub use format::OutputFormat;
+use format::Record;
+pub use highlight::{Style, highlight_line};
+use histogram::Histogram;
+pub use matcher::Matcher;
+use matcher::Patterns;
+pub use normalize::NormalForm;
+pub use owned::{SearchOptions, search_file};
+use progress::Progress;
+use search::slice_records;
+pub use search::{
+    CaseMode, Match, OwnedMatch, Searcher, find_all_ranges, find_overlapping_ranges,
+    match_positions, search, search_all, search_any, search_bytes, search_callback,
+    search_case_insensitive, search_grouped, search_iter, search_lines, search_soundex,
+    search_whole_line, search_with_matcher, search_with_spans, slice_lines, sort_lines,
+};
+
+/// The text printed for `--help`.
+pub const USAGE: &str = "\
+Usage: lilgrep [OPTIONS] QUERY [PATH]...
+       lilgrep [OPTIONS] -e PATTERN... [PATH]...
+       lilgrep [OPTIONS] --query QUERY --path PATH...
+
+Prints the lines of each PATH that contain QUERY. A PATH of - reads standard
+input. Arguments after -- are never taken as options.
+
+Matching:
+      --query QUERY       Search for QUERY; give each PATH with --path
+      --path PATH         Search PATH; repeat to give several
+      --files-from FILE   Also search the paths listed in FILE, one per line,
+                          or NUL-separated with --null-data; - reads the
+                          list from standard input
+  -i, --ignore-case       Compare case-insensitively
+  -w, --word              Only match whole words
+  -x, --line-regexp       Only match lines that are the query as a whole
+  -e, --pattern PATTERN   Search for PATTERN; repeat to give several, any of
+                          which may match. Every PATH is then a file
+      --any LIST          Search for each of the comma-separated terms of
+                          LIST, as if each were given with -e
+      --all-of, --all     A line must contain every -e pattern, in any order
+      --not PATTERN       Leave out lines containing PATTERN; repeat to give
+                          several
+      --fuzzy N           Also match text within N inserted, deleted or
+                          replaced characters of the query
+      --normalize FORM    Put the query and each line in Unicode
+                          normalization form nfc or nfd before comparing
+                          them; only accented Latin letters are normalized
+      --soundex           Match words that sound like the query, by their
+                          Soundex code; for English names only
+  -v, --invert-match      Print the lines that don't match instead; with
+                          --all-of, the lines that lack at least one pattern
+  -U, --multiline         Let a match span lines, printing every line it
+                          covers; each file is then read into memory whole
+      --line-range S:E    Only search lines S to E; either may be left out
+      --field N           Only search the Nth field of each line
+      --delimiter C       Split fields on C instead of a comma; \t is a tab
+      --diff-added        Only search the lines a unified diff adds, without
+                          their leading +
+      --diff-removed      Only search the lines a unified diff removes,
+                          without their leading -
+      --name-only         Match file names instead of contents
+      --files             Print the files that would be searched, without
+                          searching them; no query is taken
+
+Output:
+  -c, --count             Print the number of matching lines per file
+      --count-total       Print the number of matching lines across all files
+      --no-zero           Leave files without matches out of --count
+      --count-matches     Print the number of occurrences per file, counting
+                          a line with two of them twice
+      --overlapping       Count occurrences starting inside earlier ones too,
+                          so aa occurs three times in aaaa rather than twice
+      --first-last        Print the line numbers of the first and last
+                          matching lines, and their count, per file
+      --include-empty     Include files without matches in --first-last
+      --invert-files      Print the paths of the files without matching
+                          lines instead; with -c, as path:0
+      --histogram         Show on stderr where in each file its matching
+                          lines are, as one bar per tenth of the file
+  -m, --max-count N       Stop reading a file after its Nth matching line
+      --max-total N       Stop searching, and walking directories, after N
+                          matching lines in all
+      --max-per-line N    Highlight, or give --vimgrep rows to, only the
+                          first N matches of each line
+      --replace TEXT      Print matching lines with each match replaced
+      --in-place, --write Write the --replace replacements back to the files
+                          instead of printing them
+      --backup            Keep a copy of each file changed by --in-place, with
+                          .bak added to its name
+      --format FORMAT     Print matching lines as text, csv or tsv, with the
+                          columns path, line_number, column, match_count and
+                          line
+      --vimgrep           Print path:line:column:line for every match, for
+                          Vim's grepprg; tabs are never expanded
+      --no-header         Leave out the csv or tsv header row
+      --with-header N     Print the first N lines of each file with matches
+                          before its matches, whether they match or not
+      --passthru          Print every line, treating only the matching ones
+                          as matches
+      --heading           Print each file's path once above its lines, which
+                          get line numbers instead; the default on a terminal
+      --no-heading        Print the path before every line
+      --tab-width N       Print tabs as spaces up to the next multiple of N
+                          columns; 0 leaves them as they are
+      --trim              Print lines without their leading spaces and tabs
+      --output-field N    Print only the Nth field of matching lines, split
+                          on --delimiter; empty if the line has fewer
+      --skip-missing      Leave out lines without that field instead
+      --max-columns N     Cut printed lines after N characters; a line whose
+                          matches all come later is left out entirely
+      --max-columns-preview
+                          Print the first N characters of such lines too
+      --color WHEN        Highlight matches: auto, always or never; auto
+                          highlights when writing to a terminal
+      --mark              Put [ and ] around matches in printed lines,
+                          with or without color
+      --mark-start TEXT   Put TEXT before matches instead of [; implies --mark
+      --mark-end TEXT     Put TEXT after matches instead of ]; implies --mark
+      --sort              Sort matching lines
+      --sort-reverse      Sort matching lines in reverse
+      --line-buffered     Flush output after every line
+      --block-buffered    Flush output only when the buffer fills
+
+Files:
+  -r, --recursive         Search directories recursively
+  -L, --follow            Follow symbolic links to directories
+      --relative          Print paths found below an absolute directory
+                          relative to it, and without a leading ./ (default)
+      --no-relative       Print paths as they were found
+      --label NAME        Print standard input's path as NAME instead of
+                          <stdin>
+      --max-depth N       Descend at most N directories
+      --max-filesize SIZE Skip files larger than SIZE bytes; a K, M or G
+                          suffix multiplies it by 1024 that many times
+      --skip-bytes SIZE   Start each input SIZE bytes in, or at the next
+                          line after that; line numbers count from there
+  -z, --search-compressed Decompress gzip files before searching them
+      --pre COMMAND       Search what COMMAND prints when run with each
+                          file's path, instead of the file
+      --pre-glob GLOB     Only run --pre on files whose names match GLOB,
+                          where * is any text and ? any one character
+      --mmap              Read files a line at a time, so memory use doesn't
+                          grow with file size
+      --null-data         Take input and output lines to end with NUL instead
+                          of a newline, as grep -z does
+      --sort-files KEY    Search files by path, modified, size or none
+  -j, --threads N         Search and walk directories on N threads; 0 picks
+                          a number
+      --watch             Search again whenever a file changes, or with -r
+                          is added or removed
+      --progress          Show how many files have been searched on stderr,
+                          when it is a terminal
+  -s, --no-messages       Don't warn about files that can't be read
+
+Configuration:
+      --no-config         Don't read the configuration file
+      --debug-config      Describe the resolved configuration on stderr
+  -h, --help              Print this help
+
+The exit status is 0 if a line matched or a path was printed, as with --files
+and --invert-files, or with --help, 1 if nothing was, and 2 on an error, such
+as a bad argument or a file that couldn't be read.
+";
 
 /// Configuration for the minigrep application.
-/// Holds the query string, file path, and case sensitivity flag.
+/// Holds the query string, file paths and the options controlling the search.
 /// Use `Config::build` to create a new instance.
+#[derive(Default)]
 pub struct Config {
+    /// What each line is searched for, unless `patterns` are given.
     pub query: String,
-    pub file_path: String,
+    /// Patterns given with `-e`, any of which may match a line.
+    pub patterns: Vec<String>,
+    /// Whether a line must match all of the `patterns` rather than any.
+    pub all_of: bool,
+    /// Patterns a line must not contain to match.
+    pub not_patterns: Vec<String>,
+    /// Whether the lines that don't match are printed instead.
+    pub invert: bool,
+    /// Whether patterns are matched against the whole of each file, so one
+    /// containing a newline can match across lines.
+    ///
+    /// Every line a match covers is printed, and counted, as one result that
+    /// starts at the line the match starts on. This needs the whole file in
+    /// memory, so it can't be combined with `mmap` or `max_count`.
+    pub multiline: bool,
+    /// How many inserted, deleted or replaced characters text may be from a
+    /// pattern and still match.
+    pub fuzzy: Option<usize>,
+    /// Whether a pattern instead matches any whitespace-separated word with
+    /// the same Soundex code, a rough guide to English pronunciation, whatever
+    /// its case.
+    pub soundex: bool,
+    /// The Unicode normalization form patterns and lines are compared in, so
+    /// a precomposed `é` matches `e` followed by a combining accent.
+    ///
+    /// Only the accented Latin letters are covered, as no Unicode tables are
+    /// bundled, and matches are highlighted in the line as it was.
+    pub normalize: Option<NormalForm>,
+    /// Whether `--help` was given.
+    pub help: bool,
+    /// The files and directories to search; `-` reads standard input, shown
+    /// as `<stdin>` wherever a path would be, or as `label`.
+    ///
+    /// Input that isn't valid UTF-8 is searched, and printed, with each
+    /// invalid sequence replaced by U+FFFD, so ASCII text in it is still
+    /// found, though a U+FFFD in the query only matches one that was in the
+    /// file. With the `zip` feature, a file whose name ends in `.zip` is
+    /// searched as a zip archive: each of its entries without a NUL byte is
+    /// searched in its place, and shown as `archive.zip:entry`.
+    pub file_paths: Vec<PathBuf>,
+    /// A file listing more paths, already added to `file_paths` by
+    /// `Config::build`.
+    pub files_from: Option<PathBuf>,
+    /// Whether case is ignored, as it is when one of `IGNORE_CASE_VARS` is
+    /// set.
     pub ignore_case: bool,
+    /// Whether matches must be whole words.
+    pub whole_word: bool,
+    /// Whether a line must be a pattern as a whole to match.
+    pub whole_line: bool,
+    /// How matching lines are sorted, instead of kept in file order.
+    pub sort: Option<SortOrder>,
+    /// The lines searched in each file, counting records with `null_data`.
+    pub line_range: Option<RangeInclusive<usize>>,
+    /// Whether the number of occurrences in each file is printed instead of
+    /// its lines.
+    ///
+    /// Occurrences are found leftmost first and can't overlap, so `aa` occurs
+    /// twice in `aaaa`, unless `overlapping` is set.
+    pub count_matches: bool,
+    /// Whether an occurrence counts at every position it starts at, so `aa`
+    /// occurs three times in `aaaa`.
+    pub overlapping: bool,
+    /// Whether only the number of matching lines is printed: one bare number
+    /// for a single file, otherwise `path:count` for every file.
+    pub count: bool,
+    /// Whether the number of matching lines across all files is printed, as
+    /// `total:count` after a per-file count.
+    pub count_total: bool,
+    /// Whether `count` leaves out files without matches.
+    pub no_zero: bool,
+    /// Whether each file with matches gets a single `path: first=X last=Y
+    /// count=Z` line giving the numbers of its first and last matching lines.
+    pub first_last: bool,
+    /// Whether every file with matches also gets a line on stderr with ten
+    /// bars, one for each tenth of the file, whose heights show how many of
+    /// its matching lines fell there, relative to the tallest.
+    pub histogram: bool,
+    /// Whether `first_last` gives files without matches a `path: first=-
+    /// last=- count=0` line.
+    pub include_empty: bool,
+    /// Whether only the paths of the files without matching lines are
+    /// printed, one per line, or as `path:0` with `count`; each file is still
+    /// searched in full.
+    pub invert_files: bool,
+    /// How many matching lines each file is read up to; only those are
+    /// printed or counted.
+    pub max_count: Option<usize>,
+    /// How many matches of a line are highlighted or marked, given `Vimgrep`
+    /// rows, or counted in the `match_count` column; the line is printed
+    /// whole.
+    pub max_per_line: Option<usize>,
+    /// How many matching lines are printed, or counted by `count_total`,
+    /// across all files.
+    ///
+    /// Once that many have been, files not yet being searched are skipped,
+    /// and a walk still finding them stops.
+    pub max_total: Option<usize>,
+    /// Whether files are read a line at a time, keeping only their matching
+    /// lines, so memory use doesn't grow with the size of the file; `max_count`
+    /// does the same.
+    pub mmap: bool,
+    /// Whether gzip files are decompressed, in memory, and searched.
+    pub search_compressed: bool,
+    /// A command each file is run through, given its path, with what it
+    /// prints searched instead.
+    ///
+    /// Its stderr is passed on, and if it fails, the file is warned about like
+    /// one that couldn't be read.
+    pub pre: Option<PathBuf>,
+    /// The pattern a file's name must match to go through `pre`.
+    pub pre_glob: Option<String>,
+    /// Whether the query is matched against the name of each file instead of
+    /// its contents, printing the paths of the files that match without
+    /// reading them.
+    pub name_only: bool,
+    /// Whether nothing is searched, and no query is needed: the files that
+    /// would be, as `files_to_search` gives them, are printed one per line,
+    /// or ending with NUL with `null_data`.
+    pub list_files: bool,
+    /// Whether the resolved configuration is described on stderr first.
+    pub debug_config: bool,
+    /// Where each option's value came from, by field name; options left out
+    /// were left at their defaults.
+    pub origins: BTreeMap<&'static str, Origin>,
+    /// Whether directories are searched along with everything below them.
+    pub recursive: bool,
+    /// Whether paths keep a leading `./`, and those found below an absolute
+    /// directory stay absolute.
+    pub full_paths: bool,
+    /// What standard input is shown as.
+    pub label: Option<String>,
+    /// Whether symbolic links met while walking are followed into
+    /// directories; linked files are always searched.
+    pub follow: bool,
+    /// How far below each directory the walk descends.
+    pub max_depth: Option<usize>,
+    /// The size in bytes above which files, by their metadata, are skipped
+    /// without being read, whether found by the walk or given directly.
+    pub max_filesize: Option<u64>,
+    /// The byte offset each input is searched from, or from the start of the
+    /// next line if it falls inside one; line numbers count from there.
+    ///
+    /// Files are seeked, and standard input and decompressed data are read
+    /// past.
+    pub skip_bytes: u64,
+    /// The order files are searched in, and their results printed; the same
+    /// whatever the number of threads, except with `FileSort::None`.
+    pub file_sort: FileSort,
+    /// How many threads search, and walk directories, or 0 for as many as
+    /// there are CPUs.
+    ///
+    /// With `FileSort::None`, each file is searched as soon as it is found
+    /// rather than once the walk is done.
+    pub threads: usize,
+    /// How stdout is buffered.
+    pub buffering: Option<Buffering>,
+    /// Whether the search is repeated whenever one of the searched files
+    /// changes or, with `recursive`, a file is added to or removed from a
+    /// searched directory; `run` then only returns on error.
+    pub watch: bool,
+    /// Whether the number of files searched so far, the matching lines found
+    /// in them, the time taken and the last path searched are shown on
+    /// stderr, when it is a terminal.
+    ///
+    /// The line is cleared whenever results are printed and when the search
+    /// ends.
+    pub progress: bool,
+    /// Whether files that can't be read are skipped without a warning, unless
+    /// it is the only path given, as there is nothing else to search.
+    pub no_messages: bool,
+    /// What every occurrence in a printed line is replaced by.
+    pub replace: Option<String>,
+    /// How each printed line is written, as plain text, as a CSV or TSV row,
+    /// or as one `path:line:column:line` row for every match for Vim's
+    /// `grepprg`, which is never highlighted, headed or tab-expanded.
+    pub format: OutputFormat,
+    /// Whether CSV and TSV output leave out the row of column names.
+    pub no_header: bool,
+    /// How many spaces tabs in printed lines are expanded to, unless 0;
+    /// matching is unaffected.
+    pub tab_width: usize,
+    /// Whether printed lines lose their leading spaces and tabs.
+    ///
+    /// Highlights and columns count from what is left, except in `Vimgrep`
+    /// rows, which keep the line as it is in the file.
+    pub trim: bool,
+    /// The 1-based field of each matching line printed in its place, split
+    /// on `delimiter` as for `field`.
+    ///
+    /// A line without it prints as empty, or not at all with `skip_missing`,
+    /// but still counts.
+    pub output_field: Option<usize>,
+    /// Whether lines without the `output_field` are left out.
+    pub skip_missing: bool,
+    /// How many characters a printed text line is cut short at, counted as
+    /// `char`s after tabs are expanded, with a note of how many were left out;
+    /// 0 for no limit.
+    ///
+    /// If the line's matches all lie past the cut, only a note of its length
+    /// is printed, unless `max_columns_preview` is set.
+    pub max_columns: usize,
+    /// Whether a line cut short by `max_columns` is still printed up to the
+    /// cut when its matches lie past it.
+    pub max_columns_preview: bool,
+    /// Whether matches in printed text lines are highlighted with
+    /// `Style::ansi`, unless `replace` changed them; left unset, stdout being
+    /// a terminal decides.
+    pub color: Option<bool>,
+    /// Text put before and after matches in printed text lines, in plain text
+    /// and outside any color.
+    pub mark: Option<Style>,
+    /// Whether records are separated by NUL instead of newlines, on input and
+    /// output alike, as with `grep -z`; a record may then contain newlines,
+    /// which are kept.
+    pub null_data: bool,
+    /// How many of the first lines of each file with matches are printed
+    /// ahead of them, matching or not, and never again as matches; they aren't
+    /// counted.
+    pub header_lines: usize,
+    /// Whether every line is printed as it is, with its own line ending, as
+    /// soon as it is read, one file at a time; only the matching lines are
+    /// highlighted, replaced and counted.
+    pub passthru: bool,
+    /// Whether, when more than one file is searched, each file's lines are
+    /// printed below its path, numbered, with a blank line between files,
+    /// rather than prefixed with it.
+    ///
+    /// Left unset, headings are used only when stdout is a terminal. They never
+    /// apply to counts or CSV and TSV output.
+    pub heading: Option<bool>,
+    /// The 1-based field of each line that is searched, split on every
+    /// `delimiter` with no regard for quotes, though the whole line is
+    /// printed; a line with fewer fields doesn't match.
+    pub field: Option<usize>,
+    /// The side of a unified diff whose lines are searched, without the `+`
+    /// or `-` they start with.
+    ///
+    /// The `+++` and `---` headers naming the files, found between hunks by
+    /// the line counts in their `@@` lines, aren't among them.
+    pub diff: Option<DiffSide>,
+    /// What separates fields, or a comma if unset.
+    pub delimiter: Option<char>,
+    /// Whether each file with `replace` matches is rewritten, through a
+    /// temporary file renamed over it, instead of anything being printed.
+    ///
+    /// How many replacements were made in how many files is reported on
+    /// stderr, and in the `Summary`. Files that aren't valid UTF-8 are
+    /// refused rather than rewritten lossily.
+    pub in_place: bool,
+    /// Whether `in_place` copies each file to a `.bak` file first.
+    pub backup: bool,
+}
+
+/// How results written to stdout are buffered.
+///
+/// When not set explicitly, stdout is line-buffered if it is a terminal and
+/// block-buffered otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Buffering {
+    /// Flush after every line, set by `--line-buffered`.
+    Line,
+    /// Flush only when a large buffer fills up, set by `--block-buffered`.
+    Block,
+}
+
+/// The error `Config::build` gives when there is no query to search for.
+///
+/// Only given when there is no argument to take as the query: a lone one is
+/// the query, so `lilgrep needle` fails for want of a file path instead. A
+/// caller on a terminal may then ask for the query and build again with
+/// `--query` added.
+pub const MISSING_QUERY: &str = "Didn't get a query string";
+
+/// The environment variables that turn on `ignore_case` when set to anything.
+pub const IGNORE_CASE_VARS: [&str; 2] = ["LILGREP_IGNORE_CASE", "IGNORE_CASE"];
+
+/// Where the final value of an option came from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// The option was left at its default.
+    #[default]
+    Default,
+    /// The option was set by the configuration file.
+    File,
+    /// The option was set by an environment variable.
+    Env,
+    /// The option was set on the command line.
+    Argv,
+}
+
+/// Which lines of a unified diff are searched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffSide {
+    /// Lines starting with `+`, set by `--diff-added`.
+    Added,
+    /// Lines starting with `-`, set by `--diff-removed`.
+    Removed,
+}
+
+impl DiffSide {
+    /// Returns what follows the `+` or `-` of `line` if it is on this side of
+    /// the diff, or `None` for other lines. The `+++` and `---` headers naming
+    /// the files start the same way; `DiffHeaders` tells them apart.
+    pub(crate) fn content(self, line: &str) -> Option<&str> {
+        let marker = match self {
+            DiffSide::Added => '+',
+            DiffSide::Removed => '-',
+        };
+        line.strip_prefix(marker)
+    }
+}
+
+/// Follows a unified diff a line at a time to tell the `+++` and `---`
+/// headers naming its files from added and removed lines that start the same
+/// way, such as an added `++count;`. Headers only come between hunks, and each
+/// hunk's `@@` line says how many lines it has.
+#[derive(Debug, Default)]
+pub(crate) struct DiffHeaders {
+    /// The old and new lines left in the current hunk.
+    left: (usize, usize),
+}
+
+impl DiffHeaders {
+    /// Moves past `line`, the next line of the diff without its ending,
+    /// returning whether it is a file header.
+    pub(crate) fn is_header(&mut self, line: &str) -> bool {
+        if self.left == (0, 0) {
+            if let Some(lengths) = hunk_lengths(line) {
+                self.left = lengths;
+                return false;
+            }
+            return line.starts_with("+++ ") || line.starts_with("--- ");
+        }
+        let (old, new) = &mut self.left;
+        match line.as_bytes().first() {
+            Some(b'+') => *new = new.saturating_sub(1),
+            Some(b'-') => *old = old.saturating_sub(1),
+            // `\ No newline at end of file` is about the line before.
+            Some(b'\\') => {}
+            _ => {
+                *old = old.saturating_sub(1);
+                *new = new.saturating_sub(1);
+            }
+        }
+        false
+    }
+}
+
+/// Returns how many old and new lines the hunk starting with `line` has, if
+/// it is an `@@ -start,length +start,length @@` line. A length left out is 1.
+fn hunk_lengths(line: &str) -> Option<(usize, usize)> {
+    let (ranges, _) = line.strip_prefix("@@ -")?.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let length = |range: &str| match range.split_once(',') {
+        Some((_, length)) => length.parse().ok(),
+        None => range.parse::<usize>().ok().map(|_| 1),
+    };
+    Some((length(old)?, length(new)?))
+}
+
+/// Order in which matching lines are printed when sorting is requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Lexicographic order, set by `--sort`.
+    Ascending,
+    /// Reverse lexicographic order, set by `--sort-reverse`.
+    Descending,
+}
+
+/// Order in which files are searched and their results printed.
+///
+/// Every order except `None` is deterministic, whatever the number of threads.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FileSort {
+    /// The paths given in the order given, with the files found in each
+    /// directory lexicographic by path.
+    #[default]
+    Path,
+    /// Oldest modification time first, then by path.
+    Modified,
+    /// Smallest first, then by path.
+    Size,
+    /// Whatever order searches finish in, for the fastest possible output.
+    None,
 }
 
 impl Config {
     /// Constructs a `Config` from command line arguments.
     ///
+    /// Defaults are read from the configuration file first (see
+    /// `config_file_path`) unless `--no-config` is given; the environment
+    /// variables in `IGNORE_CASE_VARS` and then the command line override
+    /// them.
+    ///
+    /// Flags can appear anywhere. Of the other arguments, the first is the
+    /// query and the rest are file paths, unless patterns were given with `-e`,
+    /// in which case they are all file paths. The query can also be given with
+    /// `--query` and file paths with `--path`, but neither both ways at once,
+    /// so with `--query` the paths come from `--path` or `--files-from`; after
+    /// a lone `--`, every argument is taken as one of these even if it starts
+    /// with `-`. Those listed in the file given to `--files-from` are read here
+    /// and added to the others; with `--files-from -` that means standard input
+    /// is read to its end by `build`, before anything is searched and even with
+    /// `--debug-config`. Short flags can be bundled, as in `-ic`, and a flag's
+    /// value can be attached, as in `--threads=4` or `-j4`.
+    ///
+    /// File paths, including those given to `--path` as a separate argument,
+    /// needn't be valid UTF-8, as the system doesn't require it; they are
+    /// printed lossily. The query, flags and other flag values must be.
+    ///
     /// # Arguments
     ///
-    /// * `args` - An iterator over command line arguments.
+    /// * `args` - An iterator over command line arguments, such as
+    ///   `env::args_os()`.
+    /// * `env` - Where environment variables are looked up, both those above
+    ///   and those locating the configuration file; `ProcessEnv` for the real
+    ///   environment.
     ///
     /// # Errors
     ///
-    /// Returns an error string if the arguments are insufficient or invalid.
+    /// Returns an error string if the arguments are insufficient or invalid, or
+    /// if the configuration file can't be read or parsed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    ///
+    /// use lilgrep::Config;
+    ///
+    /// let env = HashMap::from([("IGNORE_CASE".to_string(), "1".to_string())]);
+    /// let args = ["lilgrep", "--no-config", "needle", "notes.txt"].map(String::from);
+    /// let config = Config::build(args.into_iter(), env).unwrap();
+    /// assert!(config.ignore_case);
+    /// ```
     pub fn build(
-        mut args: impl DoubleEndedIterator<Item = String>,
-    ) -> Result<Config, &'static str> {
+        args: impl Iterator<Item = impl Into<OsString>>,
+        env: impl EnvProvider,
+    ) -> Result<Config, String> {
+        let args: Vec<OsString> = args.map(Into::into).collect();
+
+        let no_config = args
+            .iter()
+            .skip(1)
+            .take_while(|arg| *arg != "--")
+            .any(|arg| arg == "--no-config");
+        let defaults = if no_config {
+            FileDefaults::default()
+        } else {
+            let dir = std::env::current_dir().unwrap_or_default();
+            match config_file::config_file_path_in(&env, &dir) {
+                Some(path) => FileDefaults::load(&path)?,
+                None => FileDefaults::default(),
+            }
+        };
+        for warning in defaults.warnings() {
+            eprintln!("lilgrep: {warning}");
+        }
+
+        Config::build_with_env(&defaults, args.into_iter(), env)
+    }
+
+    /// Constructs a `Config` like `build`, starting from the given file
+    /// defaults instead of reading the configuration file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error string if the arguments are insufficient or invalid, or
+    /// if a default has the wrong type for its option.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::path::Path;
+    ///
+    /// use lilgrep::{Config, FileDefaults};
+    ///
+    /// let defaults = FileDefaults::parse(Path::new("config.toml"), "threads = 2\nrecursive = true").unwrap();
+    /// let args = ["lilgrep", "--threads", "8", "needle", "src"].map(String::from);
+    /// let config = Config::build_with_defaults(&defaults, args.into_iter()).unwrap();
+    /// assert!(config.recursive);
+    /// assert_eq!(config.threads, 8);
+    /// ```
+    pub fn build_with_defaults(
+        defaults: &FileDefaults,
+        args: impl Iterator<Item = impl Into<OsString>>,
+    ) -> Result<Config, String> {
+        Config::build_with_env(defaults, args, ProcessEnv)
+    }
+
+    /// Constructs a `Config` like `build_with_defaults`, looking environment
+    /// variables up in `env` instead of the process environment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error string if the arguments are insufficient or invalid, or
+    /// if a default has the wrong type for its option.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    ///
+    /// use lilgrep::{Config, FileDefaults};
+    ///
+    /// let env = HashMap::from([("LILGREP_IGNORE_CASE".to_string(), String::new())]);
+    /// let args = ["lilgrep", "needle", "notes.txt"].map(String::from);
+    /// let config = Config::build_with_env(&FileDefaults::default(), args.into_iter(), env).unwrap();
+    /// assert!(config.ignore_case);
+    /// ```
+    pub fn build_with_env(
+        defaults: &FileDefaults,
+        args: impl Iterator<Item = impl Into<OsString>>,
+        env: impl EnvProvider,
+    ) -> Result<Config, String> {
+        let mut args = args.map(Into::into);
         args.next();
-        let mut args = args.rev();
 
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file path"),
+        let mut config = Config::default();
+        defaults.apply(&mut config)?;
+
+        if IGNORE_CASE_VARS.iter().any(|name| env.get(name).is_some()) {
+            config.ignore_case = true;
+            config.origins.insert("ignore_case", Origin::Env);
+        }
+
+        let mut parser = Parser::new(args);
+        let mut positionals = Vec::new();
+        let mut query = None;
+
+        while let Some(arg) = parser.next()? {
+            let flag = match arg {
+                Arg::Flag(flag) => flag,
+                Arg::Positional(arg) => {
+                    positionals.push(arg);
+                    continue;
+                }
+            };
+
+            let option = match flag.as_str() {
+                "-h" | "--help" => {
+                    config.help = true;
+                    return Ok(config);
+                }
+                "--query" => {
+                    query = Some(parser.value(&flag)?);
+                    "query"
+                }
+                "--path" => {
+                    config.file_paths.push(parser.value_os(&flag)?.into());
+                    "file_paths"
+                }
+                "--files-from" => {
+                    config.files_from = Some(parser.value_os(&flag)?.into());
+                    "files_from"
+                }
+                "-e" | "--pattern" => {
+                    config.patterns.push(parser.value(&flag)?);
+                    "patterns"
+                }
+                "--any" => {
+                    let list = parser.value(&flag)?;
+                    let terms = list.split(',').filter(|term| !term.is_empty());
+                    let before = config.patterns.len();
+                    config.patterns.extend(terms.map(String::from));
+                    if config.patterns.len() == before {
+                        return Err(format!("--any needs at least one term, but got {list:?}"));
+                    }
+                    "patterns"
+                }
+                "--not" => {
+                    config.not_patterns.push(parser.value(&flag)?);
+                    "not_patterns"
+                }
+                "--soundex" => {
+                    config.soundex = true;
+                    "soundex"
+                }
+                "--fuzzy" => {
+                    config.fuzzy = Some(parse_count(&parser.value(&flag)?, "Edit distance")?);
+                    "fuzzy"
+                }
+                "--normalize" => {
+                    config.normalize = Some(parse_normal_form(&parser.value(&flag)?)?);
+                    "normalize"
+                }
+                "--field" => {
+                    config.field = Some(parse_field(&parser.value(&flag)?)?);
+                    "field"
+                }
+                "--diff-added" => {
+                    config.diff = Some(DiffSide::Added);
+                    "diff"
+                }
+                "--diff-removed" => {
+                    config.diff = Some(DiffSide::Removed);
+                    "diff"
+                }
+                "--delimiter" => {
+                    config.delimiter = Some(parse_delimiter(&parser.value(&flag)?)?);
+                    "delimiter"
+                }
+                "--all-of" | "--all" => {
+                    config.all_of = true;
+                    "all_of"
+                }
+                "-v" | "--invert-match" => {
+                    config.invert = true;
+                    "invert"
+                }
+                "-U" | "--multiline" => {
+                    config.multiline = true;
+                    "multiline"
+                }
+                "-i" | "--ignore-case" => {
+                    config.ignore_case = true;
+                    "ignore_case"
+                }
+                "-w" | "--word" => {
+                    config.whole_word = true;
+                    "whole_word"
+                }
+                "-x" | "--line-regexp" => {
+                    config.whole_line = true;
+                    "whole_line"
+                }
+                "--sort" => {
+                    config.sort = Some(SortOrder::Ascending);
+                    "sort"
+                }
+                "--sort-reverse" => {
+                    config.sort = Some(SortOrder::Descending);
+                    "sort"
+                }
+                "--count-matches" => {
+                    config.count_matches = true;
+                    "count_matches"
+                }
+                "--overlapping" => {
+                    config.overlapping = true;
+                    "overlapping"
+                }
+                "-c" | "--count" => {
+                    config.count = true;
+                    "count"
+                }
+                "--count-total" => {
+                    config.count_total = true;
+                    "count_total"
+                }
+                "--no-zero" => {
+                    config.no_zero = true;
+                    "no_zero"
+                }
+                "--first-last" => {
+                    config.first_last = true;
+                    "first_last"
+                }
+                "--histogram" => {
+                    config.histogram = true;
+                    "histogram"
+                }
+                "--invert-files" => {
+                    config.invert_files = true;
+                    "invert_files"
+                }
+                "--include-empty" => {
+                    config.include_empty = true;
+                    "include_empty"
+                }
+                "-m" | "--max-count" => {
+                    config.max_count = Some(parse_count(&parser.value(&flag)?, "Max count")?);
+                    "max_count"
+                }
+                "--max-per-line" => {
+                    config.max_per_line = Some(parse_count(&parser.value(&flag)?, "Max per line")?);
+                    "max_per_line"
+                }
+                "--max-total" => {
+                    config.max_total = Some(parse_count(&parser.value(&flag)?, "Max total")?);
+                    "max_total"
+                }
+                "-z" | "--search-compressed" => {
+                    config.search_compressed = true;
+                    "search_compressed"
+                }
+                "--pre" => {
+                    config.pre = Some(parser.value_os(&flag)?.into());
+                    "pre"
+                }
+                "--pre-glob" => {
+                    config.pre_glob = Some(parser.value(&flag)?);
+                    "pre_glob"
+                }
+                "--null-data" => {
+                    config.null_data = true;
+                    "null_data"
+                }
+                "--mmap" => {
+                    config.mmap = true;
+                    "mmap"
+                }
+                "--name-only" => {
+                    config.name_only = true;
+                    "name_only"
+                }
+                "--files" => {
+                    config.list_files = true;
+                    "list_files"
+                }
+                "--debug-config" => {
+                    config.debug_config = true;
+                    "debug_config"
+                }
+                "-r" | "--recursive" => {
+                    config.recursive = true;
+                    "recursive"
+                }
+                "--relative" => {
+                    config.full_paths = false;
+                    "full_paths"
+                }
+                "--no-relative" => {
+                    config.full_paths = true;
+                    "full_paths"
+                }
+                "--label" => {
+                    config.label = Some(parser.value(&flag)?);
+                    "label"
+                }
+                "-L" | "--follow" => {
+                    config.follow = true;
+                    "follow"
+                }
+                "--max-depth" => {
+                    config.max_depth = Some(parse_count(&parser.value(&flag)?, "Max depth")?);
+                    "max_depth"
+                }
+                "--max-filesize" => {
+                    config.max_filesize = Some(parse_size(&parser.value(&flag)?)?);
+                    "max_filesize"
+                }
+                "--skip-bytes" => {
+                    config.skip_bytes = parse_size(&parser.value(&flag)?)?;
+                    "skip_bytes"
+                }
+                "--sort-files" => {
+                    config.file_sort = parse_file_sort(&parser.value(&flag)?)?;
+                    "file_sort"
+                }
+                "--progress" => {
+                    config.progress = true;
+                    "progress"
+                }
+                "-s" | "--no-messages" => {
+                    config.no_messages = true;
+                    "no_messages"
+                }
+                "--watch" => {
+                    config.watch = true;
+                    "watch"
+                }
+                "--replace" => {
+                    config.replace = Some(parser.value(&flag)?);
+                    "replace"
+                }
+                "--in-place" | "--write" => {
+                    config.in_place = true;
+                    "in_place"
+                }
+                "--backup" => {
+                    config.backup = true;
+                    "backup"
+                }
+                "--format" => {
+                    config.format = OutputFormat::parse(&parser.value(&flag)?)?;
+                    "format"
+                }
+                "--output-field" => {
+                    config.output_field = Some(parse_field(&parser.value(&flag)?)?);
+                    "output_field"
+                }
+                "--skip-missing" => {
+                    config.skip_missing = true;
+                    "skip_missing"
+                }
+                "--trim" => {
+                    config.trim = true;
+                    "trim"
+                }
+                "--tab-width" => {
+                    config.tab_width = parse_count(&parser.value(&flag)?, "Tab width")?;
+                    "tab_width"
+                }
+                "--max-columns" => {
+                    config.max_columns = parse_count(&parser.value(&flag)?, "Max columns")?;
+                    "max_columns"
+                }
+                "--max-columns-preview" => {
+                    config.max_columns_preview = true;
+                    "max_columns_preview"
+                }
+                "--color" => {
+                    config.color = parse_color(&parser.value(&flag)?)?;
+                    "color"
+                }
+                "--mark" => {
+                    config.mark.get_or_insert_with(default_mark);
+                    "mark"
+                }
+                "--mark-start" => {
+                    let value = parser.value(&flag)?;
+                    config.mark.get_or_insert_with(default_mark).before = value;
+                    "mark"
+                }
+                "--mark-end" => {
+                    let value = parser.value(&flag)?;
+                    config.mark.get_or_insert_with(default_mark).after = value;
+                    "mark"
+                }
+                "--with-header" => {
+                    config.header_lines = parse_count(&parser.value(&flag)?, "Header lines")?;
+                    "header_lines"
+                }
+                "--passthru" => {
+                    config.passthru = true;
+                    "passthru"
+                }
+                "--heading" => {
+                    config.heading = Some(true);
+                    "heading"
+                }
+                "--no-heading" => {
+                    config.heading = Some(false);
+                    "heading"
+                }
+                "--vimgrep" => {
+                    config.format = OutputFormat::Vimgrep;
+                    "format"
+                }
+                "--no-header" => {
+                    config.no_header = true;
+                    "no_header"
+                }
+                "--line-buffered" => {
+                    config.buffering = Some(Buffering::Line);
+                    "buffering"
+                }
+                "--block-buffered" => {
+                    config.buffering = Some(Buffering::Block);
+                    "buffering"
+                }
+                "-j" | "--threads" => {
+                    config.threads = parse_count(&parser.value(&flag)?, "Thread count")?;
+                    "threads"
+                }
+                "--line-range" => {
+                    config.line_range = Some(parse_line_range(&parser.value(&flag)?)?);
+                    "line_range"
+                }
+                // Already handled by `build`.
+                "--no-config" => continue,
+                _ => return Err(format!("Unknown flag {flag}")),
+            };
+            config.origins.insert(option, Origin::Argv);
+        }
+
+        let mut positionals = positionals.into_iter().peekable();
+        match query {
+            Some(_) if !config.patterns.is_empty() => {
+                return Err("--query can't be combined with -e".into());
+            }
+            Some(query) => {
+                // Without `--path`, the first positional would be the query.
+                if let Some(positional) = positionals.next_if(|_| config.file_paths.is_empty()) {
+                    return Err(format!(
+                        "Got {} as the query, but the query was already given with --query",
+                        positional.to_string_lossy()
+                    ));
+                }
+                config.query = query;
+            }
+            None if config.patterns.is_empty() && !config.list_files => {
+                config.query = positionals
+                    .next()
+                    .ok_or(MISSING_QUERY)?
+                    .into_string()
+                    .map_err(|query| {
+                        format!("The query isn't valid UTF-8: {}", query.to_string_lossy())
+                    })?;
+            }
+            None => {}
+        }
+
+        if config.file_paths.is_empty() {
+            config.file_paths = positionals.map(PathBuf::from).collect();
+        } else if let Some(path) = positionals.next() {
+            return Err(format!(
+                "Got {} as a file path, but file paths were already given with --path",
+                path.to_string_lossy()
+            ));
+        }
+        if let Some(list) = &config.files_from {
+            let stdin = Path::new(STDIN_PATH);
+            if list == stdin && config.file_paths.iter().any(|path| path == stdin) {
+                return Err("--files-from - reads the list from standard input, \
+                    so - can't also be searched"
+                    .into());
+            }
+            let listed = read_file_list(list, config.separator())
+                .map_err(|err| format!("Couldn't read the file list {}: {err}", list.display()))?;
+            config.file_paths.extend(listed);
+        } else if config.file_paths.is_empty() {
+            return Err("Didn't get a file path".into());
+        }
+        if config.whole_line && (config.soundex || config.fuzzy.is_some_and(|edits| edits > 0)) {
+            return Err("-x can't be combined with --soundex or --fuzzy".into());
+        }
+        if config.soundex && (config.fuzzy.is_some_and(|edits| edits > 0) || config.whole_word) {
+            return Err("--soundex can't be combined with --fuzzy or -w".into());
+        }
+        // Fails for a query too long for `--fuzzy`, or one without letters for
+        // `--soundex`.
+        Patterns::new(&config)?;
+        if config.multiline && (config.mmap || config.max_count.is_some()) {
+            return Err("-U reads whole files, so it can't be combined with --mmap or -m".into());
+        }
+        if config.multiline && config.null_data {
+            return Err("-U can't be combined with --null-data".into());
+        }
+        if config.multiline && config.field.is_some() {
+            return Err("-U can't be combined with --field".into());
+        }
+        if config.multiline && config.diff.is_some() {
+            return Err("-U can't be combined with --diff-added or --diff-removed".into());
+        }
+        if config.multiline && config.passthru {
+            return Err("-U can't be combined with --passthru".into());
+        }
+        if config.multiline && config.invert {
+            return Err("-U can't be combined with -v".into());
+        }
+        if config.in_place {
+            if config.replace.is_none() {
+                return Err("--in-place needs --replace".into());
+            }
+            if config
+                .file_paths
+                .iter()
+                .any(|path| path == Path::new(STDIN_PATH))
+            {
+                return Err("--in-place can't be used when reading standard input".into());
+            }
+            if config.search_compressed || config.multiline {
+                return Err("--in-place can't be combined with -z or -U".into());
+            }
+            if config.skip_bytes > 0 {
+                return Err("--in-place can't be combined with --skip-bytes".into());
+            }
+            if config.pre.is_some() {
+                return Err("--in-place can't be combined with --pre".into());
+            }
+        } else if config.backup {
+            return Err("--backup needs --in-place".into());
+        }
+        if config.pre_glob.is_some() && config.pre.is_none() {
+            return Err("--pre-glob needs --pre".into());
+        }
+        if config.max_total.is_some() && (config.count || config.count_matches || config.in_place) {
+            return Err(
+                "--max-total can't be combined with -c, --count-matches or --in-place".into(),
+            );
+        }
+        if config.first_last
+            && (config.count
+                || config.count_matches
+                || config.count_total
+                || config.passthru
+                || config.in_place
+                || config.max_total.is_some())
+        {
+            return Err("--first-last can't be combined with -c, --count-matches, \
+                --count-total, --passthru, --in-place or --max-total"
+                .into());
+        }
+        if config.histogram
+            && (config.mmap
+                || config.max_count.is_some()
+                || config.passthru
+                || config.in_place
+                || config.name_only)
+        {
+            return Err(
+                "--histogram needs whole files searched, so it can't be combined \
+                with --mmap, -m, --passthru, --in-place or --name-only"
+                    .into(),
+            );
+        }
+        if config.output_field.is_some() && config.format == OutputFormat::Vimgrep {
+            return Err("--output-field can't be combined with --vimgrep".into());
+        }
+        if config.skip_missing && config.output_field.is_none() {
+            return Err("--skip-missing needs --output-field".into());
+        }
+        if config.list_files && config.name_only {
+            return Err("--files can't be combined with --name-only".into());
+        }
+        if config.overlapping && !config.count_matches {
+            return Err("--overlapping needs --count-matches".into());
+        }
+        if config.max_columns_preview && config.max_columns == 0 {
+            return Err("--max-columns-preview needs --max-columns".into());
+        }
+        if config.invert_files
+            && (config.count_matches
+                || config.count_total
+                || config.first_last
+                || config.passthru
+                || config.in_place
+                || config.max_total.is_some())
+        {
+            return Err("--invert-files can't be combined with --count-matches, \
+                --count-total, --first-last, --passthru, --in-place or --max-total"
+                .into());
+        }
+        if config.invert_files
+            && (config.name_only || config.list_files || config.format.is_structured())
+        {
+            return Err("--invert-files prints paths, so it can't be combined with \
+                --name-only, --files, --vimgrep or a --format other than text"
+                .into());
+        }
+        if config.include_empty && !config.first_last {
+            return Err("--include-empty needs --first-last".into());
+        }
+        if config.watch
+            && config
+                .file_paths
+                .iter()
+                .any(|path| path == Path::new(STDIN_PATH))
+        {
+            return Err("--watch can't be used when reading standard input".into());
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the patterns to search for: every `-e` pattern if there are
+    /// any, otherwise the query.
+    pub fn queries(&self) -> impl Iterator<Item = &str> {
+        let queries = if self.patterns.is_empty() {
+            std::slice::from_ref(&self.query)
+        } else {
+            &self.patterns
         };
+        queries.iter().map(String::as_str)
+    }
+
+    /// Whether the query or a pattern has a U+FFFD in it, which would also
+    /// match where invalid UTF-8 was decoded to one.
+    fn seeks_replacement(&self) -> bool {
+        std::iter::once(&self.query)
+            .chain(&self.patterns)
+            .any(|pattern| pattern.contains(char::REPLACEMENT_CHARACTER))
+    }
+
+    /// What separates fields: the `delimiter`, or a comma.
+    pub(crate) fn delimiter(&self) -> char {
+        self.delimiter.unwrap_or(',')
+    }
+
+    /// What separates records: NUL with `null_data`, otherwise a newline.
+    pub(crate) fn separator(&self) -> char {
+        if self.null_data { '\0' } else { '\n' }
+    }
+
+    /// Returns where the value of `option` came from.
+    pub fn origin(&self, option: &str) -> Origin {
+        self.origins.get(option).copied().unwrap_or_default()
+    }
+
+    /// Returns a readable description of the resolved configuration.
+    ///
+    /// Unlike a derived `Debug`, every option is listed with where its value
+    /// came from, and each file path is shown alongside its canonical form.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lilgrep::{Config, FileDefaults};
+    ///
+    /// let args = ["lilgrep", "--word", "needle", "haystack.txt"].map(String::from);
+    /// let config = Config::build_with_defaults(&FileDefaults::default(), args.into_iter()).unwrap();
+    /// let explained = config.explain().to_string();
+    /// assert!(explained.contains("whole_word: true (argv)"));
+    /// ```
+    pub fn explain(&self) -> Explain<'_> {
+        Explain(self)
+    }
+}
+
+/// Display adapter returned by `Config::explain`.
+pub struct Explain<'a>(&'a Config);
+
+impl fmt::Display for Explain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = self.0;
+        let or_none = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+
+        if config.patterns.is_empty() {
+            writeln!(f, "query: {:?}", config.query)?;
+        }
+        for pattern in &config.patterns {
+            writeln!(f, "pattern: {pattern:?}")?;
+        }
+        for pattern in &config.not_patterns {
+            writeln!(f, "not: {pattern:?}")?;
+        }
+        for path in &config.file_paths {
+            let shown = path.display();
+            match fs::canonicalize(path) {
+                Ok(resolved) => writeln!(f, "path: {shown} ({})", resolved.display())?,
+                Err(_) => writeln!(f, "path: {shown} (not found)")?,
+            }
+        }
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+        let sort = match config.sort {
+            Some(SortOrder::Ascending) => "ascending",
+            Some(SortOrder::Descending) => "descending",
+            None => "none",
+        };
+        let threads = match config.threads {
+            0 => "auto".to_string(),
+            n => n.to_string(),
+        };
+        let buffering = match config.buffering {
+            Some(Buffering::Line) => "line",
+            Some(Buffering::Block) => "block",
+            None => "auto",
         };
 
-        let mut ignore_case = env::var("IGNORE_CASE").is_ok();
+        let options = [
+            ("ignore_case", config.ignore_case.to_string()),
+            ("whole_word", config.whole_word.to_string()),
+            ("whole_line", config.whole_line.to_string()),
+            ("all_of", config.all_of.to_string()),
+            ("invert", config.invert.to_string()),
+            ("multiline", config.multiline.to_string()),
+            (
+                "field",
+                or_none(config.field.map(|field| field.to_string())),
+            ),
+            ("delimiter", format!("{:?}", config.delimiter())),
+            (
+                "diff",
+                or_none(config.diff.map(|side| match side {
+                    DiffSide::Added => "added".to_string(),
+                    DiffSide::Removed => "removed".to_string(),
+                })),
+            ),
+            (
+                "fuzzy",
+                or_none(config.fuzzy.map(|edits| edits.to_string())),
+            ),
+            ("soundex", config.soundex.to_string()),
+            (
+                "normalize",
+                or_none(
+                    config
+                        .normalize
+                        .map(|form| format!("{form:?}").to_lowercase()),
+                ),
+            ),
+            ("sort", sort.to_string()),
+            (
+                "line_range",
+                or_none(config.line_range.as_ref().map(|range| match *range.end() {
+                    usize::MAX => format!("{}:", range.start()),
+                    end => format!("{}:{end}", range.start()),
+                })),
+            ),
+            ("count_matches", config.count_matches.to_string()),
+            ("overlapping", config.overlapping.to_string()),
+            (
+                "max_count",
+                or_none(config.max_count.map(|count| count.to_string())),
+            ),
+            (
+                "max_per_line",
+                or_none(config.max_per_line.map(|count| count.to_string())),
+            ),
+            (
+                "max_total",
+                or_none(config.max_total.map(|total| total.to_string())),
+            ),
+            ("count", config.count.to_string()),
+            ("count_total", config.count_total.to_string()),
+            ("no_zero", config.no_zero.to_string()),
+            ("first_last", config.first_last.to_string()),
+            ("include_empty", config.include_empty.to_string()),
+            ("invert_files", config.invert_files.to_string()),
+            ("histogram", config.histogram.to_string()),
+            ("name_only", config.name_only.to_string()),
+            ("list_files", config.list_files.to_string()),
+            ("recursive", config.recursive.to_string()),
+            ("full_paths", config.full_paths.to_string()),
+            ("label", or_none(config.label.clone())),
+            (
+                "files_from",
+                or_none(
+                    config
+                        .files_from
+                        .as_ref()
+                        .map(|list| list.display().to_string()),
+                ),
+            ),
+            ("follow", config.follow.to_string()),
+            ("mmap", config.mmap.to_string()),
+            ("search_compressed", config.search_compressed.to_string()),
+            (
+                "pre",
+                or_none(config.pre.as_ref().map(|pre| pre.display().to_string())),
+            ),
+            ("pre_glob", or_none(config.pre_glob.clone())),
+            ("null_data", config.null_data.to_string()),
+            (
+                "max_depth",
+                or_none(config.max_depth.map(|depth| depth.to_string())),
+            ),
+            (
+                "max_filesize",
+                or_none(config.max_filesize.map(|bytes| bytes.to_string())),
+            ),
+            ("skip_bytes", config.skip_bytes.to_string()),
+            (
+                "file_sort",
+                format!("{:?}", config.file_sort).to_lowercase(),
+            ),
+            ("threads", threads),
+            ("buffering", buffering.to_string()),
+            ("format", format!("{:?}", config.format).to_lowercase()),
+            ("no_header", config.no_header.to_string()),
+            ("tab_width", config.tab_width.to_string()),
+            ("trim", config.trim.to_string()),
+            (
+                "output_field",
+                or_none(config.output_field.map(|field| field.to_string())),
+            ),
+            ("skip_missing", config.skip_missing.to_string()),
+            ("max_columns", config.max_columns.to_string()),
+            (
+                "max_columns_preview",
+                config.max_columns_preview.to_string(),
+            ),
+            ("header_lines", config.header_lines.to_string()),
+            ("passthru", config.passthru.to_string()),
+            (
+                "heading",
+                match config.heading {
+                    Some(true) => "on",
+                    Some(false) => "off",
+                    None => "auto",
+                }
+                .to_string(),
+            ),
+            (
+                "color",
+                match config.color {
+                    Some(true) => "always",
+                    Some(false) => "never",
+                    None => "auto",
+                }
+                .to_string(),
+            ),
+            (
+                "mark",
+                or_none(
+                    config
+                        .mark
+                        .as_ref()
+                        .map(|mark| format!("{:?} {:?}", mark.before, mark.after)),
+                ),
+            ),
+            ("watch", config.watch.to_string()),
+            ("progress", config.progress.to_string()),
+            ("no_messages", config.no_messages.to_string()),
+            (
+                "replace",
+                or_none(config.replace.as_ref().map(|text| format!("{text:?}"))),
+            ),
+            ("in_place", config.in_place.to_string()),
+            ("backup", config.backup.to_string()),
+        ];
 
-        for arg in args {
-            if arg.eq("--ignore-case") {
-                ignore_case = true;
+        for (i, (name, value)) in options.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
             }
+            let origin = match config.origin(name) {
+                Origin::Default => "default",
+                Origin::File => "file",
+                Origin::Env if *name == "ignore_case" => "env LILGREP_IGNORE_CASE or IGNORE_CASE",
+                Origin::Env => "env",
+                Origin::Argv => "argv",
+            };
+            write!(f, "{name}: {value} ({origin})")?;
         }
 
-        Ok(Config {
-            query,
-            file_path,
-            ignore_case,
-        })
+        Ok(())
     }
 }
 
-/// Executes the search based on the provided configuration.
-///
-/// Reads the file specified in the configuration and searches for the query string.
-/// Prints each matching line.
+/// Parses a non-negative count given for `what`.
+fn parse_count(value: &str, what: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("{what} is not a number"))
+}
+
+/// Reads the paths listed in `list`, or on standard input for `-`, each ending
+/// with `separator`, as `--files-from` takes them.
 ///
-/// # Arguments
+/// Empty entries are left out, and so is the `\r` of a `\r\n` line ending.
+fn read_file_list(list: &Path, separator: char) -> io::Result<Vec<PathBuf>> {
+    let contents = if list == Path::new(STDIN_PATH) {
+        let mut contents = Vec::new();
+        io::stdin().lock().read_to_end(&mut contents)?;
+        contents
+    } else {
+        fs::read(list)?
+    };
+    Ok(contents
+        .split(|&byte| char::from(byte) == separator)
+        .map(|entry| match separator {
+            '\n' => entry.strip_suffix(b"\r").unwrap_or(entry),
+            _ => entry,
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+/// Turns bytes read from a file list into a path, keeping them as they are
+/// where paths needn't be UTF-8.
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).into()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+/// Parses the value of `--sort-files`.
+fn parse_file_sort(value: &str) -> Result<FileSort, &'static str> {
+    match value {
+        "path" => Ok(FileSort::Path),
+        "modified" => Ok(FileSort::Modified),
+        "size" => Ok(FileSort::Size),
+        "none" => Ok(FileSort::None),
+        _ => Err("File sort must be one of path, modified, size or none"),
+    }
+}
+
+/// Parses a buffering mode, `line` or `block`.
+fn parse_buffering(value: &str) -> Result<Buffering, &'static str> {
+    match value {
+        "line" => Ok(Buffering::Line),
+        "block" => Ok(Buffering::Block),
+        _ => Err("Buffering must be line or block"),
+    }
+}
+
+fn parse_field(value: &str) -> Result<usize, String> {
+    match parse_count(value, "Field")? {
+        0 => Err("Fields are numbered from 1".into()),
+        field => Ok(field),
+    }
+}
+
+fn parse_delimiter(value: &str) -> Result<char, &'static str> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ if value == "\\t" => Ok('\t'),
+        _ => Err("Delimiter must be a single character"),
+    }
+}
+
+/// The markers `--mark` puts around matches unless told otherwise.
+fn default_mark() -> Style {
+    Style::new("[", "]")
+}
+
+fn parse_color(value: &str) -> Result<Option<bool>, &'static str> {
+    match value {
+        "auto" => Ok(None),
+        "always" => Ok(Some(true)),
+        "never" => Ok(Some(false)),
+        _ => Err("Color must be auto, always or never"),
+    }
+}
+
+/// Parses a Unicode normalization form, `nfc` or `nfd`.
+fn parse_normal_form(value: &str) -> Result<NormalForm, &'static str> {
+    match value {
+        "nfc" | "NFC" => Ok(NormalForm::Nfc),
+        "nfd" | "NFD" => Ok(NormalForm::Nfd),
+        _ => Err("Normalization form must be nfc or nfd"),
+    }
+}
+
+/// Parses a size in bytes, optionally followed by `K`, `M` or `G` (in either
+/// case) for kibibytes, mebibytes or gibibytes.
+fn parse_size(value: &str) -> Result<u64, &'static str> {
+    let (digits, shift) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 10),
+        Some((i, 'm' | 'M')) => (&value[..i], 20),
+        Some((i, 'g' | 'G')) => (&value[..i], 30),
+        _ => (value, 0),
+    };
+    let bytes: u64 = digits
+        .parse()
+        .map_err(|_| "Size must be a number, optionally followed by K, M or G")?;
+    bytes.checked_mul(1 << shift).ok_or("Size is too large")
+}
+
+/// Parses a `START:END` line range, where both ends are 1-based and inclusive.
 ///
-/// * `config` - The configuration specifying the query, file path, and case sensitivity.
+/// Either end may be left out: `:END` starts at the first line, and `START:`
+/// runs to the last.
 ///
 /// # Errors
 ///
-/// Returns an error if the file cannot be read.
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
+/// Returns an error string if an end given is not a number, if `START` or
+/// `END` is zero, or if `END` precedes `START`.
+fn parse_line_range(value: &str) -> Result<RangeInclusive<usize>, &'static str> {
+    let (start, end) = value
+        .split_once(':')
+        .ok_or("Line range must be of the form START:END")?;
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
-    } else {
-        search(&config.query, &contents)
+    let start: usize = match start {
+        "" => 1,
+        start => start
+            .parse()
+            .map_err(|_| "Line range start is not a number")?,
+    };
+    let end: usize = match end {
+        "" => usize::MAX,
+        end => end.parse().map_err(|_| "Line range end is not a number")?,
     };
 
-    for line in results {
-        println!("{line}");
+    if start == 0 || end == 0 {
+        return Err("Line range starts at line 1");
+    }
+    if end < start {
+        return Err("Line range end must not precede its start");
     }
 
-    Ok(())
+    Ok(start..=end)
+}
+
+/// Outcome of a completed search.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    /// Whether any file could not be read.
+    pub had_errors: bool,
+    /// The number of files and directories that could not be read, each of
+    /// which was skipped with a warning while the rest were searched.
+    pub unreadable: usize,
+    /// The number of matching lines across all files searched.
+    pub matched_lines: usize,
+    /// The number of paths printed on their own: every file with
+    /// `list_files`, or those without matching lines with `invert_files`.
+    pub files_listed: usize,
+    /// With `in_place`, the number of files rewritten.
+    pub files_changed: usize,
+    /// With `in_place`, the number of matches replaced across all files.
+    pub replacements: usize,
 }
 
-/// Searches for a query string in the given contents.
+/// Executes the search based on the provided configuration.
 ///
-/// This search is case-sensitive.
+/// Reads each file specified in the configuration, and with `recursive` each
+/// one found below it, and prints what matches to stdout, as the `Config`
+/// fields describe. A file that cannot be read is reported on stderr and
+/// skipped, as is a directory that can't be listed; the returned `Summary`
+/// records that it happened, and the `lilgrep` binary then exits with status 2.
 ///
 /// # Arguments
 ///
-/// * `query` - The string to search for.
-/// * `contents` - The text to search within.
+/// * `config` - The configuration specifying the query, file paths, and search options.
+///
+/// # Errors
+///
+/// Returns an error if the search cannot be carried out at all.
+pub fn run(mut config: Config) -> Result<Summary, Box<dyn Error>> {
+    let stdout = io::stdout().lock();
+    config.color.get_or_insert(stdout.is_terminal());
+    config.heading.get_or_insert(stdout.is_terminal());
+    let buffering = config.buffering.unwrap_or(if stdout.is_terminal() {
+        Buffering::Line
+    } else {
+        Buffering::Block
+    });
+
+    match buffering {
+        // Stdout is a `LineWriter` already.
+        Buffering::Line => run_or_watch(&config, stdout),
+        Buffering::Block => run_or_watch(&config, BufWriter::with_capacity(64 * 1024, stdout)),
+    }
+}
+
+fn run_or_watch(config: &Config, out: impl Write) -> Result<Summary, Box<dyn Error>> {
+    if config.watch {
+        watch::watch(config, out)
+    } else {
+        run_to(config, out)
+    }
+}
+
+/// Executes the search like `run`, writing the results to `out` instead of
+/// stdout. `watch` is ignored, and matches are only highlighted if `color` is
+/// set to `Some(true)`, and headings only used if `heading` is.
+///
+/// # Errors
 ///
-/// # Returns
+/// Returns an error if the search cannot be carried out at all, including when
+/// writing to `out` fails.
+pub fn run_to(config: &Config, out: impl Write) -> Result<Summary, Box<dyn Error>> {
+    run_with_matcher(config, &Patterns::new(config)?, out)
+}
+
+/// Executes the search like `run_to`, deciding which lines match with
+/// `matcher` instead of the patterns in `config`.
 ///
-/// A vector of lines that contain the query string.
+/// Everything else, from the files searched to how the results are counted and
+/// printed, still follows `config`; options that change what matches, such as
+/// `ignore_case` or `invert`, are up to `matcher`.
 ///
 /// # Example
 ///
 /// ```
-/// let contents = "Rust:
-/// safe, fast, productive.
-/// Pick three.";
-/// let results = search("duct", contents);
-/// assert_eq!(results, vec!["safe, fast, productive."]);
-/// ```
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    contents
-        .lines()
-        .filter(|line| line.contains(query))
-        .collect()
-}
-
-/// Searches for a query string in the given contents, ignoring case.
+/// use lilgrep::{CaseMode, Config, Searcher, run_with_matcher};
 ///
-/// # Arguments
+/// # let path = std::env::temp_dir().join("lilgrep-doc-run-with-matcher.txt");
+/// # std::fs::write(&path, "Rust\nnothing\ntrust").unwrap();
+/// let config = Config {
+///     file_paths: vec![path.clone()],
+///     ..Config::default()
+/// };
+/// let matcher = Searcher::new("RUST", CaseMode::Insensitive, false);
+///
+/// let mut out = Vec::new();
+/// run_with_matcher(&config, &matcher, &mut out).unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), "Rust\ntrust\n");
+/// # std::fs::remove_file(path).unwrap();
+/// ```
 ///
-/// * `query` - The string to search for.
-/// * `contents` - The text to search within.
+/// # Errors
 ///
-/// # Returns
+/// Returns an error if the search cannot be carried out at all, including when
+/// writing to `out` fails.
+pub fn run_with_matcher(
+    config: &Config,
+    matcher: &(dyn Matcher + Sync),
+    mut out: impl Write,
+) -> Result<Summary, Box<dyn Error>> {
+    if config.debug_config {
+        eprintln!("{}", config.explain());
+    }
+
+    let mut summary = Summary::default();
+    let threads = thread_count(config);
+    if config.list_files {
+        for input in selected_files(config, &mut summary) {
+            write!(
+                out,
+                "{}{}",
+                display_input(&input, config),
+                config.separator()
+            )?;
+            summary.files_listed += 1;
+        }
+    } else if config.recursive && threads > 1 && config.file_sort == FileSort::None {
+        search_while_walking(config, matcher, threads, &mut out, &mut summary)?;
+    } else {
+        let files = selected_files(config, &mut summary);
+        search_files(
+            config,
+            matcher,
+            Files::Listed(&files),
+            &mut out,
+            &mut summary,
+        )?;
+    }
+    out.flush()?;
+    if config.in_place {
+        let plural = |n: usize, word: &str| match n {
+            1 => format!("1 {word}"),
+            _ => format!("{n} {word}s"),
+        };
+        eprintln!(
+            "lilgrep: made {} in {}",
+            plural(summary.replacements, "replacement"),
+            plural(summary.files_changed, "file")
+        );
+    }
+
+    Ok(summary)
+}
+
+/// Returns the files a search with `config` would read, in the order it
+/// would read them, without searching them.
 ///
-/// A vector of lines that contain the query string, case-insensitively.
+/// Every path is expanded and filtered as `run` would do it, so this shows
+/// the effect of options such as `recursive`, `max_depth`, `follow`,
+/// `max_filesize` and `file_sort`. Paths are as found, before `display_path`
+/// shortens them for printing. Anything that can't be read is reported on
+/// stderr and left out.
 ///
 /// # Example
 ///
 /// ```
-/// let contents = "Rust:
-/// safe, fast, productive.
-/// Pick three.";
-/// let results = search_case_insensitive("rUsT", contents);
-/// assert_eq!(results, vec!["Rust:"]);
+/// use std::path::PathBuf;
+///
+/// use lilgrep::{Config, files_to_search};
+///
+/// let config = Config {
+///     file_paths: vec![PathBuf::from("Cargo.toml"), PathBuf::from("src")],
+///     ..Config::default()
+/// };
+/// assert_eq!(
+///     files_to_search(&config),
+///     [PathBuf::from("Cargo.toml"), PathBuf::from("src")]
+/// );
 /// ```
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-
-    contents
-        .lines()
-        .filter(|line| line.to_lowercase().contains(&query))
-        .collect()
+pub fn files_to_search(config: &Config) -> Vec<PathBuf> {
+    let mut files: Vec<_> = selected_files(config, &mut Summary::default())
+        .into_iter()
+        .map(|input| input.path)
+        .collect();
+    // Each entry of an archive comes with its path.
+    files.dedup();
+    files
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn case_sensitive() {
-        let query = "duct";
-        let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.
-Duct tape.";
+/// A file to search: a path, or with the `zip` feature a text entry read from
+/// the archive at that path.
+#[derive(Debug, Clone)]
+pub(crate) struct Input {
+    /// The file, or the archive holding the entry.
+    pub(crate) path: PathBuf,
+    /// The entry, whose name is only ever shown, never opened as a path.
+    #[cfg(feature = "zip")]
+    pub(crate) entry: Option<std::sync::Arc<zip::TextEntry>>,
+}
 
-        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+impl Input {
+    /// Returns the last component of the file's name, or of the entry's.
+    fn file_name(&self) -> Option<&OsStr> {
+        #[cfg(feature = "zip")]
+        if let Some(entry) = &self.entry {
+            return Path::new(&entry.name).file_name();
+        }
+        self.path.file_name()
+    }
+}
+
+impl From<PathBuf> for Input {
+    fn from(path: PathBuf) -> Input {
+        Input {
+            path,
+            #[cfg(feature = "zip")]
+            entry: None,
+        }
+    }
+}
+
+/// Collects the files to search like `collect_files`, leaving out those
+/// `skipped` rules out.
+fn selected_files(config: &Config, summary: &mut Summary) -> Vec<Input> {
+    let mut files = collect_files(config, summary);
+    files.retain(|input| !skipped(config, &input.path));
+    files
+}
+
+/// Whether `path` is left out of the search despite being found, because it is
+/// larger than `max_filesize`.
+fn skipped(config: &Config, path: &Path) -> bool {
+    config.max_filesize.is_some_and(|max| {
+        path != Path::new(STDIN_PATH) && fs::metadata(path).is_ok_and(|meta| meta.len() > max)
+    })
+}
+
+/// Returns how many threads to search on.
+fn thread_count(config: &Config) -> usize {
+    match config.threads {
+        0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        n => n,
+    }
+}
+
+/// Expands the configured paths into the files to search, in search order.
+///
+/// On more than one thread, directories are walked in parallel.
+fn collect_files(config: &Config, summary: &mut Summary) -> Vec<Input> {
+    let threads = thread_count(config);
+    let mut files = Vec::new();
+    for path in &config.file_paths {
+        let start = files.len();
+        if threads == 1 || !config.recursive || !path.is_dir() {
+            walk::collect_files(path, config, &mut files, summary);
+        } else {
+            let found = Mutex::new(Vec::new());
+            let push = |path: PathBuf| {
+                found.lock().unwrap().push(path.into());
+                true
+            };
+            walk::walk_parallel(vec![path.clone()], config, threads, push, summary);
+            files.append(&mut found.into_inner().unwrap());
+        }
+        // Paths are sorted within each directory, and otherwise kept in the
+        // order they were given.
+        if config.file_sort == FileSort::Path {
+            walk::sort_files(&mut files[start..], FileSort::Path);
+        }
+    }
+    if config.file_sort != FileSort::Path {
+        walk::sort_files(&mut files, config.file_sort);
+    }
+
+    files
+}
+
+/// How many files found by the walk can wait to be searched before it pauses.
+const FOUND_CAPACITY: usize = 1024;
+
+/// Searches files while a parallel walk is still finding them, which is only
+/// possible when they needn't be sorted first.
+fn search_while_walking(
+    config: &Config,
+    matcher: &(dyn Matcher + Sync),
+    threads: usize,
+    out: &mut impl Write,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    let (sender, receiver) = mpsc::sync_channel(FOUND_CAPACITY);
+    let mut walked = Summary::default();
+    let (listed, dirs) = walk::split_roots(config, &mut walked);
+
+    thread::scope(|scope| {
+        let walked = &mut walked;
+        scope.spawn(move || {
+            // Sending only fails once the search has given up.
+            let send = |input: Input| skipped(config, &input.path) || sender.send(input).is_ok();
+            if listed.into_iter().all(send) {
+                let send = |path: PathBuf| send(path.into());
+                walk::walk_parallel(dirs, config, threads, send, walked);
+            }
+        });
+        search_files(config, matcher, Files::Found(receiver), out, summary)
+    })?;
+
+    summary.had_errors |= walked.had_errors;
+    summary.unreadable += walked.unreadable;
+    Ok(())
+}
+
+/// The files for `search_files` to search.
+pub(crate) enum Files<'a> {
+    /// Every file, in search order.
+    Listed(&'a [Input]),
+    /// Files as a walk finds them, until it is done.
+    Found(mpsc::Receiver<Input>),
+}
+
+impl<'a> Files<'a> {
+    /// Returns how many files there are, when they are known up front.
+    fn len(&self) -> Option<usize> {
+        match self {
+            Files::Listed(files) => Some(files.len()),
+            Files::Found(_) => None,
+        }
+    }
+
+    fn into_inputs(self) -> Box<dyn Iterator<Item = Input> + Send + 'a> {
+        match self {
+            Files::Listed(files) => Box::new(files.iter().cloned()),
+            Files::Found(receiver) => Box::new(receiver.into_iter()),
+        }
+    }
+}
+
+/// Searches `files`, writing the results to `out` in the configured order.
+fn search_files(
+    config: &Config,
+    matcher: &(dyn Matcher + Sync),
+    files: Files,
+    out: &mut impl Write,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    let with_path = config.recursive || config.file_paths.len() > 1 || names_archive(config);
+    let headings = with_path
+        && config.heading == Some(true)
+        && !config.format.is_structured()
+        && !config.count
+        && !config.count_matches
+        && !config.first_last
+        && !config.invert_files;
+
+    if config.name_only {
+        for input in files.into_inputs() {
+            let Some(name) = input.file_name() else {
+                continue;
+            };
+            if matcher.is_match(&name.to_string_lossy()) {
+                writeln!(out, "{}", display_input(&input, config))?;
+                summary.matched_lines += 1;
+            }
+        }
+        return Ok(());
+    }
+
+    // Set once `max_total` is reached, so no more files are searched and a
+    // walk still finding them is stopped.
+    let done = AtomicBool::new(false);
+    let search = |input: &Input| {
+        if done.load(Ordering::Relaxed) {
+            return Ok(FileOutput::default());
+        }
+        search_input(matcher, config, input, with_path, headings)
+    };
+    if let Some(header) = config.format.header().filter(|_| !config.no_header) {
+        out.write_all(header.as_bytes())?;
+    }
+
+    let mut progress =
+        (config.progress && io::stderr().is_terminal()).then(|| Progress::new(files.len()));
+    let mut headed = false;
+    let mut emit = |input: &Input, result: io::Result<FileOutput>| {
+        let mut matched_lines = 0;
+        match result {
+            Ok(mut output) => {
+                if let Some(max_total) = config.max_total {
+                    let left = max_total.saturating_sub(summary.matched_lines);
+                    output.truncate(left);
+                    if output.matched_lines == left {
+                        done.store(true, Ordering::Relaxed);
+                    }
+                }
+                summary.matched_lines += output.matched_lines;
+                summary.files_listed += usize::from(output.listed);
+                if output.replacements > 0 {
+                    summary.files_changed += 1;
+                    summary.replacements += output.replacements;
+                }
+                if headings && !output.text.is_empty() {
+                    if headed {
+                        writeln!(out)?;
+                    }
+                    writeln!(out, "{}", display_input(input, config))?;
+                    headed = true;
+                }
+                match &mut progress {
+                    // Clear the line first, and make sure the results are out
+                    // before it is drawn again.
+                    Some(progress) if !output.text.is_empty() => {
+                        progress.finish();
+                        out.write_all(output.text.as_bytes())?;
+                        out.flush()?;
+                    }
+                    _ => out.write_all(output.text.as_bytes())?,
+                }
+                matched_lines = output.matched_lines;
+                if let Some(histogram) = &output.histogram {
+                    if let Some(progress) = &mut progress {
+                        progress.finish();
+                    }
+                    out.flush()?;
+                    eprintln!(
+                        "{} |{}|",
+                        display_input(input, config),
+                        histogram.sparkline()
+                    );
+                }
+            }
+            Err(err) => {
+                if let Some(progress) = &mut progress {
+                    progress.finish();
+                }
+                warn(config, &input.path, &err);
+                summary.had_errors = true;
+                summary.unreadable += 1;
+            }
+        }
+        if let Some(progress) = &mut progress {
+            progress.file_done(&display_input(input, config), matched_lines);
+        }
+        Ok(())
+    };
+
+    let threads = thread_count(config);
+    if config.passthru && !config.count && !config.count_matches {
+        passthru_files(config, matcher, files, with_path, headings, out, summary)?;
+    } else if threads == 1 || files.len().is_some_and(|len| len < 2) {
+        for input in files.into_inputs() {
+            if done.load(Ordering::Relaxed) {
+                break;
+            }
+            emit(&input, search(&input))?;
+        }
+    } else {
+        let ordered = config.file_sort != FileSort::None;
+        let files = files.into_inputs();
+        search_parallel(files, threads, ordered, &done, search, &mut emit)?;
+    }
+    if let Some(progress) = &mut progress {
+        progress.finish();
+    }
+
+    if config.count_total {
+        if config.count {
+            writeln!(out, "total:{}", summary.matched_lines)?;
+        } else {
+            writeln!(out, "{}", summary.matched_lines)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Searches one of the files for `search_files`, naming the file in any
+/// error.
+fn search_input(
+    matcher: &dyn Matcher,
+    config: &Config,
+    input: &Input,
+    with_path: bool,
+    headings: bool,
+) -> io::Result<FileOutput> {
+    search_for_output(matcher, config, input, with_path && !headings, headings)
+        .map_err(|err| SearchError::io("read", &input.path, err))
+}
+
+/// The path that means standard input.
+const STDIN_PATH: &str = "-";
+
+/// How standard input is named in output.
+const STDIN_LABEL: &str = "<stdin>";
+
+/// Reports `err`, which concerns `path`, on stderr, unless `no_messages` hides
+/// it.
+pub(crate) fn warn(config: &Config, path: &Path, err: &dyn fmt::Display) {
+    if let Some(warning) = warning(config, path, err) {
+        eprintln!("{warning}");
+    }
+}
+
+/// Returns the line `warn` prints, or `None` if there is nothing to print.
+///
+/// The only path on the command line is always reported, as nothing else was
+/// searched; with `recursive`, what is found below it is not.
+fn warning(config: &Config, path: &Path, err: &dyn fmt::Display) -> Option<String> {
+    let named = matches!(&config.file_paths[..], [only] if only == path);
+    (!config.no_messages || named).then(|| format!("warning: {err}"))
+}
+
+/// Whether a zip archive is among the configured paths, with the `zip` feature,
+/// in which case its entries are shown like separate files.
+#[cfg(feature = "zip")]
+fn names_archive(config: &Config) -> bool {
+    config.file_paths.iter().any(|path| zip::is_archive(path))
+}
+
+#[cfg(not(feature = "zip"))]
+fn names_archive(_: &Config) -> bool {
+    false
+}
+
+/// Returns `input` as it should appear in output: its path as `display_path`
+/// shows it, followed for an entry of a zip archive by `:` and the entry's
+/// name.
+fn display_input(input: &Input, config: &Config) -> String {
+    let path = display_path(&input.path, config);
+    #[cfg(feature = "zip")]
+    if let Some(entry) = &input.entry {
+        return format!("{path}:{}", entry.name);
+    }
+    path
+}
+
+/// Returns `path` as it should appear in output.
+///
+/// Standard input appears as the `label`, if there is one. Unless
+/// `full_paths`, a path found below an absolute directory given on the command
+/// line is shown relative to that directory, and a leading `./` is left out;
+/// paths below a relative directory already start with its name.
+fn display_path(path: &Path, config: &Config) -> String {
+    if path == Path::new(STDIN_PATH) {
+        return config.label.as_deref().unwrap_or(STDIN_LABEL).to_string();
+    }
+    if config.full_paths {
+        return path.display().to_string();
+    }
+
+    let shown = config
+        .file_paths
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|root| root.is_absolute())
+        .find_map(|root| {
+            let rest = path.strip_prefix(root).ok()?;
+            (!rest.as_os_str().is_empty()).then_some(rest)
+        })
+        .unwrap_or(path);
+    shown
+        .strip_prefix(".")
+        .unwrap_or(shown)
+        .display()
+        .to_string()
+}
+
+/// Opens `input`'s file, or standard input for `-`.
+///
+/// With `search_compressed`, gzip data, recognised by its magic bytes whatever
+/// the file is called, is decompressed first. An entry of a zip archive is
+/// read from the contents `zip::entries` kept. With `pre`, a file that
+/// `pre_glob` allows is run through the preprocessor instead, and its output
+/// read as it comes. Either way, `skip_start` then moves past the first
+/// `skip_bytes` bytes.
+fn open_input(input: &Input, config: &Config) -> io::Result<Box<dyn BufRead>> {
+    #[cfg(feature = "zip")]
+    if let Some(entry) = &input.entry {
+        let mut reader: Box<dyn BufRead> = Box::new(io::Cursor::new(entry.contents.clone()));
+        skip_start(&mut reader, config, 0)?;
+        return Ok(reader);
+    }
+    let path = input.path.as_path();
+    let mut seeked = 0;
+    let mut reader: Box<dyn BufRead> = if path == Path::new(STDIN_PATH) {
+        Box::new(io::stdin().lock())
+    } else if let Some(command) = &config.pre
+        && pre::applies(config.pre_glob.as_deref(), path)
+    {
+        let mut reader = pre::open(command.as_os_str(), path)?;
+        skip_start(&mut reader, config, 0)?;
+        return Ok(reader);
+    } else {
+        let mut file = File::open(path)?;
+        // Seeking a compressed file would hide its magic bytes.
+        if config.skip_bytes > 0 && !config.search_compressed {
+            seeked = config.skip_bytes - 1;
+            file.seek(SeekFrom::Start(seeked))?;
+        }
+        Box::new(BufReader::new(file))
+    };
+
+    if config.search_compressed && reader.fill_buf()?.starts_with(&gzip::MAGIC) {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        reader = Box::new(io::Cursor::new(gzip::decompress(&data)?));
+    }
+
+    skip_start(&mut reader, config, seeked)?;
+    Ok(reader)
+}
+
+/// Moves `reader`, which has already been moved `seeked` bytes into its input,
+/// past the first `skip_bytes` bytes, and on to the start of the next line
+/// unless the last byte skipped ends one.
+///
+/// Input that can't seek, such as standard input or decompressed data, is read
+/// and thrown away.
+fn skip_start(reader: &mut Box<dyn BufRead>, config: &Config, seeked: u64) -> io::Result<()> {
+    let Some(last) = config.skip_bytes.checked_sub(1) else {
+        return Ok(());
+    };
+    // Everything up to the byte before the first one wanted, which tells
+    // whether that one starts a line.
+    io::copy(&mut reader.by_ref().take(last - seeked), &mut io::sink())?;
+    reader.skip_until(config.separator() as u8)?;
+    Ok(())
+}
+
+/// Reads the whole of `input`, or standard input for `-`.
+///
+/// Input that isn't valid UTF-8 is decoded lossily, which keeps every ASCII
+/// byte as it is, so ASCII text can still be found in it. If the query could
+/// match the U+FFFD standing for an invalid sequence, it comes with the text
+/// from `mask_invalid` too, for deciding which lines match.
+fn read_input(input: &Input, config: &Config) -> io::Result<(String, Option<String>)> {
+    let mut contents = Vec::new();
+    open_input(input, config)?.read_to_end(&mut contents)?;
+    Ok(match String::from_utf8(contents) {
+        Ok(contents) => (contents, None),
+        Err(err) => {
+            let masked = config
+                .seeks_replacement()
+                .then(|| mask_invalid(err.as_bytes()));
+            (String::from_utf8_lossy(err.as_bytes()).into_owned(), masked)
+        }
+    })
+}
+
+/// Decodes `bytes` as `String::from_utf8_lossy` does, but with each invalid
+/// sequence turned into three NULs rather than U+FFFD.
+///
+/// That keeps every offset the same as in the lossy text, and as a query can't
+/// hold a NUL, whatever matches the masked line is really in the file.
+fn mask_invalid(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        if !chunk.invalid().is_empty() {
+            text.push_str("\0".repeat(char::REPLACEMENT_CHARACTER.len_utf8()).as_str());
+        }
+    }
+    text
+}
+
+/// What searching a single file produced.
+#[derive(Default)]
+struct FileOutput {
+    /// The text to print for the file.
+    text: String,
+    /// The number of matching lines in the file.
+    matched_lines: usize,
+    /// Where in `text` each printed matching line ends.
+    ends: Vec<usize>,
+    /// Where the matching lines are, with `histogram`.
+    histogram: Option<Histogram>,
+    /// How many matches were written back to the file, with `in_place`.
+    replacements: usize,
+    /// Whether the path was printed on its own, with `invert_files`.
+    listed: bool,
+}
+
+impl FileOutput {
+    /// Drops every matching line after the first `limit`, along with
+    /// anything printed after them.
+    fn truncate(&mut self, limit: usize) {
+        if self.matched_lines <= limit {
+            return;
+        }
+        self.matched_lines = limit;
+        if !self.ends.is_empty() {
+            let end = limit.checked_sub(1).map_or(0, |last| self.ends[last]);
+            self.text.truncate(end);
+            self.ends.truncate(limit);
+        }
+    }
+}
+
+/// Writes the lines of one file in the configured format.
+struct Printer<'a> {
+    matcher: &'a dyn Matcher,
+    config: &'a Config,
+    /// The path of the file, as displayed.
+    display: String,
+    with_path: bool,
+    numbered: bool,
+    /// How to highlight matches, if they are highlighted.
+    style: Option<Style>,
+}
+
+impl<'a> Printer<'a> {
+    fn new(
+        matcher: &'a dyn Matcher,
+        config: &'a Config,
+        display: String,
+        with_path: bool,
+        numbered: bool,
+    ) -> Printer<'a> {
+        let color = (config.color == Some(true)).then(Style::ansi);
+        // Markers go outside the color, so they print uncolored.
+        let style = match (config.mark.clone(), color) {
+            (Some(mark), Some(color)) => Some(Style {
+                before: mark.before + &color.before,
+                after: color.after + &mark.after,
+            }),
+            (mark, color) => mark.or(color),
+        }
+        .filter(|_| !config.format.is_structured() && config.replace.is_none());
+
+        Printer {
+            matcher,
+            config,
+            display,
+            with_path,
+            numbered,
+            style,
+        }
+    }
+
+    /// Appends `line` to `output`. Unless `matched`, it is printed as it is,
+    /// with nothing replaced, highlighted or pointed at.
+    fn line(&self, output: &mut String, line_number: usize, line: &str, matched: bool) {
+        let config = self.config;
+        let replaced = config
+            .replace
+            .as_ref()
+            .filter(|_| matched)
+            .map(|replacement| self.matcher.replace(line, replacement));
+        let mut text = replaced.as_deref().unwrap_or(line);
+        let mut ranges = if matched
+            && (config.format.is_structured() || self.style.is_some() || config.max_columns > 0)
+        {
+            let mut ranges = self.matcher.find_all(line);
+            if let (Some(replacement), Some(replaced)) = (&config.replace, &replaced) {
+                ranges = format::replaced_ranges(&ranges, replacement, replaced);
+            }
+            if let Some(max) = config.max_per_line {
+                ranges.truncate(max);
+            }
+            ranges
+        } else {
+            Vec::new()
+        };
+        if config.multiline {
+            // The line break ending the last line covered isn't printed.
+            text = text.strip_suffix('\n').unwrap_or(text);
+            for range in &mut ranges {
+                range.end = range.end.min(text.len());
+            }
+        }
+        if let Some(field) = config.output_field.filter(|_| matched) {
+            match format::nth_field(text, field, config.delimiter()) {
+                Some((start, part)) => {
+                    ranges.retain(|range| range.start < start + part.len() || range.is_empty());
+                    for range in &mut ranges {
+                        range.end = range.end.min(start + part.len());
+                    }
+                    ranges = format::shift_ranges(ranges, start);
+                    text = part;
+                }
+                None if config.skip_missing => return,
+                None => {
+                    text = "";
+                    ranges.clear();
+                }
+            }
+        }
+        if config.trim && config.format != OutputFormat::Vimgrep {
+            let trimmed = text.trim_start_matches([' ', '\t']);
+            let cut = text.len() - trimmed.len();
+            text = trimmed;
+            ranges = format::shift_ranges(ranges, cut);
+        }
+        // Vim wants columns of the line as it is in the file.
+        let expanded;
+        if config.tab_width > 0 && config.format != OutputFormat::Vimgrep && text.contains('\t') {
+            (expanded, ranges) = format::expand_tabs(text, config.tab_width, &ranges);
+            text = &expanded;
+        }
+        let mut elided = None;
+        if config.max_columns > 0
+            && config.format == OutputFormat::Text
+            && let Some((cut, more)) = format::column_cut(text, config.max_columns)
+        {
+            if config.max_columns_preview
+                || !matched
+                || ranges.iter().any(|range| range.start < cut)
+            {
+                text = &text[..cut];
+                ranges.retain(|range| range.start < cut);
+                for range in &mut ranges {
+                    range.end = range.end.min(cut);
+                }
+                elided = Some(format!(" [... {more} more characters]"));
+            } else {
+                text = "";
+                ranges.clear();
+                let total = config.max_columns + more;
+                elided = Some(format!("[omitted line of {total} characters]"));
+            }
+        }
+        let highlighted = self
+            .style
+            .as_ref()
+            .filter(|_| matched)
+            .map(|style| style.apply(text, &ranges));
+        let mut text = highlighted.as_deref().unwrap_or(text);
+        let with_notice;
+        if let Some(notice) = elided {
+            with_notice = format!("{text}{notice}");
+            text = &with_notice;
+        }
+        config.format.write(
+            output,
+            &Record {
+                path: &self.display,
+                with_path: self.with_path,
+                numbered: self.numbered,
+                line_number,
+                ranges: &ranges,
+                text,
+                terminator: config.separator(),
+            },
+        );
+    }
+}
+
+/// Searches a single file, returning the text to print for it, with each line
+/// prefixed by the path if `with_path` and by its number if `numbered`.
+fn search_for_output(
+    matcher: &dyn Matcher,
+    config: &Config,
+    input: &Input,
+    with_path: bool,
+    numbered: bool,
+) -> io::Result<FileOutput> {
+    if config.in_place
+        && let Some(replacement) = &config.replace
+    {
+        let (matched_lines, replacements) =
+            in_place::replace(matcher, config, &input.path, replacement)?;
+        return Ok(FileOutput {
+            matched_lines,
+            replacements,
+            ..FileOutput::default()
+        });
+    }
+    let streamed = config.mmap || config.max_count.is_some();
+    let (contents, numbers, masked) = if streamed {
+        let (contents, numbers) = read_matches(matcher, config, input)?;
+        (contents, numbers, None)
+    } else {
+        let (contents, masked) = read_input(input, config)?;
+        (contents, Vec::new(), masked)
+    };
+    let separator = config.separator();
+    // A hit on a replaced sequence isn't in the file, so lines are matched in
+    // the masked copy, whose offsets are the same, and printed from `contents`.
+    let masked = masked.filter(|_| !config.multiline);
+    let matched = masked.as_deref().unwrap_or(&contents);
+    let lines = if streamed {
+        // Only the matching lines were kept.
+        contents.split_terminator(separator).collect()
+    } else {
+        let searched = match &config.line_range {
+            Some(range) => slice_records(matched, range, separator),
+            None => matched,
+        };
+        if config.multiline {
+            multiline_matches(matcher, searched)
+        } else if config.null_data {
+            searched
+                .split_terminator(separator)
+                .filter(|record| matcher.is_match(record))
+                .collect()
+        } else {
+            matcher.search(searched)
+        }
+    };
+    let lines: Vec<&str> = match &masked {
+        Some(masked) => lines
+            .into_iter()
+            .map(|line| {
+                let start = line.as_ptr() as usize - masked.as_ptr() as usize;
+                &contents[start..start + line.len()]
+            })
+            .collect(),
+        None => lines,
+    };
+    let lines = match config.diff {
+        Some(_) if !streamed => without_diff_headers(&contents, lines, separator),
+        _ => lines,
+    };
+    let mut results: Vec<(usize, &str)> = if streamed {
+        numbers.into_iter().zip(lines).collect()
+    } else if config.format.is_structured()
+        || config.header_lines > 0
+        || numbered
+        || config.first_last
+        || config.histogram
+    {
+        number_lines(&contents, lines, separator)
+    } else {
+        lines.into_iter().map(|line| (0, line)).collect()
+    };
+
+    let header: Vec<(usize, &str)> = if config.header_lines == 0 {
+        Vec::new()
+    } else if streamed {
+        // Kept along with the matches.
+        results
+            .iter()
+            .copied()
+            .take_while(|&(number, _)| number <= config.header_lines)
+            .collect()
+    } else {
+        let header = slice_records(&contents, &(1..=config.header_lines), separator);
+        let header: Vec<&str> = match separator {
+            '\n' => header.lines().collect(),
+            _ => header.split_terminator(separator).collect(),
+        };
+        (1..).zip(header).collect()
+    };
+    results.retain(|&(number, _)| number > config.header_lines || config.header_lines == 0);
+    let matched_lines = results.len();
+    let histogram = (config.histogram && matched_lines > 0).then(|| {
+        let total_lines = contents.split_terminator(separator).count();
+        Histogram::new(results.iter().map(|&(number, _)| number), total_lines)
+    });
+
+    let display = display_input(input, config);
+    let prefix = if with_path {
+        format!("{display}:")
+    } else {
+        String::new()
+    };
+
+    let mut output = String::new();
+    let listed = config.invert_files && matched_lines == 0;
+
+    if config.invert_files {
+        if listed {
+            let count = if config.count { ":0" } else { "" };
+            writeln!(output, "{display}{count}").unwrap();
+        }
+        results.clear();
+    } else if config.count_matches {
+        let count: usize = results
+            .iter()
+            .map(|(_, line)| matcher.find_all(line).len())
+            .sum();
+        writeln!(output, "{prefix}{count}").unwrap();
+        results.clear();
+    } else if config.count {
+        if !(with_path && config.no_zero && matched_lines == 0) {
+            writeln!(output, "{prefix}{matched_lines}").unwrap();
+        }
+        results.clear();
+    } else if config.first_last {
+        match (results.first(), results.last()) {
+            (Some((first, _)), Some((last, _))) => {
+                writeln!(
+                    output,
+                    "{display}: first={first} last={last} count={matched_lines}"
+                )
+                .unwrap();
+            }
+            _ if config.include_empty => {
+                writeln!(output, "{display}: first=- last=- count=0").unwrap();
+            }
+            _ => {}
+        }
+        results.clear();
+    } else if config.count_total {
+        results.clear();
+    }
+
+    let printer = Printer::new(matcher, config, display, with_path, numbered);
+    match config.sort {
+        Some(SortOrder::Ascending) => results.sort_by(|a, b| a.1.cmp(b.1)),
+        Some(SortOrder::Descending) => results.sort_by(|a, b| b.1.cmp(a.1)),
+        None => {}
+    }
+
+    if !results.is_empty() {
+        for &(line_number, line) in &header {
+            printer.line(&mut output, line_number, line, false);
+        }
+    }
+    let mut ends = Vec::with_capacity(results.len());
+    for (line_number, line) in results {
+        printer.line(&mut output, line_number, line, true);
+        ends.push(output.len());
+    }
+
+    Ok(FileOutput {
+        text: output,
+        matched_lines,
+        ends,
+        histogram,
+        replacements: 0,
+        listed,
+    })
+}
+
+/// Returns the lines of `contents` covered by each match of `matcher`, which
+/// may run across line breaks, as one slice per match. Each slice includes the
+/// line break ending its last line, if there is one, as a match may too.
+///
+/// Matches that share a line share a slice, which is kept only if `matcher`
+/// matches it as a whole, so that every `--all-of` pattern has to be within
+/// it and no `--not` pattern can be.
+fn multiline_matches<'a>(matcher: &dyn Matcher, contents: &'a str) -> Vec<&'a str> {
+    let mut covered: Vec<Range<usize>> = Vec::new();
+
+    for range in matcher.find_all(contents) {
+        let start = contents[..range.start].rfind('\n').map_or(0, |i| i + 1);
+        // Up to and including the line break ending the last line covered,
+        // which a match ending with a line break already has.
+        let end = if !range.is_empty() && contents[..range.end].ends_with('\n') {
+            range.end
+        } else {
+            contents[range.end..]
+                .find('\n')
+                .map_or(contents.len(), |i| range.end + i + 1)
+        };
+
+        match covered.last_mut() {
+            Some(last) if start < last.end => last.end = last.end.max(end),
+            _ => covered.push(start..end),
+        }
+    }
+
+    covered
+        .into_iter()
+        .map(|range| &contents[range])
+        .filter(|lines| matcher.is_match(lines))
+        .collect()
+}
+
+/// Leaves the file headers of the diff in `contents` out of `lines`, which are
+/// in order and all borrowed from `contents`, as `DiffHeaders` finds them.
+fn without_diff_headers<'a>(
+    contents: &str,
+    mut lines: Vec<&'a str>,
+    separator: char,
+) -> Vec<&'a str> {
+    let mut headers = DiffHeaders::default();
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for record in contents.split_inclusive(separator) {
+        if headers.is_header(without_ending(record, separator)) {
+            starts.push(offset);
+        }
+        offset += record.len();
+    }
+    lines.retain(|line| {
+        let start = line.as_ptr() as usize - contents.as_ptr() as usize;
+        starts.binary_search(&start).is_err()
+    });
+    lines
+}
+
+/// Pairs each of `lines`, which are in order and all borrowed from `contents`,
+/// with its 1-based line number, counting lines as ending with `separator`.
+fn number_lines<'a>(contents: &str, lines: Vec<&'a str>, separator: char) -> Vec<(usize, &'a str)> {
+    let mut number = 1;
+    let mut counted = 0;
+
+    lines
+        .into_iter()
+        .map(|line| {
+            let offset = line.as_ptr() as usize - contents.as_ptr() as usize;
+            number += contents.as_bytes()[counted..offset]
+                .iter()
+                .filter(|&&b| b == separator as u8)
+                .count();
+            counted = offset;
+            (number, line)
+        })
+        .collect()
+}
+
+/// Reads `input` a line at a time, or a record at a time with `null_data`,
+/// returning only the matching lines within the configured line range, along
+/// with their line numbers. The first `header_lines` lines are returned too,
+/// whether they match or not, and don't count towards `max_count`.
+///
+/// Only the current line and the matches are held in memory, whatever the size
+/// of the file, and reading stops after the `max_count`th match.
+fn read_matches(
+    matcher: &dyn Matcher,
+    config: &Config,
+    input: &Input,
+) -> io::Result<(String, Vec<usize>)> {
+    let mut reader = open_input(input, config)?;
+    let range = config.line_range.clone().unwrap_or(1..=usize::MAX);
+    let max_count = config.max_count.unwrap_or(usize::MAX);
+
+    let mut matches = String::new();
+    let mut numbers = Vec::new();
+    let mut found = 0;
+    let separator = config.separator();
+    let mut line = Vec::new();
+    let mut masked = config.seeks_replacement().then(String::new);
+    let mut diff_headers = config.diff.map(|_| DiffHeaders::default());
+    let mut number = 0;
+
+    while found < max_count && number < *range.end() {
+        let Some(text) = read_record(&mut reader, separator, &mut line, masked.as_mut())? else {
+            break;
+        };
+        number += 1;
+        let header = diff_headers
+            .as_mut()
+            .is_some_and(|headers| headers.is_header(text));
+
+        if number <= config.header_lines {
+            matches.push_str(text);
+            matches.push(separator);
+            numbers.push(number);
+        } else if range.contains(&number)
+            && !header
+            && record_matches(matcher, text, masked.as_deref())
+        {
+            matches.push_str(text);
+            matches.push(separator);
+            numbers.push(number);
+            found += 1;
+        }
+    }
+
+    Ok((matches, numbers))
+}
+
+/// Reads the next line from `reader` into `buffer`, or the next record ending
+/// with `separator`, returning it without its ending, or `None` at the end.
+///
+/// A line that isn't valid UTF-8 is decoded lossily, as by `read_input`. Given
+/// `masked`, it is left empty for a valid line, and otherwise set to the line
+/// as `mask_invalid` decodes it, without its ending.
+fn read_record<'a>(
+    reader: &mut dyn BufRead,
+    separator: char,
+    buffer: &'a mut Vec<u8>,
+    masked: Option<&mut String>,
+) -> io::Result<Option<&'a str>> {
+    buffer.clear();
+    if reader.read_until(separator as u8, buffer)? == 0 {
+        return Ok(None);
+    }
+
+    let valid = str::from_utf8(buffer).is_ok();
+    if let Some(masked) = masked {
+        masked.clear();
+        if !valid {
+            masked.push_str(&mask_invalid(buffer));
+            let len = without_ending(masked, separator).len();
+            masked.truncate(len);
+        }
+    }
+    if !valid {
+        *buffer = String::from_utf8_lossy(buffer).into_owned().into_bytes();
+    }
+    let text = str::from_utf8(buffer).expect("lossy decoding produces UTF-8");
+    Ok(Some(without_ending(text, separator)))
+}
+
+/// Returns `record` without the `separator` ending it, or for lines the CRLF.
+fn without_ending(record: &str, separator: char) -> &str {
+    let record = record.strip_suffix(separator).unwrap_or(record);
+    match separator {
+        '\n' => record.strip_suffix('\r').unwrap_or(record),
+        _ => record,
+    }
+}
+
+/// Whether `matcher` matches `text`, a record from `read_record`, judging by
+/// its `masked` copy when it wasn't valid UTF-8.
+fn record_matches(matcher: &dyn Matcher, text: &str, masked: Option<&str>) -> bool {
+    match masked {
+        Some(masked) if !masked.is_empty() => matcher.is_match(masked),
+        _ => matcher.is_match(text),
+    }
+}
+
+/// Searches `files` one after another for `passthru`, writing each line to
+/// `out` as soon as it is read rather than once its file is done.
+fn passthru_files(
+    config: &Config,
+    matcher: &dyn Matcher,
+    files: Files,
+    with_path: bool,
+    headings: bool,
+    out: &mut impl Write,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    let mut headed = false;
+    for input in files.into_inputs() {
+        let left = config
+            .max_total
+            .map_or(usize::MAX, |max| max.saturating_sub(summary.matched_lines));
+        if left == 0 {
+            break;
+        }
+        let heading = headings.then(|| {
+            let gap = if headed { "\n" } else { "" };
+            format!("{gap}{}\n", display_input(&input, config))
+        });
+        let display = display_input(&input, config);
+        let printer = Printer::new(matcher, config, display, with_path && !headings, headings);
+        match passthru_file(&printer, &input, heading.as_deref(), left, out) {
+            Ok((matched_lines, written)) => {
+                summary.matched_lines += matched_lines;
+                headed |= written;
+            }
+            Err(err) => {
+                warn(
+                    config,
+                    &input.path,
+                    &SearchError::io("read", &input.path, err),
+                );
+                summary.had_errors = true;
+                summary.unreadable += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copies every line of `input` to `out` with `printer` as it is read, with
+/// the line ending it had in the file, treating only the matching lines as
+/// matches. `heading` is written ahead of the first line.
+///
+/// Returns how many lines matched, reading no further than the `limit`th
+/// match, and whether anything was written.
+fn passthru_file(
+    printer: &Printer,
+    input: &Input,
+    heading: Option<&str>,
+    limit: usize,
+    out: &mut dyn Write,
+) -> io::Result<(usize, bool)> {
+    let (matcher, config) = (printer.matcher, printer.config);
+    let mut reader = open_input(input, config)?;
+    let separator = config.separator();
+
+    let mut printed = String::new();
+    let mut matched_lines = 0;
+    let mut written = false;
+    let mut line = Vec::new();
+    let mut masked = config.seeks_replacement().then(String::new);
+    let mut diff_headers = config.diff.map(|_| DiffHeaders::default());
+    let mut number = 0;
+
+    while matched_lines < limit {
+        let Some(len) =
+            read_record(&mut reader, separator, &mut line, masked.as_mut())?.map(str::len)
+        else {
+            break;
+        };
+        let record = str::from_utf8(&line).expect("read_record decodes each record");
+        let (text, ending) = record.split_at(len);
+        number += 1;
+        let header = diff_headers
+            .as_mut()
+            .is_some_and(|headers| headers.is_header(text));
+        let matched = !header && record_matches(matcher, text, masked.as_deref());
+        matched_lines += usize::from(matched);
+        if config.count_total {
+            continue;
+        }
+
+        printed.clear();
+        printer.line(&mut printed, number, text, matched);
+        // A CRLF or a missing final line break is kept as it was.
+        if config.format == OutputFormat::Text && printed.ends_with(separator) {
+            printed.pop();
+            printed.push_str(ending);
+        }
+        if printed.is_empty() {
+            continue;
+        }
+        if let Some(heading) = heading.filter(|_| !written) {
+            out.write_all(heading.as_bytes())?;
+        }
+        written = true;
+        out.write_all(printed.as_bytes())?;
+    }
+
+    Ok((matched_lines, written))
+}
+
+/// Searches `files` on `threads` worker threads, passing each result to `emit`
+/// on the calling thread.
+///
+/// When `ordered`, results are emitted in the order of `files` regardless of
+/// which search finishes first; otherwise as soon as each one is available.
+/// Workers take the next file whenever they are free, so `files` can still be
+/// producing them while the first are searched. Once `stop` is set they take
+/// no more, and `files` is dropped as soon as they are done.
+fn search_parallel<F, T, S, E>(
+    files: impl Iterator<Item = F> + Send,
+    threads: usize,
+    ordered: bool,
+    stop: &AtomicBool,
+    search: S,
+    mut emit: E,
+) -> io::Result<()>
+where
+    F: Send,
+    T: Send,
+    S: Fn(&F) -> io::Result<T> + Sync,
+    E: FnMut(&F, io::Result<T>) -> io::Result<()>,
+{
+    let next = Mutex::new(files.enumerate());
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (next, search) = (&next, &search);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let Some((index, path)) = next.lock().unwrap().next() else {
+                        break;
+                    };
+                    let result = search(&path);
+                    // Stop early if the receiving side has given up.
+                    if sender.send((index, path, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut pending = BTreeMap::new();
+        let mut next_to_emit = 0;
+
+        for (index, path, result) in receiver {
+            if !ordered {
+                emit(&path, result)?;
+                continue;
+            }
+
+            pending.insert(index, (path, result));
+            while let Some((path, result)) = pending.remove(&next_to_emit) {
+                emit(&path, result)?;
+                next_to_emit += 1;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env};
+
+    use super::*;
+
+    fn config(query: &str, file_paths: Vec<String>) -> Config {
+        Config {
+            query: query.to_string(),
+            file_paths: file_paths.into_iter().map(PathBuf::from).collect(),
+            ..Config::default()
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("lilgrep-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn temp_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("lilgrep-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn case_sensitive() {
+        let query = "duct";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+
+        assert_eq!(vec!["safe, fast, productive."], search(query, contents));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let query = "rUsT";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(
+            vec!["Rust:", "Trust me."],
+            search_case_insensitive(query, contents)
+        );
+    }
+
+    #[test]
+    fn sorted_output() {
+        let contents = "\
+pear
+apple
+fig
+banana";
+        let mut results = search("", contents);
+
+        sort_lines(&mut results, SortOrder::Ascending);
+        assert_eq!(vec!["apple", "banana", "fig", "pear"], results);
+
+        sort_lines(&mut results, SortOrder::Descending);
+        assert_eq!(vec!["pear", "fig", "banana", "apple"], results);
+    }
+
+    #[test]
+    fn line_range_restricts_search() {
+        let contents = "\
+match one
+match two
+match three
+match four";
+
+        assert_eq!(
+            vec!["match two", "match three"],
+            search("match", slice_lines(contents, &(2..=3)))
+        );
+        assert!(search("match", slice_lines(contents, &(5..=9))).is_empty());
+    }
+
+    #[test]
+    fn size_parsing() {
+        assert_eq!(Ok(500), parse_size("500"));
+        assert_eq!(Ok(500 * 1024), parse_size("500K"));
+        assert_eq!(Ok(1 << 20), parse_size("1m"));
+        assert_eq!(Ok(3 << 30), parse_size("3G"));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("2T").is_err());
+        assert_eq!(
+            Err("Size is too large"),
+            parse_size("18446744073709551615K")
+        );
+    }
+
+    #[test]
+    fn line_range_parsing() {
+        assert_eq!(Ok(1000..=2000), parse_line_range("1000:2000"));
+        assert_eq!(Ok(3..=3), parse_line_range("3:3"));
+        assert!(parse_line_range("0:5").is_err());
+        assert!(parse_line_range("9:5").is_err());
+        assert!(parse_line_range("5").is_err());
+        assert!(parse_line_range("a:5").is_err());
+        assert!(parse_line_range("5:b").is_err());
+        assert!(parse_line_range("3:0").is_err());
+    }
+
+    #[test]
+    fn line_ranges_may_be_open_ended() {
+        assert_eq!(Ok(100..=usize::MAX), parse_line_range("100:"));
+        assert_eq!(Ok(1..=50), parse_line_range(":50"));
+        assert_eq!(Ok(1..=usize::MAX), parse_line_range(":"));
+
+        let contents: String = (1..=6).map(|i| format!("line {i}\n")).collect();
+        let path = temp_file("open-range.txt", &contents);
+        for mmap in [false, true] {
+            let output = |range: &str| {
+                let args = ["--line-range", range, "line", path.as_str()].into_iter();
+                let mut config = Config::build(
+                    ["lilgrep", "--no-config"].into_iter().chain(args),
+                    HashMap::new(),
+                )
+                .unwrap();
+                config.mmap = mmap;
+                let mut out = Vec::new();
+                run_to(&config, &mut out).unwrap();
+                String::from_utf8(out).unwrap()
+            };
+            assert_eq!(output("5:"), "line 5\nline 6\n");
+            assert_eq!(output(":2"), "line 1\nline 2\n");
+            assert_eq!(output("7:"), "");
+        }
+        assert_eq!(
+            slice_lines(&contents, &(5..=usize::MAX)),
+            "line 5\nline 6\n"
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn case_insensitive_matches_allocating_search() {
+        fn allocating<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+            let query = query.to_lowercase();
+            contents
+                .lines()
+                .filter(|line| line.to_lowercase().contains(&query))
+                .collect()
+        }
+
+        let contents = "\
+Rust:
+TRUST ME
+straße
+STRASSE
+\u{212A}elvin
+\u{130}stanbul
+ΣΊΣΥΦΟΣ
+plain ascii line
+";
+        let queries = [
+            "",
+            "rust",
+            "RUST",
+            "t m",
+            "straße",
+            "ss",
+            "k",
+            "kelvin",
+            "i",
+            "σ",
+            "σίσυφος",
+            "é",
+        ];
+
+        for query in queries {
+            assert_eq!(
+                allocating(query, contents),
+                search_case_insensitive(query, contents),
+                "query {query:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unreadable_file_is_skipped() {
+        let readable = temp_file("readable.txt", "needle\n");
+        let missing = temp_file("missing.txt", "");
+        fs::remove_file(&missing).unwrap();
+
+        let summary = run(config("needle", vec![readable.clone(), missing])).unwrap();
+        assert!(summary.had_errors);
+
+        let summary = run(config("needle", vec![readable.clone()])).unwrap();
+        assert!(!summary.had_errors);
+
+        fs::remove_file(readable).unwrap();
+    }
+
+    #[test]
+    fn whole_text_search_matches_line_by_line_search() {
+        let contents = "\
+needle at the start
+no match here
+needle needle twice on one line
+windows line\r
+ends in cr\r\r
+bare\rcarriage return
+last line has a needle";
+        let queries = [
+            "needle",
+            "line",
+            "\r",
+            "cr\r",
+            "e",
+            "twice on",
+            "d\n",
+            "last line has a needle",
+        ];
+
+        for query in queries {
+            let expected: Vec<&str> = contents
+                .lines()
+                .filter(|line| line.contains(query))
+                .collect();
+            assert_eq!(expected, search(query, contents), "query {query:?}");
+
+            let with_newline = format!("{contents}\r\n");
+            let expected: Vec<&str> = with_newline
+                .lines()
+                .filter(|line| line.contains(query))
+                .collect();
+            assert_eq!(expected, search(query, &with_newline), "query {query:?}");
+        }
+    }
+
+    #[test]
+    fn whole_word_ignore_case() {
+        let contents = "\
+Error: disk full
+ERROR in module
+no errors here
+TerrorBird
+error_code=5
+see (error).";
+
+        assert_eq!(
+            vec!["Error: disk full", "ERROR in module", "see (error)."],
+            Searcher::new("error", CaseMode::Insensitive, true).search(contents)
+        );
+        assert_eq!(
+            vec!["see (error)."],
+            Searcher::new("error", CaseMode::Sensitive, true).search(contents)
+        );
+    }
+
+    #[test]
+    fn find_ranges_index_the_original_line() {
+        let searcher = Searcher::new("ab", CaseMode::Sensitive, false);
+        assert_eq!(vec![0..2, 3..5], searcher.find_ranges("ab ab a"));
+
+        let searcher = Searcher::new("AB", CaseMode::Insensitive, false);
+        assert_eq!(vec![0..2, 3..5], searcher.find_ranges("ab Ab a"));
+
+        // The Kelvin sign is three bytes but lowercases to the one-byte `k`.
+        let line = "\u{212A}elvin and kelvin";
+        let searcher = Searcher::new("KELVIN", CaseMode::Insensitive, false);
+        let ranges = searcher.find_ranges(line);
+        assert_eq!(vec![0..8, 13..19], ranges);
+        assert_eq!("\u{212A}elvin", &line[ranges[0].clone()]);
+
+        let searcher = Searcher::new("ab", CaseMode::Sensitive, true);
+        assert_eq!(vec![6..8], searcher.find_ranges("abab, ab"));
+        assert!(searcher.find_ranges("").is_empty());
+    }
+
+    #[test]
+    fn count_matches_counts_occurrences() {
+        let contents = "\
+error ... error
+no problem
+aaaa
+Error and ERROR";
+
+        let searcher = Searcher::new("error", CaseMode::Sensitive, false);
+        assert_eq!(2, searcher.count_matches(contents));
+
+        let searcher = Searcher::new("error", CaseMode::Insensitive, false);
+        assert_eq!(4, searcher.count_matches(contents));
+
+        let searcher = Searcher::new("aa", CaseMode::Sensitive, false);
+        assert_eq!(2, searcher.count_matches(contents));
+
+        let searcher = Searcher::new("AA", CaseMode::Insensitive, false);
+        assert_eq!(2, searcher.count_matches(contents));
+    }
+
+    #[test]
+    fn explain_annotates_origins() {
+        let mut config = config("needle", vec!["missing.txt".to_string()]);
+        config.ignore_case = true;
+        config.origins.insert("ignore_case", Origin::Env);
+        config.line_range = Some(2..=5);
+        config.origins.insert("line_range", Origin::Argv);
+
+        let explained = config.explain().to_string();
+        assert!(explained.contains("query: \"needle\""));
+        assert!(explained.contains("path: missing.txt (not found)"));
+        assert!(explained.contains("ignore_case: true (env LILGREP_IGNORE_CASE or IGNORE_CASE)"));
+        assert!(explained.contains("whole_word: false (default)"));
+        assert!(explained.contains("line_range: 2:5 (argv)"));
+    }
+
+    #[test]
+    fn recursive_output_is_deterministic() {
+        let root = temp_dir("deterministic");
+        for dir in ["a", "b/c", "d"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for (i, file) in [
+            "a/1.txt",
+            "a/2.txt",
+            "b/c/3.txt",
+            "b/4.txt",
+            "d/5.txt",
+            "6.txt",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let contents = "needle\nhay\n".repeat(i + 1);
+            fs::write(root.join(file), contents).unwrap();
+        }
+
+        let search = |threads| {
+            let mut config = config("needle", vec![root.to_string_lossy().into_owned()]);
+            config.recursive = true;
+            config.threads = threads;
+
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let first = search(4);
+        assert_eq!(first, search(4));
+        assert_eq!(first, search(1));
+
+        let paths: Vec<&str> = first
+            .lines()
+            .map(|line| line.rsplit_once(':').unwrap().0)
+            .collect();
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!(sorted, paths);
+        assert_eq!(21, paths.len());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_are_not_followed_forever() {
+        use std::os::unix::fs::symlink;
+
+        let root = temp_dir("symlinks");
+        fs::write(root.join("a.txt"), "needle\n").unwrap();
+        symlink(root.join("a.txt"), root.join("link.txt")).unwrap();
+        symlink(&root, root.join("loop")).unwrap();
+
+        let search = |follow| {
+            let mut config = config("needle", vec![root.to_string_lossy().into_owned()]);
+            config.recursive = true;
+            config.follow = follow;
+
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let expected = "a.txt:needle\nlink.txt:needle\n";
+        assert_eq!(expected, search(false));
+        assert_eq!(expected, search(true));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn max_depth_prunes_the_walk() {
+        let root = temp_dir("max-depth");
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        for file in ["top.txt", "sub/middle.txt", "sub/deeper/bottom.txt"] {
+            fs::write(root.join(file), "needle\n").unwrap();
+        }
+
+        let search = |max_depth| {
+            let mut config = config("needle", vec![root.to_string_lossy().into_owned()]);
+            config.recursive = true;
+            config.max_depth = max_depth;
+
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap().lines().count()
+        };
+
+        assert_eq!(1, search(Some(0)));
+        assert_eq!(2, search(Some(1)));
+        assert_eq!(3, search(Some(2)));
+        assert_eq!(3, search(None));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn search_iter_is_lazy() {
+        let contents = "\
+match one
+skip
+match two
+match three";
+
+        let mut matches = search_iter("match", contents);
+        assert_eq!(Some("match one"), matches.next());
+        assert_eq!(Some("match two"), matches.next());
+
+        assert_eq!(
+            vec!["match one"],
+            search_iter("match", contents).take(1).collect::<Vec<_>>()
+        );
+        assert_eq!(4, search_iter("", contents).count());
+        assert_eq!(0, search_iter("one\nskip", contents).count());
+    }
+
+    #[test]
+    fn replace_substitutes_matches_on_matching_lines() {
+        let file = temp_file("replace.txt", "cat and cat\ndog\nCAT\n");
+
+        let mut config = config("cat", vec![file.clone()]);
+        config.replace = Some("bird".to_string());
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!("bird and bird\n", String::from_utf8(out).unwrap());
+
+        config.ignore_case = true;
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!("bird and bird\nbird\n", String::from_utf8(out).unwrap());
+
+        let searcher = Searcher::new("ab", CaseMode::Sensitive, true);
+        assert_eq!("abab [x]", searcher.replace("abab [ab]", "x"));
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn replace_points_at_the_replaced_text() {
+        let file = temp_file("replace-tabs.txt", "aaaaaaaa\tneedle\n");
+        let output = |format: OutputFormat, max_columns: usize, color: bool| {
+            let mut config = config("needle", vec![file.clone()]);
+            config.replace = Some("x".to_string());
+            config.tab_width = 4;
+            config.format = format;
+            config.max_columns = max_columns;
+            config.color = Some(color);
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let csv = output(OutputFormat::Csv, 0, false);
+        assert!(csv.ends_with(&format!("{file},1,13,1,aaaaaaaa    x\n")));
+        assert_eq!(output(OutputFormat::Text, 50, false), "aaaaaaaa    x\n");
+        let vimgrep = output(OutputFormat::Vimgrep, 0, false);
+        assert_eq!(vimgrep, format!("{file}:1:10:aaaaaaaa\tx\n"));
+        // Replaced text isn't highlighted.
+        assert_eq!(output(OutputFormat::Text, 0, true), "aaaaaaaa    x\n");
+
+        assert_eq!(
+            // From `ab - ab`.
+            format::replaced_ranges(&[0..2, 5..7], "xyz", "xyz - xyz"),
+            [0..3, 6..9]
+        );
+        assert!(format::replaced_ranges(&[0..2, 3..4], "xyz", "no").is_empty());
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn count_lists_each_file() {
+        let a = temp_file(
+            "count_a.txt",
+            "needle
+needle needle
+hay
+",
+        );
+        let b = temp_file(
+            "count_b.txt",
+            "hay
+",
+        );
+        let output = |config: &Config| {
+            let mut out = Vec::new();
+            run_to(config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let mut config = config("needle", vec![a.clone()]);
+        config.count = true;
+        assert_eq!("2\n", output(&config));
+
+        config.file_paths.push(b.clone().into());
+        assert_eq!(format!("{a}:2\n{b}:0\n"), output(&config));
+
+        config.no_zero = true;
+        config.count_total = true;
+        assert_eq!(format!("{a}:2\ntotal:2\n"), output(&config));
+
+        config.count = false;
+        assert_eq!("2\n", output(&config));
+
+        fs::remove_file(a).unwrap();
+        fs::remove_file(b).unwrap();
+    }
+
+    #[test]
+    fn build_understands_short_and_attached_flags() {
+        let build = |args: &[&str]| {
+            let args = ["lilgrep"].iter().chain(args).map(|arg| arg.to_string());
+            Config::build_with_defaults(&FileDefaults::default(), args)
+        };
+
+        let config = build(&["-wc", "-j2", "--max-depth=3", "needle", "a.txt"]).unwrap();
+        assert!(config.whole_word && config.count);
+        assert_eq!(config.threads, 2);
+        assert_eq!(config.max_depth, Some(3));
+        assert_eq!(config.file_paths, [Path::new("a.txt")]);
+
+        let config = build(&["-e", "timeout", "a.txt", "-eretry", "b.txt"]).unwrap();
+        assert_eq!(config.queries().collect::<Vec<_>>(), ["timeout", "retry"]);
+        assert_eq!(config.file_paths, [Path::new("a.txt"), Path::new("b.txt")]);
+
+        let err = |args: &[&str]| build(args).err().unwrap();
+        assert_eq!(
+            err(&["--colour", "needle", "a.txt"]),
+            "Unknown flag --colour"
+        );
+        assert_eq!(err(&["-wK", "needle", "a.txt"]), "Unknown flag -K");
+        assert_eq!(err(&["--threads"]), "Didn't get a value for --threads");
+        assert_eq!(
+            err(&["--word=yes", "needle", "a.txt"]),
+            "--word doesn't take a value"
+        );
+    }
+
+    #[test]
+    fn flags_may_follow_positionals() {
+        let build = |args: &[&str]| {
+            let args = ["lilgrep"].iter().chain(args).map(|arg| arg.to_string());
+            Config::build_with_defaults(&FileDefaults::default(), args)
+        };
+
+        let config = build(&["needle", "a.txt", "--ignore-case", "b.txt"]).unwrap();
+        assert!(config.ignore_case);
+        assert_eq!(config.query, "needle");
+        assert_eq!(config.file_paths, [Path::new("a.txt"), Path::new("b.txt")]);
+
+        let config = build(&["needle", "--", "-foo"]).unwrap();
+        assert_eq!(config.file_paths, [Path::new("-foo")]);
+
+        let config = build(&["-i", "--", "--word", "--ignore-case"]).unwrap();
+        assert!(config.ignore_case && !config.whole_word);
+        assert_eq!(config.query, "--word");
+        assert_eq!(config.file_paths, [Path::new("--ignore-case")]);
+
+        assert_eq!(build(&["needle", "-foo"]).err().unwrap(), "Unknown flag -f");
+    }
+
+    #[test]
+    fn name_only_matches_file_names() {
+        let root = temp_dir("name_only");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/Main.rs"), "no match in here\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "main\n").unwrap();
+        fs::write(root.join("main.txt"), "").unwrap();
+
+        let mut config = config("main", vec![root.display().to_string()]);
+        config.recursive = true;
+        config.name_only = true;
+        let output = |config: &Config| {
+            let mut out = Vec::new();
+            run_to(config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!("main.txt\n", output(&config));
+
+        config.ignore_case = true;
+        assert_eq!("main.txt\nsrc/Main.rs\n", output(&config));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn query_and_paths_by_flag() {
+        let build = |args: &[&str]| {
+            let args = ["lilgrep"].iter().chain(args).map(|arg| arg.to_string());
+            Config::build_with_defaults(&FileDefaults::default(), args)
+        };
+
+        let config = build(&["--path", "a.txt", "--query", "needle", "--path=b.txt"]).unwrap();
+        assert_eq!(config.query, "needle");
+        assert_eq!(config.file_paths, [Path::new("a.txt"), Path::new("b.txt")]);
+
+        let config = build(&["needle", "a.txt", "b.txt"]).unwrap();
+        assert_eq!(config.query, "needle");
+        assert_eq!(config.file_paths, [Path::new("a.txt"), Path::new("b.txt")]);
+
+        let config = build(&["needle", "--path", "a.txt"]).unwrap();
+        assert_eq!(config.query, "needle");
+        assert_eq!(config.file_paths, [Path::new("a.txt")]);
+
+        let err = |args: &[&str]| build(args).err().unwrap();
+        assert_eq!(err(&[]), MISSING_QUERY);
+        assert_eq!(err(&["--path", "a.txt"]), MISSING_QUERY);
+        // A lone argument is the query.
+        assert_eq!(err(&["needle"]), "Didn't get a file path");
+        assert_eq!(
+            err(&["needle", "b.txt", "--path", "a.txt"]),
+            "Got b.txt as a file path, but file paths were already given with --path"
+        );
+        assert_eq!(
+            err(&["a.txt", "--query", "needle"]),
+            "Got a.txt as the query, but the query was already given with --query"
+        );
+        assert_eq!(
+            err(&["--query", "needle", "--path", "a.txt", "b.txt"]),
+            "Got b.txt as a file path, but file paths were already given with --path"
+        );
+        assert_eq!(
+            err(&["--query", "needle", "-e", "hay", "a.txt"]),
+            "--query can't be combined with -e"
+        );
+    }
+
+    #[test]
+    fn not_patterns_apply_to_counts() {
+        let file = temp_file("not.txt", "error: deprecation\nerror: disk\nok\n");
+        let mut config = config("error", vec![file.clone()]);
+        config.not_patterns = vec!["deprecation".to_string()];
+
+        let output = |config: &Config| {
+            let mut out = Vec::new();
+            run_to(config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!("error: disk\n", output(&config));
+
+        config.count = true;
+        assert_eq!("1\n", output(&config));
+
+        config.invert = true;
+        assert_eq!("2\n", output(&config));
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn max_count_applies_per_file() {
+        let root = temp_dir("max_count");
+        fs::create_dir_all(&root).unwrap();
+        for (name, contents) in [
+            ("a.txt", "hay\nneedle 1\nneedle 2\n"),
+            ("b.txt", "needle 3\r\nneedle 4\r\n"),
+            ("c.txt", "hay\n"),
+        ] {
+            fs::write(root.join(name), contents).unwrap();
+        }
+
+        let mut config = config("needle", vec![root.display().to_string()]);
+        config.recursive = true;
+        config.max_count = Some(1);
+        let mut out = Vec::new();
+        let summary = run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            "a.txt:needle 1\nb.txt:needle 3\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(summary.matched_lines, 2);
+
+        config.line_range = Some(3..=3);
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!("a.txt:needle 2\n", String::from_utf8(out).unwrap());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn all_ranges_are_leftmost_and_non_overlapping() {
+        assert_eq!(vec![0..2, 2..4], find_all_ranges("aa", "aaaaa", false));
+        assert_eq!(vec![0..3, 3..6], find_all_ranges("abc", "abcabc", false));
+        assert_eq!(vec![4..7], find_all_ranges("end", "the end", false));
+        assert!(find_all_ranges("", "anything", false).is_empty());
+
+        // `İ` lowercases to two characters, three bytes in all.
+        let line = "xİx İ";
+        for range in find_all_ranges("i\u{307}", line, true) {
+            assert!(line.is_char_boundary(range.start) && line.is_char_boundary(range.end));
+            assert_eq!("İ", &line[range]);
+        }
+        assert_eq!(2, find_all_ranges("i\u{307}", line, true).len());
+
+        let spans = search_with_spans("ab", "abab\nno\nxab ab", false);
+        assert_eq!(
+            vec![("abab", vec![0..2, 2..4]), ("xab ab", vec![1..3, 4..6])],
+            spans
+        );
+    }
+
+    #[test]
+    fn streamed_search_of_a_large_file() {
+        let line = "haystack ".repeat(100);
+        let mut contents = String::new();
+        for i in 0..20_000 {
+            contents.push_str(&line);
+            if i % 5_000 == 0 {
+                contents.push_str("needle");
+            }
+            contents.push('\n');
+        }
+        let file = temp_file("streamed.txt", &contents);
+
+        let mut config = config("needle", vec![file.clone()]);
+        config.count = true;
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+
+        config.mmap = true;
+        let mut streamed = Vec::new();
+        run_to(&config, &mut streamed).unwrap();
+        assert_eq!("4\n", String::from_utf8(streamed.clone()).unwrap());
+        assert_eq!(out, streamed);
+
+        config.count = false;
+        config.line_range = Some(5_000..=10_000);
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(format!("{line}needle\n"), String::from_utf8(out).unwrap());
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn compressed_files_are_searched() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/poem.txt.gz");
+        let mut config = config("nobody", vec![path.to_string()]);
+        config.search_compressed = true;
+
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            "I'm nobody! Who are you?\nAre you nobody, too?\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        config.mmap = true;
+        config.count = true;
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!("2\n", String::from_utf8(out).unwrap());
+
+        // Without -z the compressed bytes are searched as they are.
+        config.search_compressed = false;
+        let mut out = Vec::new();
+        let summary = run_to(&config, &mut out).unwrap();
+        assert_eq!("0\n", String::from_utf8(out).unwrap());
+        assert!(!summary.had_errors);
+    }
+
+    #[test]
+    fn csv_rows_for_matching_lines() {
+        let file = temp_file("format.csv.txt", "plain\nneedle, \"quoted\"\tneedle\nhay\n");
+        let mut config = config("needle", vec![file.clone()]);
+        config.format = OutputFormat::Csv;
+
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            format!(
+                "path,line_number,column,match_count,line\n{file},2,1,2,\"needle, \"\"quoted\"\"\tneedle\"\n"
+            ),
+            String::from_utf8(out).unwrap()
+        );
+
+        config.format = OutputFormat::Tsv;
+        config.no_header = true;
+        config.mmap = true;
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            format!("{file}\t2\t1\t2\tneedle, \"quoted\"\\tneedle\n"),
+            String::from_utf8(out).unwrap()
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn match_positions_do_not_overlap() {
+        assert_eq!(vec![(0, 2), (2, 4)], match_positions("aa", "aaaa", false));
+        assert_eq!(vec![(0, 2)], match_positions("aa", "aaa", false));
+        assert_eq!(vec![(1, 4)], match_positions("aba", "xababa", false));
+        assert_eq!(
+            vec![(0, 3), (4, 7)],
+            match_positions("FOO", "foo Foo", true)
+        );
+        assert!(match_positions("FOO", "foo", false).is_empty());
+    }
+
+    #[test]
+    fn tab_width_expands_printed_lines() {
+        let file = temp_file("tabs.txt", "key\tneedle\nno tabs needle\n");
+        let mut config = config("needle", vec![file.clone()]);
+        config.tab_width = 8;
+
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            "key     needle\nno tabs needle\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        config.format = OutputFormat::Csv;
+        config.no_header = true;
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            format!("{file},1,9,1,key     needle\n{file},2,9,1,no tabs needle\n"),
+            String::from_utf8(out).unwrap()
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn multiline_matches_cover_every_line() {
+        let contents = "fn main(\n    a: u8,\n    b: u8,\n) {}\nfn other() {}\n";
+        let file = temp_file("multiline.rs", contents);
+        let output = |patterns: &[&str]| {
+            let mut config = config("", vec![file.clone()]);
+            config.patterns = patterns.iter().map(|pattern| pattern.to_string()).collect();
+            config.multiline = true;
+            config.format = OutputFormat::Csv;
+            config.no_header = true;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            output(&["main(\n    a"]),
+            format!("{file},1,4,1,\"fn main(\n    a: u8,\"\n")
+        );
+        assert_eq!(
+            output(&["u8,\n    b: u8,\n) {}\nfn"]),
+            format!("{file},2,8,1,\"    a: u8,\n    b: u8,\n) {{}}\nfn other() {{}}\"\n")
+        );
+        // Matches sharing a line are printed together, once.
+        assert_eq!(
+            output(&["a: u8,\n    b", ": u8,\n)"]),
+            format!("{file},2,5,2,\"    a: u8,\n    b: u8,\n) {{}}\"\n")
+        );
+        assert_eq!(
+            output(&["u8,\n"]),
+            format!("{file},2,8,1,\"    a: u8,\"\n{file},3,8,1,\"    b: u8,\"\n")
+        );
+
+        let mut config = config("main(\n", vec![file.clone(), file.clone()]);
+        config.multiline = true;
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{file}:fn main(\n{file}:fn main(\n")
+        );
+
+        let err = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            Config::build(args.map(|arg| arg.to_string()), HashMap::new())
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            err(&["-U", "--mmap", "a", "b.txt"]),
+            "-U reads whole files, so it can't be combined with --mmap or -m"
+        );
+        assert_eq!(err(&["-Uv", "a", "b.txt"]), "-U can't be combined with -v");
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn not_flags_exclude_lines_from_the_command_line() {
+        let file = temp_file("foo-bar.txt", "foo\nfoo bar\nFOO BAR\nFoo baz\nbar\n");
+        let output = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            let config = Config::build(args.map(|arg| arg.to_string()), HashMap::new()).unwrap();
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(output(&["foo", &file, "--not", "bar"]), "foo\n");
+        assert_eq!(
+            output(&["-i", "foo", &file, "--not", "bar"]),
+            "foo\nFoo baz\n"
+        );
+        assert_eq!(
+            output(&["-i", "foo", &file, "--not", "bar", "--not=BAZ"]),
+            "foo\n"
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn color_highlights_text_output() {
+        let file = temp_file("color.txt", "one needle\ttwo needle\nnothing\n");
+        let output = |color: Option<bool>, tab_width: usize| {
+            let mut config = config("needle", vec![file.clone()]);
+            config.color = color;
+            config.tab_width = tab_width;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(output(None, 0), "one needle\ttwo needle\n");
+        assert_eq!(output(Some(false), 0), "one needle\ttwo needle\n");
+        assert_eq!(
+            output(Some(true), 0),
+            "one \x1b[1;31mneedle\x1b[0m\ttwo \x1b[1;31mneedle\x1b[0m\n"
+        );
+        assert_eq!(
+            output(Some(true), 4),
+            "one \x1b[1;31mneedle\x1b[0m  two \x1b[1;31mneedle\x1b[0m\n"
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn null_data_splits_records_on_nul() {
+        let contents = "one\nneedle\0two\0needle three\n\0";
+        let file = temp_file("null-data.txt", contents);
+        let output = |config: &Config| {
+            let mut out = Vec::new();
+            run_to(config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let mut config = config("needle", vec![file.clone()]);
+        config.null_data = true;
+        assert_eq!(output(&config), "one\nneedle\0needle three\n\0");
+
+        // The same records, a line at a time.
+        config.mmap = true;
+        assert_eq!(output(&config), "one\nneedle\0needle three\n\0");
+        config.mmap = false;
+
+        config.line_range = Some(2..=3);
+        config.format = OutputFormat::Csv;
+        config.no_header = true;
+        assert_eq!(
+            output(&config),
+            format!("{file},3,1,1,\"needle three\n\"\0")
+        );
+
+        config.null_data = false;
+        config.line_range = None;
+        assert_eq!(
+            output(&config),
+            format!("{file},2,1,2,needle\0two\0needle three\n")
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn with_header_prints_leading_lines_once() {
+        let people = temp_file("people.csv", "id,name\n1,alice\n2,bob\n3,anna\n");
+        let empty = temp_file("staff.csv", "id,name\n4,zed\n");
+        let output = |config: &Config| {
+            let mut out = Vec::new();
+            run_to(config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        // The header matches too, but is only printed as the header.
+        let mut config = config("a", vec![people.clone(), empty.clone()]);
+        config.header_lines = 1;
+        let expected = format!("{people}:id,name\n{people}:1,alice\n{people}:3,anna\n");
+        assert_eq!(output(&config), expected);
+
+        config.max_count = Some(1);
+        assert_eq!(
+            output(&config),
+            format!("{people}:id,name\n{people}:1,alice\n")
+        );
+        config.max_count = None;
+
+        config.count = true;
+        assert_eq!(output(&config), format!("{people}:2\n{empty}:0\n"));
+
+        fs::remove_file(people).unwrap();
+        fs::remove_file(empty).unwrap();
+    }
+
+    #[test]
+    fn passthru_prints_every_line() {
+        let contents = "first\n\nsecond needle\n\tthird\nneedle\n";
+        let file = temp_file("passthru.txt", contents);
+        let mut config = config("needle", vec![file.clone()]);
+        config.passthru = true;
+        let output = |config: &Config| {
+            let mut out = Vec::new();
+            let summary = run_to(config, &mut out).unwrap();
+            (String::from_utf8(out).unwrap(), summary.matched_lines)
+        };
+
+        assert_eq!(output(&config), (contents.to_string(), 2));
+
+        config.mmap = true;
+        assert_eq!(output(&config), (contents.to_string(), 2));
+
+        config.color = Some(true);
+        assert_eq!(
+            output(&config).0,
+            "first\n\nsecond \x1b[1;31mneedle\x1b[0m\n\tthird\n\x1b[1;31mneedle\x1b[0m\n"
+        );
+
+        // Line endings come out as they went in.
+        let contents = "first\r\nneedle\r\n\nlast needle";
+        fs::write(&file, contents).unwrap();
+        config.color = Some(false);
+        assert_eq!(output(&config), (contents.to_string(), 2));
+        config.color = Some(true);
+        assert_eq!(
+            output(&config).0,
+            "first\r\n\x1b[1;31mneedle\x1b[0m\r\n\nlast \x1b[1;31mneedle\x1b[0m"
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn headings_group_lines_by_file() {
+        let first = temp_file("heading-a.txt", "needle one\nnothing\nneedle\none\n");
+        let second = temp_file("heading-b.txt", "no match\n");
+        let third = temp_file("heading-c.txt", "a\nb\nneedle two\n");
+        let output = |heading: Option<bool>| {
+            let mut config = config("needle", vec![first.clone(), second.clone(), third.clone()]);
+            config.heading = heading;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            output(Some(true)),
+            format!("{first}\n1:needle one\n3:needle\n\n{third}\n3:needle two\n")
+        );
+        let classic = format!("{first}:needle one\n{first}:needle\n{third}:needle two\n");
+        assert_eq!(output(Some(false)), classic);
+        assert_eq!(output(None), classic);
+
+        for file in [first, second, third] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn field_restricts_matching_to_one_column() {
+        let csv = temp_file("field.csv", "name,city\nparis,lyon\nlyon,paris\n");
+        let tsv = temp_file("field.tsv", "lyon\tparis, france\nparis\tlyon\n");
+        let output = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            let config = Config::build(args.map(|arg| arg.to_string()), HashMap::new()).unwrap();
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(output(&["--field", "2", "paris", &csv]), "lyon,paris\n");
+        assert_eq!(output(&["--field", "3", "paris", &csv]), "");
+        assert_eq!(
+            output(&["--field=2", "--delimiter", "\t", "paris", &tsv]),
+            "lyon\tparis, france\n"
+        );
+        assert_eq!(
+            output(&["--field=2", "--delimiter", "\\t", "lyon", &tsv]),
+            "paris\tlyon\n"
+        );
+
+        let err = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            Config::build(args.map(|arg| arg.to_string()), HashMap::new())
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            err(&["--field", "0", "a", "b"]),
+            "Fields are numbered from 1"
+        );
+        assert_eq!(
+            err(&["--delimiter", "ab", "a", "b"]),
+            "Delimiter must be a single character"
+        );
+
+        fs::remove_file(csv).unwrap();
+        fs::remove_file(tsv).unwrap();
+    }
+
+    #[test]
+    fn vimgrep_prints_a_record_per_match() {
+        let file = temp_file("vimgrep.txt", "nothing\n\tneedle and needle\n");
+        let mut config = config("needle", vec![file.clone()]);
+        config.format = OutputFormat::Vimgrep;
+        config.tab_width = 8;
+        config.color = Some(true);
+        config.heading = Some(true);
+
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{file}:2:2:\tneedle and needle\n{file}:2:13:\tneedle and needle\n")
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn in_place_rewrites_matching_lines() {
+        let original = "a cat\r\nno match\n\tcat cat\nlast cat";
+        let file = temp_file("in-place.txt", original);
+        let untouched = temp_file("in-place-none.txt", "nothing\n");
+        let args = [
+            "lilgrep",
+            "--no-config",
+            "--replace=dog",
+            "--in-place",
+            "--backup",
+            "cat",
+        ];
+        let args = args.into_iter().chain([file.as_str(), untouched.as_str()]);
+        let config = Config::build(args.map(String::from), HashMap::new()).unwrap();
+
+        let mut out = Vec::new();
+        let summary = run_to(&config, &mut out).unwrap();
+        assert!(out.is_empty());
+        assert_eq!(summary.matched_lines, 3);
+        assert_eq!((summary.files_changed, summary.replacements), (1, 4));
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "a dog\r\nno match\n\tdog dog\nlast dog"
+        );
+        assert_eq!(fs::read_to_string(format!("{file}.bak")).unwrap(), original);
+        assert!(!Path::new(&format!("{untouched}.bak")).exists());
+
+        // The lines -v selects have nothing in them to replace.
+        fs::remove_file(format!("{file}.bak")).unwrap();
+        let inverted = Config {
+            invert: true,
+            ..config
+        };
+        let summary = run_to(&inverted, &mut Vec::new()).unwrap();
+        assert_eq!(summary.matched_lines, 5);
+        assert_eq!((summary.files_changed, summary.replacements), (0, 0));
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "a dog\r\nno match\n\tdog dog\nlast dog"
+        );
+        assert!(!Path::new(&format!("{file}.bak")).exists());
+        assert!(!Path::new(&format!("{untouched}.bak")).exists());
+
+        // Nor does replacing a match with itself change anything to back up.
+        let same = Config {
+            query: "dog".to_string(),
+            replace: Some("dog".to_string()),
+            invert: false,
+            ..inverted
+        };
+        let summary = run_to(&same, &mut Vec::new()).unwrap();
+        assert_eq!(summary.matched_lines, 3);
+        assert_eq!((summary.files_changed, summary.replacements), (0, 0));
+        assert!(!Path::new(&format!("{file}.bak")).exists());
+
+        let err = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            Config::build(args.map(|arg| arg.to_string()), HashMap::new())
+                .err()
+                .unwrap()
+        };
+        assert_eq!(err(&["--in-place", "a", "b"]), "--in-place needs --replace");
+        assert_eq!(err(&["--backup", "a", "b"]), "--backup needs --in-place");
+
+        for path in [file, untouched] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn all_requires_every_pattern() {
+        let contents = "disk full\nfull disk error\nerror\nDISK ERROR\n";
+        assert_eq!(
+            search_all(&["error", "disk"], contents),
+            ["full disk error"]
+        );
+        assert_eq!(search_all(&[], "a\nb"), ["a", "b"]);
+
+        let file = temp_file("all.txt", contents);
+        let args = [
+            "lilgrep",
+            "--no-config",
+            "--all",
+            "-i",
+            "-e",
+            "error",
+            "-e",
+            "disk",
+        ];
+        let config = Config::build(
+            args.into_iter().chain([file.as_str()]).map(String::from),
+            HashMap::new(),
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "full disk error\nDISK ERROR\n"
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn any_matches_each_listed_term() {
+        let contents = "disk full\nall quiet\nTimeout reached\nretrying\n";
+        assert_eq!(
+            search_any(&["disk", "", "retry"], contents),
+            ["disk full", "retrying"]
+        );
+
+        let file = temp_file("any.txt", contents);
+        let build = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            Config::build(args.map(|a| a.to_string()), HashMap::new())
+        };
+        let config = build(&["-i", "--any", "disk,timeout,,retry,", &file]).unwrap();
+        assert_eq!(config.patterns, ["disk", "timeout", "retry"]);
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "disk full\nTimeout reached\nretrying\n"
+        );
+        assert_eq!(
+            build(&["--any", ",", &file]).err().unwrap(),
+            "--any needs at least one term, but got \",\""
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn max_total_stops_files_still_being_found() {
+        let file = temp_file("max-total-found.txt", "needle\n");
+        let mut config = config("needle", vec![file.clone()]);
+        config.max_total = Some(1);
+        config.threads = 4;
+        config.file_sort = FileSort::None;
+        let matcher = Patterns::new(&config).unwrap();
+
+        // Stands in for a walk, which stops once a path can't be sent.
+        let (sender, receiver) = mpsc::sync_channel(FOUND_CAPACITY);
+        let total = 100 * FOUND_CAPACITY;
+        thread::scope(|scope| {
+            let walk = scope.spawn(move || {
+                (0..total)
+                    .take_while(|_| sender.send(PathBuf::from(&file).into()).is_ok())
+                    .count()
+            });
+            let mut summary = Summary::default();
+            let found = Files::Found(receiver);
+            search_files(&config, &matcher, found, &mut io::sink(), &mut summary).unwrap();
+            assert_eq!(summary.matched_lines, 1);
+            assert!(walk.join().unwrap() < total);
+        });
+    }
+
+    #[test]
+    fn max_total_caps_matches_across_files() {
+        let dir = temp_dir("max-total");
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(
+                dir.join(name),
+                format!("{name} needle 1\nno\n{name} needle 2\n"),
+            )
+            .unwrap();
+        }
+        let output = |threads: usize, max_total: usize, count_total: bool| {
+            let mut config = config("needle", vec![dir.display().to_string()]);
+            config.recursive = true;
+            config.threads = threads;
+            config.max_total = Some(max_total);
+            config.count_total = count_total;
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            (String::from_utf8(out).unwrap(), summary.matched_lines)
+        };
+
+        let expected = "a.txt:a.txt needle 1\na.txt:a.txt needle 2\nb.txt:b.txt needle 1\n";
+        assert_eq!(output(1, 3, false), (expected.to_string(), 3));
+        assert_eq!(output(4, 3, false), (expected.to_string(), 3));
+        assert_eq!(output(1, 0, false), (String::new(), 0));
+        assert_eq!(output(4, 5, true), ("5\n".to_string(), 5));
+        assert_eq!(output(1, 100, false).1, 6);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn paths_are_shown_relative_to_the_search_root() {
+        let root = temp_dir("relative");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "needle\n").unwrap();
+        fs::write(root.join("top.txt"), "needle\nneedle\n").unwrap();
+
+        let output = |root: &str, full_paths: bool, count: bool| {
+            let mut config = config("needle", vec![root.to_string()]);
+            config.recursive = true;
+            config.full_paths = full_paths;
+            config.count = count;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let absolute = root.display().to_string();
+        assert_eq!(
+            output(&absolute, false, false),
+            "src/lib.rs:needle\ntop.txt:needle\ntop.txt:needle\n"
+        );
+        assert_eq!(output(&absolute, false, true), "src/lib.rs:1\ntop.txt:2\n");
+        assert_eq!(
+            output(&absolute, true, true),
+            format!("{absolute}/src/lib.rs:1\n{absolute}/top.txt:2\n")
+        );
+
+        let config = config("needle", vec![".".to_string()]);
+        assert_eq!(
+            display_path(Path::new("./src/lib.rs"), &config),
+            "src/lib.rs"
+        );
+        assert_eq!(display_path(Path::new("src/lib.rs"), &config), "src/lib.rs");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn parallel_walk_finds_the_same_files() {
+        let root = temp_dir("parallel-walk");
+        for dir in ["a/b/c", "a/d", "e"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "1.txt",
+            "a/2.txt",
+            "a/b/3.txt",
+            "a/b/c/4.txt",
+            "a/d/5.txt",
+            "e/6.txt",
+        ] {
+            fs::write(root.join(file), "needle\n").unwrap();
+        }
+
+        let search = |threads, max_depth, file_sort| {
+            let mut config = config("needle", vec![root.display().to_string()]);
+            config.recursive = true;
+            config.threads = threads;
+            config.max_depth = max_depth;
+            config.file_sort = file_sort;
+            config.count = true;
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            let mut lines: Vec<String> = String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect();
+            lines.sort();
+            (lines, summary.matched_lines)
+        };
+
+        for max_depth in [None, Some(0), Some(1)] {
+            let sequential = search(1, max_depth, FileSort::Path);
+            assert_eq!(search(4, max_depth, FileSort::Path), sequential);
+            assert_eq!(search(4, max_depth, FileSort::None), sequential);
+        }
+        assert_eq!(search(4, Some(1), FileSort::None).1, 3);
+        assert_eq!(search(4, None, FileSort::None).1, 6);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn named_paths_keep_their_order() {
+        let root = temp_dir("named-order");
+        for file in ["b.txt", "a.txt", "dir/z.txt", "dir/y.txt"] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "needle\n").unwrap();
+        }
+        let paths = ["b.txt", "dir", "a.txt"].map(|path| root.join(path).display().to_string());
+        let mut config = config("needle", paths.to_vec());
+        config.recursive = true;
+        config.count = true;
+        for threads in [1, 4] {
+            config.threads = threads;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            let order: Vec<_> = String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .map(|line| line.rsplit(['/', '\\']).next().unwrap().to_string())
+                .collect();
+            assert_eq!(order, ["b.txt:1", "y.txt:1", "z.txt:1", "a.txt:1"]);
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn first_last_reports_the_span_of_matches() {
+        let spread = temp_file(
+            "first-last.log",
+            "start\nerror one\nok\nok\nerror two\nok\nerror three\nend\n",
+        );
+        let clean = temp_file("first-quiet.log", "ok\nok\n");
+        let output = |include_empty: bool| {
+            let mut config = config("error", vec![spread.clone(), clean.clone()]);
+            config.first_last = true;
+            config.include_empty = include_empty;
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            (String::from_utf8(out).unwrap(), summary.matched_lines)
+        };
+
+        let found = format!("{spread}: first=2 last=7 count=3\n");
+        assert_eq!(output(false), (found.clone(), 3));
+        assert_eq!(
+            output(true),
+            (format!("{found}{clean}: first=- last=- count=0\n"), 3)
+        );
+
+        let err = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            Config::build(args.map(|arg| arg.to_string()), HashMap::new())
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            err(&["--include-empty", "error", "a.log"]),
+            "--include-empty needs --first-last"
+        );
+        assert!(err(&["--first-last", "-c", "error", "a.log"]).starts_with("--first-last can't"));
+
+        fs::remove_file(spread).unwrap();
+        fs::remove_file(clean).unwrap();
+    }
+
+    #[test]
+    fn ignore_case_is_read_from_either_env_var() {
+        let build = |vars: &[&str], args: &[&str]| {
+            let args = ["lilgrep"].iter().chain(args).map(|arg| arg.to_string());
+            let env: HashMap<String, String> = vars
+                .iter()
+                .map(|var| (var.to_string(), String::new()))
+                .collect();
+            Config::build_with_env(&FileDefaults::default(), args, env).unwrap()
+        };
+
+        assert!(!build(&[], &["needle", "a.txt"]).ignore_case);
+        assert!(!build(&["OTHER"], &["needle", "a.txt"]).ignore_case);
+        for var in IGNORE_CASE_VARS {
+            let config = build(&[var], &["needle", "a.txt"]);
+            assert!(config.ignore_case);
+            assert_eq!(config.origin("ignore_case"), Origin::Env);
+        }
+
+        let config = build(&["IGNORE_CASE"], &["-i", "needle", "a.txt"]);
+        assert_eq!(config.origin("ignore_case"), Origin::Argv);
+    }
+
+    #[test]
+    fn build_reads_the_config_file_named_by_env() {
+        let file = temp_file("env-config.toml", "whole_word = true\n");
+        let env = |vars: &[(&str, &str)]| -> HashMap<String, String> {
+            vars.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let build = |env: &HashMap<String, String>, args: &[&str]| {
+            let args = ["lilgrep"].iter().chain(args).map(|arg| arg.to_string());
+            Config::build(args, env)
+        };
+
+        let named = env(&[("LILGREP_CONFIG", &file)]);
+        let config = build(&named, &["needle", "a.txt"]).unwrap();
+        assert!(config.whole_word);
+        assert_eq!(config.origin("whole_word"), Origin::File);
+        assert!(!config.ignore_case);
+        assert!(
+            !build(&named, &["--no-config", "needle", "a.txt"])
+                .unwrap()
+                .whole_word
+        );
+
+        let missing = env(&[("LILGREP_CONFIG", "/nonexistent/lilgrep.toml")]);
+        assert!(build(&missing, &["needle", "a.txt"]).is_err());
+
+        let home = env(&[("HOME", "/nonexistent"), ("IGNORE_CASE", "")]);
+        let config = build(&home, &["needle", "a.txt"]).unwrap();
+        assert!(config.ignore_case && !config.whole_word);
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn soundex_matches_words_that_sound_alike() {
+        let contents = "Robert Smith\nRichard Smith\nsigned: rupert.\n";
+        assert_eq!(
+            search_soundex("Robert", contents),
+            ["Robert Smith", "signed: rupert."]
+        );
+        assert!(search_soundex("--", contents).is_empty());
+
+        let file = temp_file("soundex.txt", contents);
+        let mut config = config("Rubert", vec![file.clone()]);
+        config.soundex = true;
+        config.color = Some(true);
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        let style = Style::ansi();
+        let (on, off) = (style.before, style.after);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{on}Robert{off} Smith\nsigned: {on}rupert{off}.\n")
+        );
+
+        let err = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            Config::build(args.map(|arg| arg.to_string()), HashMap::new())
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            err(&["--soundex", "--fuzzy", "1", "Robert", "a.txt"]),
+            "--soundex can't be combined with --fuzzy or -w"
+        );
+        assert_eq!(
+            err(&["--soundex", "42", "a.txt"]),
+            "--soundex needs a word with letters, but got \"42\""
+        );
+        let config = Config {
+            query: "42".to_string(),
+            soundex: true,
+            ..config
+        };
+        assert_eq!(
+            run_to(&config, io::sink()).unwrap_err().to_string(),
+            "--soundex needs a word with letters, but got \"42\""
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn overlong_fuzzy_queries_are_an_error() {
+        let message = format!(
+            "--fuzzy only supports queries of up to {} characters",
+            fuzzy::MAX_LEN
+        );
+        let mut config = config(&"a".repeat(fuzzy::MAX_LEN + 1), vec!["a.txt".to_string()]);
+        config.fuzzy = Some(1);
+        assert_eq!(
+            run_to(&config, io::sink()).unwrap_err().to_string(),
+            message
+        );
+
+        // Decomposed, each `é` is two characters.
+        let query = "é".repeat(fuzzy::MAX_LEN / 2 + 1);
+        let args = [
+            "lilgrep",
+            "--no-config",
+            "--fuzzy",
+            "1",
+            "--normalize",
+            "nfd",
+            &query,
+            "a.txt",
+        ];
+        let err = Config::build(args.iter().map(|arg| arg.to_string()), HashMap::new());
+        assert_eq!(err.err().unwrap(), message);
+    }
+
+    #[test]
+    fn files_that_are_not_utf8_are_still_searched() {
+        let bytes = b"\xff\xfe binary header\nplain ascii needle\n\xc3(needle\nnothing\n";
+        assert_eq!(
+            search_bytes(b"needle", bytes),
+            [&b"plain ascii needle"[..], b"\xc3(needle"]
+        );
+
+        let path = env::temp_dir().join(format!("lilgrep-{}-binary.txt", std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        for mmap in [false, true] {
+            let mut config = config("needle", vec![path.display().to_string()]);
+            config.mmap = mmap;
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                "plain ascii needle\n\u{fffd}(needle\n"
+            );
+            assert!(!summary.had_errors);
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn trim_strips_indentation_and_keeps_highlights() {
+        let file = temp_file(
+            "trim.rs",
+            "fn main() {\n \t  let needle = 1;\n\t\tneedle();\n}\n",
+        );
+        let output = |format: OutputFormat, color: bool| {
+            let mut config = config("needle", vec![file.clone()]);
+            config.trim = true;
+            config.format = format;
+            config.color = Some(color);
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let style = Style::ansi();
+        let (on, off) = (style.before, style.after);
+        assert_eq!(
+            output(OutputFormat::Text, true),
+            format!("let {on}needle{off} = 1;\n{on}needle{off}();\n")
+        );
+        let csv = output(OutputFormat::Csv, false);
+        assert!(csv.contains(&format!(
+            "{file},2,5,1,let needle = 1;\n{file},3,1,1,needle();\n"
+        )));
+        let vimgrep = output(OutputFormat::Vimgrep, false);
+        assert!(vimgrep.contains(":2:9: \t  let needle = 1;\n"));
+
+        fs::remove_file(file).unwrap();
     }
 
     #[test]
-    fn case_insensitive() {
-        let query = "rUsT";
-        let contents = "\
-Rust:
-safe, fast, productive.
-Pick three.
-Trust me.";
+    fn mark_surrounds_every_match_on_a_line() {
+        let file = temp_file("mark.txt", "needle, needle and needle\nnothing\n");
+        let output = |mark: Style, color: bool| {
+            let mut config = config("needle", vec![file.clone()]);
+            config.mark = Some(mark);
+            config.color = Some(color);
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
 
         assert_eq!(
-            vec!["Rust:", "Trust me."],
-            search_case_insensitive(query, contents)
+            output(default_mark(), false),
+            "[needle], [needle] and [needle]\n"
+        );
+        assert_eq!(
+            output(Style::new("<<", ">>"), false),
+            "<<needle>>, <<needle>> and <<needle>>\n"
+        );
+        let style = Style::ansi();
+        let (on, off) = (style.before, style.after);
+        assert_eq!(
+            output(default_mark(), true).lines().next().unwrap(),
+            format!("[{on}needle{off}], [{on}needle{off}] and [{on}needle{off}]")
+        );
+
+        let args = ["lilgrep", "--no-config", "--mark-end", "}", "x", "a.txt"];
+        let config =
+            Config::build(args.into_iter().map(|arg| arg.to_string()), HashMap::new()).unwrap();
+        assert_eq!(config.mark, Some(Style::new("[", "}")));
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn max_columns_cuts_long_lines() {
+        let file = temp_file(
+            "max-columns.txt",
+            "short needle\nnéédle néédle and then quite a lot more\nfar off, the needle\n",
+        );
+        let output = |query: &str, preview: bool, color: bool| {
+            let mut config = config(query, vec![file.clone()]);
+            config.max_columns = 12;
+            config.max_columns_preview = preview;
+            config.color = Some(color);
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            output("needle", false, false),
+            "short needle\n[omitted line of 19 characters]\n"
+        );
+        assert_eq!(
+            output("needle", true, false),
+            "short needle\nfar off, the [... 7 more characters]\n"
+        );
+        // Columns are characters, not bytes, and a match across the cut is
+        // highlighted up to it.
+        let style = Style::ansi();
+        let (on, off) = (style.before, style.after);
+        assert_eq!(
+            output("néédle", false, true),
+            format!("{on}néédle{off} {on}néédl{off} [... 27 more characters]\n")
+        );
+
+        let args = [
+            "lilgrep",
+            "--no-config",
+            "--max-columns-preview",
+            "x",
+            "a.txt",
+        ];
+        let err = Config::build(args.into_iter().map(|arg| arg.to_string()), HashMap::new())
+            .err()
+            .unwrap();
+        assert_eq!(err, "--max-columns-preview needs --max-columns");
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn count_counts_lines_and_count_matches_occurrences() {
+        let searcher = Searcher::new("needle", CaseMode::Sensitive, false);
+        assert_eq!(searcher.count_lines("needle and needle\n"), 1);
+        assert_eq!(searcher.count_matches("needle and needle\n"), 2);
+
+        let twice = temp_file("count-twice.txt", "needle and needle\nhay\n");
+        let once = temp_file("count-once.txt", "hay\nneedle\n");
+        let output = |count_matches: bool| {
+            let mut config = config("needle", vec![twice.clone(), once.clone()]);
+            config.count = !count_matches;
+            config.count_matches = count_matches;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(output(false), format!("{twice}:1\n{once}:1\n"));
+        assert_eq!(output(true), format!("{twice}:2\n{once}:1\n"));
+
+        fs::remove_file(twice).unwrap();
+        fs::remove_file(once).unwrap();
+    }
+
+    #[test]
+    fn write_keeps_line_endings_and_permissions() {
+        let crlf = temp_file("write-crlf.txt", "one cat\r\ntwo\r\ncat cat\r\n");
+        let unterminated = temp_file("write-unterminated.txt", "cat\nno newline cat");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&crlf, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+        let args = [
+            "lilgrep",
+            "--no-config",
+            "--replace",
+            "dog",
+            "--write",
+            "cat",
+        ];
+        let args = args
+            .into_iter()
+            .chain([crlf.as_str(), unterminated.as_str()]);
+        let config = Config::build(args.map(String::from), HashMap::new()).unwrap();
+
+        let summary = run_to(&config, &mut Vec::new()).unwrap();
+        assert_eq!((summary.files_changed, summary.replacements), (2, 5));
+        assert_eq!(
+            fs::read_to_string(&crlf).unwrap(),
+            "one dog\r\ntwo\r\ndog dog\r\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&unterminated).unwrap(),
+            "dog\nno newline dog"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&crlf).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+
+        let args = [
+            "lilgrep",
+            "--no-config",
+            "--replace",
+            "dog",
+            "--write",
+            "cat",
+            "-",
+        ];
+        let err = Config::build(args.into_iter().map(String::from), HashMap::new())
+            .err()
+            .unwrap();
+        assert_eq!(err, "--in-place can't be used when reading standard input");
+
+        fs::remove_file(crlf).unwrap();
+        fs::remove_file(unterminated).unwrap();
+    }
+
+    #[test]
+    fn search_lines_takes_a_slice() {
+        let owned = [String::from("one needle"), String::from("hay")];
+        let lines = [owned[0].as_str(), owned[1].as_str(), "needle\nneedle", ""];
+        assert_eq!(
+            search_lines("needle", &lines),
+            ["one needle", "needle\nneedle"]
+        );
+        assert_eq!(search_lines("", &lines), lines);
+        assert!(search_lines("Needle", &lines).is_empty());
+    }
+
+    #[test]
+    fn io_errors_name_the_path() {
+        // What `search_files` warns about a file it couldn't search.
+        let search = |path: &Path| {
+            let config = config("needle", vec![path.display().to_string()]);
+            let summary = run_to(&config, io::sink()).unwrap();
+            assert!(summary.had_errors);
+            assert_eq!(summary.unreadable, 1);
+
+            let matcher = Patterns::new(&config).unwrap();
+            let input = path.to_path_buf().into();
+            let err = search_input(&matcher, &config, &input, false, false)
+                .err()
+                .unwrap();
+            warning(&config, path, &err).unwrap()
+        };
+
+        let missing = env::temp_dir().join("lilgrep-missing").join("app.log");
+        let err = search(&missing);
+        assert!(err.starts_with(&format!(
+            "warning: failed to read '{}': ",
+            missing.display()
+        )));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let dir = temp_dir("locked");
+            let file = dir.join("app.log");
+            fs::write(&file, "needle\n").unwrap();
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o000)).unwrap();
+            // Permissions don't stop root, so there may be nothing to see.
+            if fs::read_dir(&dir).is_err() {
+                let err = search(&file);
+                assert!(
+                    err.starts_with(&format!("warning: failed to read '{}': ", file.display()))
+                );
+                assert!(err.contains("ermission denied"));
+            }
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn max_filesize_skips_large_files() {
+        let root = temp_dir("max-filesize");
+        fs::write(
+            root.join("big.map"),
+            format!("needle{}\n", " ".repeat(2048)),
+        )
+        .unwrap();
+        fs::write(root.join("small.js"), "needle\n").unwrap();
+        let mut config = config("needle", vec![root.display().to_string()]);
+        config.recursive = true;
+        config.count = true;
+        config.max_filesize = Some(1024);
+        for (threads, sort) in [
+            (1, FileSort::Path),
+            (4, FileSort::Path),
+            (4, FileSort::None),
+        ] {
+            config.threads = threads;
+            config.file_sort = sort;
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert_eq!(summary.matched_lines, 1);
+            // Not even counted as having no matches.
+            assert!(
+                out.ends_with("small.js:1\n") && !out.contains("big.map"),
+                "{out}"
+            );
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn owned_matches_outlive_the_contents() {
+        fn read_matches(path: &Path) -> Vec<OwnedMatch> {
+            let options = SearchOptions {
+                case: CaseMode::Insensitive,
+                whole_word: true,
+            };
+            search_file(path, "needle", &options).unwrap()
+        }
+
+        let file = temp_file("owned.txt", "Needle\nneedles\nhay\na needle\n");
+        let matches = thread::spawn({
+            let file = file.clone();
+            move || read_matches(Path::new(&file))
+        })
+        .join()
+        .unwrap();
+        let lines: Vec<_> = matches
+            .iter()
+            .map(|found| (found.line_number, found.line.as_str()))
+            .collect();
+        assert_eq!(lines, [(1, "Needle"), (4, "a needle")]);
+
+        let searcher = Searcher::new("needle", CaseMode::Insensitive, true);
+        let borrowed = searcher.search_matches("Needle\nneedles\nhay\na needle\n");
+        assert_eq!(
+            borrowed.iter().map(Match::to_owned).collect::<Vec<_>>(),
+            matches
+        );
+
+        let missing = Path::new(&file).with_extension("gone");
+        let err = search_file(&missing, "needle", &SearchOptions::default()).unwrap_err();
+        assert!(err.to_string().contains(&missing.display().to_string()));
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn overlapping_ranges_start_at_every_position() {
+        let pairs = |ranges: Vec<Range<usize>>| -> Vec<(usize, usize)> {
+            ranges
+                .into_iter()
+                .map(|range| (range.start, range.end))
+                .collect()
+        };
+        let both = |query: &str, line: &str, ignore_case: bool| {
+            (
+                pairs(find_all_ranges(query, line, ignore_case)),
+                pairs(find_overlapping_ranges(query, line, ignore_case)),
+            )
+        };
+
+        assert_eq!(
+            both("aba", "ababab", false),
+            (vec![(0, 3)], vec![(0, 3), (2, 5)])
+        );
+        assert_eq!(
+            both("aa", "aaaa", false),
+            (vec![(0, 2), (2, 4)], vec![(0, 2), (1, 3), (2, 4)])
+        );
+        assert_eq!(
+            both("a", "aaa", false),
+            (vec![(0, 1), (1, 2), (2, 3)], vec![(0, 1), (1, 2), (2, 3)])
+        );
+        assert_eq!(both("abab", "abab", false), (vec![(0, 4)], vec![(0, 4)]));
+        assert_eq!(both("abab", "aba", false), (vec![], vec![]));
+        assert_eq!(both("", "aaa", false), (vec![], vec![]));
+
+        // Folded text follows the same policy, with offsets into the line.
+        assert_eq!(
+            both("ABA", "xAbAbA", true),
+            (vec![(1, 4)], vec![(1, 4), (3, 6)])
+        );
+        assert_eq!(
+            both("éé", "ÉÉÉ", true),
+            (vec![(0, 4)], vec![(0, 4), (2, 6)])
+        );
+        // `İ` lowercases to two characters, and each match covers it whole.
+        let (_, ranges) = both("i\u{307}", "İİ", true);
+        assert_eq!(ranges, vec![(0, 2), (2, 4)]);
+
+        let file = temp_file("overlapping.txt", "aaaa\nababab\n");
+        let count = |query: &str, overlapping: bool| {
+            let mut config = config(query, vec![file.clone()]);
+            config.count_matches = true;
+            config.overlapping = overlapping;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(count("aa", false), "2\n");
+        assert_eq!(count("aa", true), "3\n");
+        assert_eq!(count("aba", true), "2\n");
+
+        let args = ["lilgrep", "--no-config", "--overlapping", "aa", "a.txt"];
+        let err = Config::build(args.into_iter().map(String::from), HashMap::new())
+            .err()
+            .unwrap();
+        assert_eq!(err, "--overlapping needs --count-matches");
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn files_lists_what_would_be_searched() {
+        let root = temp_dir("list-files");
+        fs::create_dir_all(root.join("deep/deeper")).unwrap();
+        fs::write(root.join("a.txt"), "x").unwrap();
+        fs::write(root.join("big.txt"), "x".repeat(100)).unwrap();
+        fs::write(root.join("deep/b.txt"), "x").unwrap();
+        fs::write(root.join("deep/deeper/c.txt"), "x").unwrap();
+
+        let args = [
+            "lilgrep",
+            "--no-config",
+            "--files",
+            "-r",
+            "--max-depth",
+            "1",
+        ];
+        let args = args.iter().copied().chain([root.to_str().unwrap()]);
+        let mut config = Config::build(args.map(String::from), HashMap::new()).unwrap();
+        config.file_sort = FileSort::Path;
+        config.max_filesize = Some(10);
+        assert_eq!(
+            files_to_search(&config),
+            [root.join("a.txt"), root.join("deep/b.txt")]
+        );
+
+        config.null_data = true;
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a.txt\0deep/b.txt\0");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn normalize_matches_composed_and_decomposed_forms() {
+        let composed = "Café crème";
+        let decomposed = "Cafe\u{301} cre\u{300}me";
+        let file = temp_file(
+            "normalize.txt",
+            &format!("{composed}\n{decomposed}\ncafe\n"),
+        );
+        let output = |query: &str, normalize: Option<NormalForm>| {
+            let mut config = config(query, vec![file.clone()]);
+            config.normalize = normalize;
+            config.color = Some(true);
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let style = Style::ansi();
+        let (on, off) = (style.before, style.after);
+        assert_eq!(output("é c", None), format!("Caf{on}é c{off}rème\n"));
+        for form in [NormalForm::Nfc, NormalForm::Nfd] {
+            let expected = format!("Caf{on}é c{off}rème\nCaf{on}e\u{301} c{off}re\u{300}me\n");
+            assert_eq!(output("é c", Some(form)), expected);
+            assert_eq!(output("e\u{301} c", Some(form)), expected);
+        }
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn output_field_prints_one_column() {
+        let csv = temp_file("output-field.csv", "paris,lyon,nice\nlyon\nnice,paris\n");
+        let output = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            let config = Config::build(args.map(|arg| arg.to_string()), HashMap::new()).unwrap();
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            (String::from_utf8(out).unwrap(), summary.matched_lines)
+        };
+
+        assert_eq!(
+            output(&["--output-field", "2", "lyon", &csv]),
+            ("lyon\n\n".to_string(), 2)
+        );
+        assert_eq!(
+            output(&["--output-field", "2", "--skip-missing", "lyon", &csv]),
+            ("lyon\n".to_string(), 2)
         );
+        assert_eq!(
+            output(&["--output-field=1", "--delimiter", "i", "nice", &csv]),
+            ("par\nn\n".to_string(), 2)
+        );
+        assert_eq!(
+            output(&["--output-field", "2", "--color", "always", "par", &csv]).0,
+            "lyon\n\u{1b}[1;31mpar\u{1b}[0mis\n"
+        );
+
+        fs::remove_file(csv).unwrap();
+    }
+
+    #[test]
+    fn no_messages_hides_warnings_but_not_errors() {
+        let root = temp_dir("no-messages");
+        fs::write(root.join("a.txt"), "needle\n").unwrap();
+        let gone = root.join("gone.txt");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("nowhere"), &gone).unwrap();
+        let mut config = config("needle", vec![root.display().to_string()]);
+        config.recursive = true;
+        config.count = true;
+        let err = io::Error::from(io::ErrorKind::NotFound);
+
+        for no_messages in [false, true] {
+            config.no_messages = no_messages;
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), "a.txt:1\n");
+            assert_eq!(summary.had_errors, cfg!(unix));
+            assert_eq!(warning(&config, &gone, &err).is_none(), no_messages);
+        }
+
+        config.file_paths = vec![gone.clone()];
+        let shown = warning(&config, &gone, &err).unwrap();
+        assert_eq!(shown, format!("warning: {err}"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip_archives_are_searched_entry_by_entry() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/logs.zip");
+        let mut config = config("error", vec![path.to_string()]);
+        for threads in [1, 4] {
+            config.threads = threads;
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                format!(
+                    "{path}:app.log:error: disk full\n{path}:nested/notes.txt:no errors here\n"
+                )
+            );
+            assert!(!summary.had_errors);
+        }
+
+        config.list_files = true;
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{path}:app.log\n{path}:nested/notes.txt\n")
+        );
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip_entry_names_never_reach_outside_the_archive() {
+        let outside = Path::new("/tmp/lilgrep-zip-outside.txt");
+        fs::write(outside, "secret token\n").unwrap();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/evil.zip");
+        let config = config("token", vec![path.to_string()]);
+
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{path}:a//b.txt:token in a//b\n")
+        );
+    }
+
+    #[test]
+    fn invert_files_lists_files_without_matches() {
+        let root = temp_dir("invert-files");
+        fs::write(root.join("a.txt"), "needle\n").unwrap();
+        fs::write(root.join("b.txt"), "hay\nstack\n").unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub").join("c.txt"), "more hay\nneedle\n").unwrap();
+        let mut config = config("needle", vec![root.display().to_string()]);
+        config.recursive = true;
+        config.invert_files = true;
+        config.heading = Some(true);
+        config.file_sort = FileSort::Path;
+
+        let output = |config: &Config| {
+            let mut out = Vec::new();
+            run_to(config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(output(&config), "b.txt\n");
+        config.count = true;
+        assert_eq!(output(&config), "b.txt:0\n");
+        let summary = run_to(&config, io::sink()).unwrap();
+        assert_eq!(summary.files_listed, 1);
+
+        let err = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config", "--invert-files"]
+                .iter()
+                .chain(args);
+            Config::build(args.map(|arg| arg.to_string()), HashMap::new())
+                .err()
+                .unwrap()
+        };
+        for args in [
+            &["--name-only", "needle", "a.txt"][..],
+            &["--files", "a.txt"],
+            &["--vimgrep", "needle", "a.txt"],
+            &["--format", "csv", "needle", "a.txt"],
+            &["--format=tsv", "needle", "a.txt"],
+        ] {
+            assert_eq!(
+                err(args),
+                "--invert-files prints paths, so it can't be combined with \
+                --name-only, --files, --vimgrep or a --format other than text"
+            );
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_may_be_invalid_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let mut name = format!("lilgrep-{}-caf", std::process::id()).into_bytes();
+        name.extend(b"\xe9.txt");
+        let path = env::temp_dir().join(OsString::from_vec(name));
+        fs::write(&path, "needle\nhay\n").unwrap();
+        let other = temp_file("utf8-other.txt", "needle\n");
+
+        let args = ["lilgrep".into(), "--no-config".into(), "needle".into()];
+        let args = args
+            .into_iter()
+            .chain([path.clone().into_os_string(), other.clone().into()]);
+        let config = Config::build(args, HashMap::new()).unwrap();
+        assert_eq!(config.file_paths[0], path);
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{}:needle\n{other}:needle\n", path.display())
+        );
+        assert!(path.display().to_string().ends_with("caf\u{fffd}.txt"));
+
+        let args = ["lilgrep", "--no-config"].map(OsString::from).into_iter();
+        let query = OsString::from_vec(b"caf\xe9".to_vec());
+        let built = Config::build(args.chain([query, "a.txt".into()]), HashMap::new());
+        assert_eq!(
+            built.err().unwrap(),
+            "The query isn't valid UTF-8: caf\u{fffd}"
+        );
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(other).unwrap();
+    }
+
+    #[test]
+    fn max_per_line_limits_highlighted_matches() {
+        let file = temp_file("max-per-line.txt", "ab ab ab ab ab\nno match\n");
+        let mut config = config("ab", vec![file.clone()]);
+        config.max_per_line = Some(2);
+        let output = |config: &Config| {
+            let mut out = Vec::new();
+            run_to(config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        config.mark = Some(Style::new("[", "]"));
+        assert_eq!(output(&config), "[ab] [ab] ab ab ab\n");
+        config.mark = None;
+        config.format = OutputFormat::Vimgrep;
+        assert_eq!(
+            output(&config),
+            format!("{file}:1:1:ab ab ab ab ab\n{file}:1:4:ab ab ab ab ab\n")
+        );
+        config.format = OutputFormat::Text;
+        config.count_matches = true;
+        assert_eq!(output(&config), "5\n");
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn files_from_adds_the_listed_paths() {
+        let root = temp_dir("files-from");
+        let (a, b) = (root.join("a.txt"), root.join("b.txt"));
+        fs::write(&a, "needle\n").unwrap();
+        fs::write(&b, "hay\nneedle\nneedle\n").unwrap();
+        let missing = root.join("missing.txt");
+        let list = root.join("list.txt");
+        let listed = format!(
+            "{}\r\n\n{}\n{}\n",
+            a.display(),
+            b.display(),
+            missing.display()
+        );
+        fs::write(&list, listed).unwrap();
+        let build = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            Config::build(args.map(|arg| arg.to_string()), HashMap::new())
+        };
+        let output = |config: &Config| {
+            let mut out = Vec::new();
+            let summary = run_to(config, &mut out).unwrap();
+            (String::from_utf8(out).unwrap(), summary.had_errors)
+        };
+
+        let list_arg = list.display().to_string();
+        let config = build(&["-c", "needle", "--files-from", &list_arg]).unwrap();
+        assert_eq!(config.file_paths, [a.clone(), b.clone(), missing]);
+        let (a_shown, b_shown) = (a.display(), b.display());
+        assert_eq!(
+            output(&config),
+            (format!("{a_shown}:1\n{b_shown}:2\n"), true)
+        );
+
+        fs::write(&list, format!("{}\0{}\0", a.display(), b.display())).unwrap();
+        let config = build(&["--null-data", "--files-from", &list_arg, "needle"]).unwrap();
+        assert_eq!(config.file_paths, [a, b]);
+        let err = build(&["--files-from", "-", "needle", "-"]).err().unwrap();
+        assert!(err.starts_with("--files-from - reads the list from standard input"));
+        let err = build(&["--files-from", "nowhere.txt", "needle"])
+            .err()
+            .unwrap();
+        assert!(err.starts_with("Couldn't read the file list nowhere.txt: "));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_carries_on_past_unreadable_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = temp_dir("walk-locked");
+        fs::write(root.join("ok.txt"), "needle\n").unwrap();
+        let locked = root.join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("secret.txt"), "needle\n").unwrap();
+        fs::create_dir(root.join("open")).unwrap();
+        fs::write(root.join("open").join("also.txt"), "needle\n").unwrap();
+        // A link to nothing can't be read by anyone, root included.
+        std::os::unix::fs::symlink(root.join("gone.txt"), root.join("dangling.txt")).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions don't stop root, which then reads the locked directory.
+        let denied = fs::read_dir(&locked).is_err();
+
+        let mut config = config("needle", vec![root.display().to_string()]);
+        config.recursive = true;
+        config.no_messages = true;
+        config.file_sort = FileSort::Path;
+        for threads in [1, 4] {
+            config.threads = threads;
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert!(out.contains("ok.txt:needle\n") && out.contains("also.txt:needle\n"));
+            assert!(summary.had_errors);
+            assert_eq!(summary.unreadable, 1 + usize::from(denied));
+            assert_eq!(out.contains("secret.txt"), !denied);
+        }
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn whole_line_needs_the_line_to_be_the_query() {
+        let file = temp_file("whole-line.txt", "Active\nInactive\nactive\nactive \n");
+        let output = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            let config = Config::build(args.map(|arg| arg.to_string()), HashMap::new()).unwrap();
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(output(&["-x", "active", &file]), "active\n");
+        assert_eq!(output(&["-xi", "active", &file]), "Active\nactive\n");
+        assert_eq!(
+            output(&["--line-regexp", "-v", "active", &file]),
+            "Active\nInactive\nactive \n"
+        );
+        assert_eq!(output(&["active", &file]), "Inactive\nactive\nactive \n");
+        assert_eq!(
+            output(&["-x", "--mark", "-e", "Active", "-e", "active", &file]),
+            "[Active]\n[active]\n"
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn replaced_sequences_never_match_the_query() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/invalid_utf8.log"
+        );
+        let output = |query: &str, mmap: bool| {
+            let mut config = config(query, vec![path.to_string()]);
+            config.mmap = mmap;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        for mmap in [false, true] {
+            assert_eq!(
+                output("error", mmap),
+                "error: disk \u{fffd}\u{fffd} full\nerror: \u{fffd}( retrying\n"
+            );
+            // Only the line that really has one.
+            assert_eq!(output("\u{fffd}", mmap), "warning: \u{fffd} in the name\n");
+            assert_eq!(output("disk \u{fffd}", mmap), "");
+        }
+
+        // Every other line, counted the same way.
+        let mut config = config("\u{fffd}", vec![path.to_string()]);
+        config.invert = true;
+        for mmap in [false, true] {
+            config.mmap = mmap;
+            config.count = false;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                "boot ok\nerror: disk \u{fffd}\u{fffd} full\nerror: \u{fffd}( retrying\nall done\n"
+            );
+            config.count = true;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), "4\n");
+        }
+        config.count = false;
+        config.passthru = true;
+        config.color = Some(true);
+        let mut out = Vec::new();
+        assert_eq!(run_to(&config, &mut out).unwrap().matched_lines, 4);
+    }
+
+    #[test]
+    fn diff_sides_search_only_their_lines() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/change.diff");
+        let output = |side: DiffSide, query: &str, invert: bool| {
+            let mut config = config(query, vec![path.to_string()]);
+            config.diff = Some(side);
+            config.invert = invert;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let removed = "-    let timeout = 30;\n-// timeout in seconds\n--- timeout note\n";
+        assert_eq!(
+            output(DiffSide::Added, "timeout", false),
+            "+    let timeout = 60;\n+++attempts; // timeout\n"
+        );
+        assert_eq!(output(DiffSide::Removed, "timeout", false), removed);
+        // The marker isn't part of what is searched, and headers never match,
+        // though lines inside a hunk may start like them.
+        assert_eq!(output(DiffSide::Added, "+    let", false), "");
+        assert_eq!(
+            output(DiffSide::Added, "++", false),
+            "+++attempts; // timeout\n"
+        );
+        assert_eq!(output(DiffSide::Removed, "a/src", false), "");
+        assert_eq!(output(DiffSide::Added, "b/src", false), "");
+        assert_eq!(
+            output(DiffSide::Added, "timeout", true),
+            "+    let retries = 3;\n"
+        );
+
+        // Read a line at a time, the headers are told apart the same way.
+        let mut config = config("timeout", vec![path.to_string()]);
+        config.diff = Some(DiffSide::Removed);
+        config.mmap = true;
+        let mut out = Vec::new();
+        run_to(&config, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), removed);
+        config.mmap = false;
+        config.passthru = true;
+        let summary = run_to(&config, io::sink()).unwrap();
+        assert_eq!(summary.matched_lines, 3);
+
+        assert_eq!(DiffSide::Added.content("++x"), Some("+x"));
+        assert_eq!(DiffSide::Removed.content("-"), Some(""));
+        assert_eq!(DiffSide::Removed.content(" -"), None);
+
+        let mut headers = DiffHeaders::default();
+        let diff = [
+            "--- a/x",
+            "+++ b/x",
+            "@@ -1 +1,2 @@",
+            "--- old",
+            "+++ new",
+            "+++ more",
+        ];
+        let found: Vec<bool> = diff.iter().map(|line| headers.is_header(line)).collect();
+        assert_eq!(found, [true, true, false, false, false, false]);
+        // The hunk is over, so what follows is the next file's header.
+        assert!(headers.is_header("--- a/y"));
+        assert_eq!(hunk_lengths("@@ -3,0 +4 @@ fn main() {"), Some((0, 1)));
+        assert_eq!(hunk_lengths("@@ bad @@"), None);
+    }
+
+    #[test]
+    fn skip_bytes_starts_at_the_next_line() {
+        // The lines start at offsets 0, 10, 19 and 31.
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/rotated.log");
+        let output = |skip_bytes: u64, mmap: bool| {
+            // Each line has one space.
+            let mut config = config(" ", vec![path.to_string()]);
+            config.skip_bytes = skip_bytes;
+            config.mmap = mmap;
+            config.format = OutputFormat::Vimgrep;
+            let mut out = Vec::new();
+            run_to(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        for mmap in [false, true] {
+            let all = output(0, mmap);
+            assert!(all.starts_with(&format!("{path}:1:6:alpha one\n")));
+            assert_eq!(all.lines().count(), 4);
+            // On a line boundary, and just past one.
+            assert_eq!(
+                output(10, mmap),
+                format!("{path}:1:5:beta two\n{path}:2:6:gamma three\n{path}:3:6:delta four\n")
+            );
+            assert_eq!(
+                output(11, mmap),
+                format!("{path}:1:6:gamma three\n{path}:2:6:delta four\n")
+            );
+            assert_eq!(output(31, mmap), format!("{path}:1:6:delta four\n"));
+            assert_eq!(output(100, mmap), "");
+        }
+
+        let err = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            Config::build(args.map(|a| a.to_string()), HashMap::new())
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            err(&[
+                "--in-place",
+                "--replace",
+                "b",
+                "--skip-bytes",
+                "1K",
+                "a",
+                path
+            ]),
+            "--in-place can't be combined with --skip-bytes"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pre_searches_what_the_command_prints() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("pre");
+        fs::write(dir.join("notes.txt"), "quiet\nneedle here\n").unwrap();
+        fs::write(dir.join("report.pdf"), "binary-ish needle\n").unwrap();
+        let script = |name: &str, body: &str| {
+            let path = dir.join(name);
+            fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+        let upper = script("upper.sh", "tr a-z A-Z < \"$1\"");
+        let failing = script("failing.sh", "echo needle; exit 3");
+
+        let run = |pre: &Path, glob: Option<&str>, query: &str, mmap: bool| {
+            let paths =
+                ["notes.txt", "report.pdf"].map(|name| dir.join(name).display().to_string());
+            let mut config = config(query, paths.to_vec());
+            config.pre = Some(pre.to_path_buf());
+            config.pre_glob = glob.map(String::from);
+            config.mmap = mmap;
+            config.no_messages = true;
+            let mut out = Vec::new();
+            let summary = run_to(&config, &mut out).unwrap();
+            (String::from_utf8(out).unwrap(), summary)
+        };
+        for mmap in [false, true] {
+            let (out, _) = run(Path::new("cat"), None, "needle", mmap);
+            assert_eq!(out.lines().count(), 2);
+            let (out, _) = run(&upper, None, "NEEDLE", mmap);
+            assert!(out.contains("notes.txt:NEEDLE HERE\n"));
+            assert!(out.contains("report.pdf:BINARY-ISH NEEDLE\n"));
+            // Only the PDF goes through the script.
+            let (out, _) = run(&upper, Some("*.pdf"), "needle", mmap);
+            assert!(out.contains("notes.txt:needle here\n"));
+            assert!(!out.contains("report.pdf"));
+
+            let (_, summary) = run(&failing, None, "needle", mmap);
+            assert!(summary.had_errors);
+            assert_eq!(summary.unreadable, 2);
+        }
+        let (_, summary) = run(&dir.join("missing.sh"), None, "needle", false);
+        assert_eq!(summary.unreadable, 2);
+
+        let err = |args: &[&str]| {
+            let args = ["lilgrep", "--no-config"].iter().chain(args);
+            Config::build(args.map(|a| a.to_string()), HashMap::new())
+                .err()
+                .unwrap()
+        };
+        assert_eq!(
+            err(&["--pre-glob", "*.pdf", "a", "b"]),
+            "--pre-glob needs --pre"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
     }
 }
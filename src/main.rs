@@ -1,15 +1,70 @@
-use std::{env, process};
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, IsTerminal},
+    process,
+};
 
-use lilgrep::Config;
+use lilgrep::{Config, MISSING_QUERY, ProcessEnv, USAGE};
 
 fn main() {
-    let config = Config::build(env::args()).unwrap_or_else(|err| {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let config = match Config::build(args.clone().into_iter(), ProcessEnv) {
+        Err(err) if err == MISSING_QUERY && io::stdin().is_terminal() => match prompt_query() {
+            Some(query) => {
+                // Right after the program name, so a `--` can't make it a path.
+                args.insert(1.min(args.len()), format!("--query={query}").into());
+                Config::build(args.into_iter(), ProcessEnv)
+            }
+            None => Err(err),
+        },
+        config => config,
+    }
+    .unwrap_or_else(|err| {
         eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        process::exit(2);
     });
 
-    if let Err(e) = lilgrep::run(config) {
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    if config.help {
+        print!("{USAGE}");
+        return;
+    }
+
+    // Printing a path, as --files and --invert-files do, matches no line but
+    // isn't a search that came up empty. The lines --invert-files matched are
+    // in the files it leaves out, so they don't count.
+    let lines_count = !config.invert_files;
+    match lilgrep::run(config) {
+        Ok(summary) if summary.had_errors => process::exit(2),
+        Ok(summary)
+            if summary.files_listed == 0 && (summary.matched_lines == 0 || !lines_count) =>
+        {
+            process::exit(1)
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            process::exit(2);
+        }
+    }
+}
+
+/// Asks for the query on stderr, and once more if the answer is empty.
+///
+/// Returns `None` if it is empty again, or if stdin ends or can't be read.
+fn prompt_query() -> Option<String> {
+    for _ in 0..2 {
+        eprint!("Search for: ");
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            // Ctrl-D leaves the cursor after the prompt.
+            eprintln!();
+            return None;
+        }
+        let query = line.trim_end_matches(['\n', '\r']);
+        if !query.is_empty() {
+            return Some(query.to_string());
+        }
     }
+    None
 }
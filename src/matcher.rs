@@ -0,0 +1,665 @@
+//! Deciding which lines match: the `Matcher` trait, and the patterns of a
+//! search combined into one.
+
+use std::{iter, ops::Range};
+
+use crate::{
+    CaseMode, Config, DiffSide, NormalForm, Searcher, format,
+    fuzzy::{self, Fuzzy},
+    normalize::{normalize, normalize_with_spans},
+    phonetic::Soundex,
+    search,
+};
+
+/// A predicate on lines, with a way to point at what matched.
+///
+/// `Searcher` is one, and so is whatever `Config` asks for; implement it to
+/// search with logic of your own through `search_with_matcher` or
+/// `run_with_matcher`. The trait is object safe, so a `Box<dyn Matcher>` can
+/// stand in for a concrete matcher.
+///
+/// # Example
+///
+/// ```
+/// use std::ops::Range;
+///
+/// use lilgrep::{Matcher, search_with_matcher};
+///
+/// /// Lines that are entirely digits.
+/// struct Digits;
+///
+/// impl Matcher for Digits {
+///     fn is_match(&self, line: &str) -> bool {
+///         !line.is_empty() && line.bytes().all(|b| b.is_ascii_digit())
+///     }
+///
+///     fn find(&self, line: &str) -> Option<Range<usize>> {
+///         self.is_match(line).then(|| 0..line.len())
+///     }
+/// }
+///
+/// let matcher: Box<dyn Matcher> = Box::new(Digits);
+/// assert_eq!(search_with_matcher(&matcher, "12\nab\n7"), ["12", "7"]);
+/// ```
+pub trait Matcher {
+    /// Checks whether a single line matches.
+    fn is_match(&self, line: &str) -> bool;
+
+    /// Returns the byte range of the first match in `line`, if it has one.
+    ///
+    /// A line can match without anything in it to point at, as when a search
+    /// is inverted; `find` then returns `None`.
+    fn find(&self, line: &str) -> Option<Range<usize>>;
+
+    /// Returns the byte ranges of every match in `line`, in order and never
+    /// overlapping.
+    ///
+    /// By default `find` is called again after the end of each match.
+    fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut from = 0;
+
+        while let Some(range) = line.get(from..).and_then(|rest| self.find(rest)) {
+            let range = from + range.start..from + range.end;
+            from = if range.is_empty() {
+                // Step over the next character so the search moves on.
+                range.end + line[range.end..].chars().next().map_or(1, char::len_utf8)
+            } else {
+                range.end
+            };
+            ranges.push(range);
+        }
+
+        ranges
+    }
+
+    /// Returns the lines of `contents` that match, in order.
+    fn search<'a>(&self, contents: &'a str) -> Vec<&'a str> {
+        contents
+            .lines()
+            .filter(|line| self.is_match(line))
+            .collect()
+    }
+
+    /// Returns `line` with every range found by `find_all` replaced by
+    /// `replacement`.
+    fn replace(&self, line: &str, replacement: &str) -> String {
+        search::replace_ranges(line, self.find_all(line), replacement)
+    }
+}
+
+impl<M: Matcher + ?Sized> Matcher for &M {
+    fn is_match(&self, line: &str) -> bool {
+        (**self).is_match(line)
+    }
+
+    fn find(&self, line: &str) -> Option<Range<usize>> {
+        (**self).find(line)
+    }
+
+    fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        (**self).find_all(line)
+    }
+
+    fn search<'a>(&self, contents: &'a str) -> Vec<&'a str> {
+        (**self).search(contents)
+    }
+
+    fn replace(&self, line: &str, replacement: &str) -> String {
+        (**self).replace(line, replacement)
+    }
+}
+
+impl<M: Matcher + ?Sized> Matcher for Box<M> {
+    fn is_match(&self, line: &str) -> bool {
+        (**self).is_match(line)
+    }
+
+    fn find(&self, line: &str) -> Option<Range<usize>> {
+        (**self).find(line)
+    }
+
+    fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        (**self).find_all(line)
+    }
+
+    fn search<'a>(&self, contents: &'a str) -> Vec<&'a str> {
+        (**self).search(contents)
+    }
+
+    fn replace(&self, line: &str, replacement: &str) -> String {
+        (**self).replace(line, replacement)
+    }
+}
+
+impl Matcher for Searcher {
+    fn is_match(&self, line: &str) -> bool {
+        Searcher::is_match(self, line)
+    }
+
+    fn find(&self, line: &str) -> Option<Range<usize>> {
+        self.find_ranges(line).into_iter().next()
+    }
+
+    fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        self.find_ranges(line)
+    }
+
+    fn search<'a>(&self, contents: &'a str) -> Vec<&'a str> {
+        Searcher::search(self, contents)
+    }
+
+    fn replace(&self, line: &str, replacement: &str) -> String {
+        Searcher::replace(self, line, replacement)
+    }
+}
+
+/// One of the patterns a line is searched for.
+enum Pattern {
+    Literal(Searcher),
+    /// A query the whole line must be, with `whole_line`.
+    Line(Searcher),
+    Fuzzy(Fuzzy),
+    Soundex(Soundex),
+}
+
+impl Pattern {
+    fn len(&self) -> usize {
+        match self {
+            Pattern::Literal(searcher) | Pattern::Line(searcher) => searcher.needle.len(),
+            Pattern::Fuzzy(fuzzy) => fuzzy.len(),
+            Pattern::Soundex(soundex) => soundex.len(),
+        }
+    }
+
+    fn matches(&self, line: &str, scratch: &mut String) -> bool {
+        match self {
+            Pattern::Literal(searcher) => searcher.matches(line, scratch),
+            Pattern::Line(searcher) => searcher.is_line_match(line),
+            Pattern::Fuzzy(fuzzy) => fuzzy.is_match(line),
+            Pattern::Soundex(soundex) => soundex.is_match(line),
+        }
+    }
+
+    fn find_ranges(&self, line: &str, overlapping: bool) -> Vec<Range<usize>> {
+        match self {
+            Pattern::Literal(searcher) if overlapping => searcher.find_overlapping_ranges(line),
+            Pattern::Literal(searcher) => searcher.find_ranges(line),
+            Pattern::Line(searcher) if searcher.is_line_match(line) => {
+                iter::once(0..line.len()).collect()
+            }
+            Pattern::Line(_) => Vec::new(),
+            Pattern::Fuzzy(fuzzy) => fuzzy.find_ranges(line),
+            Pattern::Soundex(soundex) => soundex.find_ranges(line),
+        }
+    }
+}
+
+/// Every pattern of a search, combined as configured.
+///
+/// A line matches when it contains any of the patterns, or with `all_of` every
+/// one of them, and none of the `--not` patterns; `invert` then negates that,
+/// so with `all_of` a line matches unless it contains all of the patterns.
+/// With `fuzzy`, the patterns also match within that many edits, and with
+/// `soundex` they match words that sound alike instead; `--not` patterns
+/// always match exactly. With `whole_line`, a pattern must be the whole line,
+/// but `--not` patterns are still looked for anywhere in it. With `diff`, only
+/// the lines on that side of a diff can match, and only what follows their
+/// `+` or `-` is looked at. With `field`, only that field of each line
+/// is looked at, and a line with fewer fields contains none of the patterns.
+/// With `overlapping`, literal patterns are found at every position, and the
+/// ranges of different patterns aren't merged. With `normalize`, patterns and
+/// lines are both put in that form before they are compared.
+///
+/// `run_to` builds them once, case-folding each query then, and lends them to
+/// every thread that searches, so nothing is prepared again per file.
+pub(crate) struct Patterns {
+    searchers: Vec<Pattern>,
+    negatives: Vec<Searcher>,
+    all_of: bool,
+    invert: bool,
+    diff: Option<DiffSide>,
+    /// The 1-based field to search, and what separates fields.
+    field: Option<(usize, char)>,
+    overlapping: bool,
+    normalize: Option<NormalForm>,
+}
+
+impl Patterns {
+    /// Prepares the patterns of `config`: the query, or every `-e` pattern.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a `fuzzy` pattern is longer than `fuzzy::MAX_LEN`
+    /// characters, once normalized, or a `soundex` one has no letters to
+    /// encode.
+    pub(crate) fn new(config: &Config) -> Result<Patterns, String> {
+        let case = if config.ignore_case {
+            CaseMode::Insensitive
+        } else {
+            CaseMode::Sensitive
+        };
+        let normal = |pattern: &str| match config.normalize {
+            Some(form) => normalize(pattern, form),
+            None => pattern.to_string(),
+        };
+        let mut searchers: Vec<Pattern> = config
+            .queries()
+            .map(|given| {
+                let query = &normal(given);
+                Ok(match config.fuzzy {
+                    _ if config.soundex => {
+                        Pattern::Soundex(Soundex::new(query).ok_or_else(|| {
+                            format!("--soundex needs a word with letters, but got {given:?}")
+                        })?)
+                    }
+                    Some(max_edits) if max_edits > 0 => {
+                        Pattern::Fuzzy(Fuzzy::new(query, case, max_edits).ok_or_else(|| {
+                            format!(
+                                "--fuzzy only supports queries of up to {} characters",
+                                fuzzy::MAX_LEN
+                            )
+                        })?)
+                    }
+                    _ if config.whole_line => Pattern::Line(Searcher::new(query, case, false)),
+                    _ => Pattern::Literal(Searcher::new(query, case, config.whole_word)),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        let negatives = config
+            .not_patterns
+            .iter()
+            .map(|pattern| Searcher::new(&normal(pattern), case, config.whole_word))
+            .collect();
+
+        // Every pattern costs about the same to look for, so the cheapest way
+        // to rule a line out is to try the pattern least likely to occur
+        // first, and a longer pattern is less likely to occur.
+        if config.all_of {
+            searchers.sort_by_key(|searcher| std::cmp::Reverse(searcher.len()));
+        }
+
+        Ok(Patterns {
+            searchers,
+            negatives,
+            all_of: config.all_of,
+            invert: config.invert,
+            diff: config.diff,
+            field: config.field.map(|field| (field, config.delimiter())),
+            overlapping: config.overlapping,
+            normalize: config.normalize,
+        })
+    }
+
+    /// Returns the byte ranges in `line` covered by any pattern, in order and
+    /// with overlapping ranges merged unless `overlapping` is set. `--not`
+    /// patterns are never included.
+    ///
+    /// With `invert` a matching line is one that lacks the patterns, so there
+    /// is nothing to point at and no ranges are returned.
+    pub(crate) fn find_ranges(&self, line: &str) -> Vec<Range<usize>> {
+        if self.invert {
+            return Vec::new();
+        }
+        let Some((offset, line)) = self.field(line) else {
+            return Vec::new();
+        };
+        let normalized = self.normalize.map(|form| normalize_with_spans(line, form));
+        let moved = |range: Range<usize>| match &normalized {
+            Some((_, spans)) => {
+                let start = spans.get(range.start).map_or(line.len(), |span| span.start);
+                let end = if range.is_empty() {
+                    start
+                } else {
+                    spans[range.end - 1].end
+                };
+                offset + start..offset + end
+            }
+            None => offset + range.start..offset + range.end,
+        };
+        let line = normalized.as_ref().map_or(line, |(text, _)| text.as_str());
+        let overlapping = self.overlapping;
+        if let [searcher] = self.searchers.as_slice() {
+            return searcher
+                .find_ranges(line, overlapping)
+                .into_iter()
+                .map(moved)
+                .collect();
+        }
+
+        let mut ranges: Vec<Range<usize>> = self
+            .searchers
+            .iter()
+            .flat_map(|searcher| searcher.find_ranges(line, overlapping))
+            .map(moved)
+            .collect();
+        ranges.sort_by_key(|range| range.start);
+        if overlapping {
+            return ranges;
+        }
+
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start < last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    /// Returns the part of `line` to search and where in `line` it starts:
+    /// all of it, or the chosen field if the line has that many, after the
+    /// `+` or `-` of a line on the chosen side of a diff.
+    fn field<'a>(&self, line: &'a str) -> Option<(usize, &'a str)> {
+        let (offset, line) = match self.diff {
+            Some(side) => (1, side.content(line)?),
+            None => (0, line),
+        };
+        let Some((field, delimiter)) = self.field else {
+            return Some((offset, line));
+        };
+        format::nth_field(line, field, delimiter).map(|(start, field)| (offset + start, field))
+    }
+
+    fn matches(&self, line: &str, scratch: &mut String) -> bool {
+        // Even an inverted search leaves the rest of the diff out.
+        if self.diff.is_some_and(|side| side.content(line).is_none()) {
+            return false;
+        }
+        let Some((_, line)) = self.field(line) else {
+            return self.invert;
+        };
+        let normalized = self.normalize.map(|form| normalize(line, form));
+        let line = normalized.as_deref().unwrap_or(line);
+        let mut found = self
+            .searchers
+            .iter()
+            .map(|searcher| searcher.matches(line, scratch));
+        let combined = if self.all_of {
+            found.all(|found| found)
+        } else {
+            found.any(|found| found)
+        };
+        let excluded = combined
+            && self
+                .negatives
+                .iter()
+                .any(|negative| negative.matches(line, scratch));
+        (combined && !excluded) != self.invert
+    }
+}
+
+impl Matcher for Patterns {
+    fn is_match(&self, line: &str) -> bool {
+        self.matches(line, &mut String::new())
+    }
+
+    fn find(&self, line: &str) -> Option<Range<usize>> {
+        self.find_ranges(line).into_iter().next()
+    }
+
+    fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        self.find_ranges(line)
+    }
+
+    fn search<'a>(&self, contents: &'a str) -> Vec<&'a str> {
+        if let [Pattern::Literal(searcher)] = self.searchers.as_slice()
+            && self.negatives.is_empty()
+            && !self.invert
+            && self.diff.is_none()
+            && self.field.is_none()
+            && self.normalize.is_none()
+        {
+            return searcher.search(contents);
+        }
+
+        let mut scratch = String::new();
+        contents
+            .lines()
+            .filter(|line| self.matches(line, &mut scratch))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORPUS: &str = "\
+connection timeout, will retry
+retry succeeded
+Timeout after 3 RETRIES
+nothing to see here
+timeout";
+
+    fn matcher(patterns: &[&str], all_of: bool, invert: bool, ignore_case: bool) -> Patterns {
+        Patterns::new(&Config {
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            all_of,
+            invert,
+            ignore_case,
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn any_and_all_of() {
+        let any = matcher(&["timeout", "retry"], false, false, false);
+        assert_eq!(
+            any.search(CORPUS),
+            [
+                "connection timeout, will retry",
+                "retry succeeded",
+                "timeout"
+            ]
+        );
+
+        let all = matcher(&["timeout", "retry"], true, false, false);
+        assert_eq!(all.search(CORPUS), ["connection timeout, will retry"]);
+
+        let all = matcher(&["timeout", "retr"], true, false, true);
+        assert_eq!(
+            all.search(CORPUS),
+            ["connection timeout, will retry", "Timeout after 3 RETRIES"]
+        );
+    }
+
+    #[test]
+    fn invert_negates_the_combination() {
+        let not_all = matcher(&["timeout", "retry"], true, true, false);
+        assert_eq!(
+            not_all.search(CORPUS),
+            [
+                "retry succeeded",
+                "Timeout after 3 RETRIES",
+                "nothing to see here",
+                "timeout"
+            ]
+        );
+
+        let none = matcher(&["timeout", "retry"], false, true, false);
+        assert_eq!(
+            none.search(CORPUS),
+            ["Timeout after 3 RETRIES", "nothing to see here"]
+        );
+    }
+
+    #[test]
+    fn ranges_of_every_pattern_are_merged() {
+        let any = matcher(&["ab", "bc", "x"], false, false, false);
+        assert_eq!(any.find_ranges("abc x ab"), [0..3, 4..5, 6..8]);
+        assert_eq!(any.replace("abc x ab", "_"), "_ _ _");
+    }
+
+    #[test]
+    fn not_patterns_exclude_lines() {
+        let positive = |not_patterns: &[&str], invert: bool, ignore_case: bool| {
+            Patterns::new(&Config {
+                query: "timeout".to_string(),
+                not_patterns: not_patterns
+                    .iter()
+                    .map(|pattern| pattern.to_string())
+                    .collect(),
+                invert,
+                ignore_case,
+                ..Config::default()
+            })
+            .unwrap()
+        };
+
+        let matcher = positive(&["retry"], false, false);
+        assert_eq!(matcher.search(CORPUS), ["timeout"]);
+
+        let matcher = positive(&["RETR"], false, true);
+        assert_eq!(matcher.search(CORPUS), ["timeout"]);
+
+        let matcher = positive(&["retry", "nothing"], true, false);
+        assert_eq!(
+            matcher.search(CORPUS),
+            [
+                "connection timeout, will retry",
+                "retry succeeded",
+                "Timeout after 3 RETRIES",
+                "nothing to see here"
+            ]
+        );
+    }
+
+    #[test]
+    fn fuzzy_patterns() {
+        let fuzzy = |query: &str, fuzzy: Option<usize>| {
+            Patterns::new(&Config {
+                query: query.to_string(),
+                fuzzy,
+                ..Config::default()
+            })
+            .unwrap()
+        };
+
+        assert_eq!(
+            fuzzy("timeout", Some(0)).search(CORPUS),
+            fuzzy("timeout", None).search(CORPUS)
+        );
+        assert_eq!(
+            fuzzy("retyr", Some(2)).search(CORPUS),
+            ["connection timeout, will retry", "retry succeeded"]
+        );
+        assert_eq!(
+            fuzzy("tmeout", Some(1)).find_ranges("a timeout"),
+            vec![2..9]
+        );
+    }
+
+    #[test]
+    fn default_find_all_moves_past_each_match() {
+        /// Every run of the same letter, found one at a time.
+        struct Runs;
+
+        impl Matcher for Runs {
+            fn is_match(&self, line: &str) -> bool {
+                !line.is_empty()
+            }
+
+            fn find(&self, line: &str) -> Option<Range<usize>> {
+                let first = line.chars().next()?;
+                let len = line.find(|c| c != first).unwrap_or(line.len());
+                Some(0..len)
+            }
+        }
+
+        assert_eq!(Runs.find_all("aabéé"), [0..2, 2..3, 3..7]);
+        assert_eq!(Runs.replace("aabéé", "_"), "___");
+        assert!(Runs.find_all("").is_empty());
+
+        let boxed: Box<dyn Matcher> = Box::new(Runs);
+        assert_eq!(boxed.search("xx\n\ny"), ["xx", "y"]);
+    }
+
+    #[test]
+    fn references_and_boxes_forward_every_method() {
+        /// Matches everything, and replaces by shouting.
+        struct Shout;
+
+        impl Matcher for Shout {
+            fn is_match(&self, _: &str) -> bool {
+                true
+            }
+
+            fn find(&self, line: &str) -> Option<Range<usize>> {
+                Some(0..line.len())
+            }
+
+            fn find_all(&self, _: &str) -> Vec<Range<usize>> {
+                vec![0..1, 2..3]
+            }
+
+            fn search<'a>(&self, contents: &'a str) -> Vec<&'a str> {
+                vec![contents]
+            }
+
+            fn replace(&self, line: &str, _: &str) -> String {
+                line.to_uppercase()
+            }
+        }
+
+        fn check(matcher: impl Matcher) {
+            assert!(matcher.is_match(""));
+            assert_eq!(matcher.find("abc"), Some(0..3));
+            assert_eq!(matcher.find_all("abc"), [0..1, 2..3]);
+            assert_eq!(matcher.search("a\nb"), ["a\nb"]);
+            assert_eq!(matcher.replace("abc", "_"), "ABC");
+        }
+        check(&Shout);
+        check(Box::new(Shout));
+        let boxed: Box<dyn Matcher> = Box::new(Shout);
+        check(&boxed);
+    }
+
+    #[test]
+    fn only_the_chosen_field_is_searched() {
+        let field = |query: &str, field: usize, delimiter: char| {
+            Patterns::new(&Config {
+                query: query.to_string(),
+                field: Some(field),
+                delimiter: Some(delimiter),
+                ..Config::default()
+            })
+            .unwrap()
+        };
+        let csv = "id,name,city\n1,paris,lyon\n2,lyon,paris\n3,nice";
+        assert_eq!(field("paris", 3, ',').search(csv), ["2,lyon,paris"]);
+        assert_eq!(field("paris", 2, ',').search(csv), ["1,paris,lyon"]);
+        assert_eq!(
+            field("paris", 2, ',').find_ranges("2,paris,paris"),
+            vec![2..7]
+        );
+        // Out of range fields never match.
+        assert!(field("nice", 3, ',').search(csv).is_empty());
+        assert!(field("1", 4, ',').search(csv).is_empty());
+
+        let tsv = "a\tb,c\tneedle\nneedle\tb\tc";
+        assert_eq!(field("needle", 3, '\t').search(tsv), ["a\tb,c\tneedle"]);
+        assert_eq!(
+            field("b,c", 2, '\t').find_ranges("a\tb,c\tneedle"),
+            vec![2..5]
+        );
+
+        // Left unset, the delimiter is a comma, as on the command line.
+        let unset = Patterns::new(&Config {
+            query: "paris".to_string(),
+            field: Some(3),
+            ..Config::default()
+        })
+        .unwrap();
+        assert_eq!(unset.search(csv), ["2,lyon,paris"]);
+    }
+
+    #[test]
+    fn patterns_can_be_shared_between_threads() {
+        fn shared<T: Send + Sync>() {}
+        shared::<Patterns>();
+        shared::<Searcher>();
+    }
+}
@@ -0,0 +1,68 @@
+//! Searching a file for results that don't borrow its contents.
+//!
+//! The functions in `search` return lines borrowed from the text given to
+//! them, which is the cheapest way to search text already in memory. Here the
+//! file is read and dropped again, and each matching line is copied out, so the
+//! results can be returned from a helper or sent to another thread.
+
+use std::{fs, path::Path};
+
+use crate::{CaseMode, OwnedMatch, SearchError, Searcher};
+
+/// How `search_file` compares the query with each line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub case: CaseMode,
+    /// Whether the query must occur as a whole word.
+    pub whole_word: bool,
+}
+
+impl Default for SearchOptions {
+    /// A case-sensitive search for the query anywhere in a line.
+    fn default() -> SearchOptions {
+        SearchOptions {
+            case: CaseMode::Sensitive,
+            whole_word: false,
+        }
+    }
+}
+
+/// Reads the file at `path` and returns its lines matching `query`, in order.
+///
+/// A file that isn't valid UTF-8 is decoded lossily, as `run` does.
+///
+/// # Errors
+///
+/// Returns a `SearchError::Io` naming `path` if the file can't be read.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::{SearchOptions, search_file};
+///
+/// # let path = std::env::temp_dir().join("lilgrep-doc-search-file.txt");
+/// # std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+/// let matches = search_file(&path, "t", &SearchOptions::default()).unwrap();
+/// let lines: Vec<_> = matches.iter().map(|found| found.line.as_str()).collect();
+/// assert_eq!(lines, ["two", "three"]);
+/// assert_eq!(matches[1].line_number, 3);
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+pub fn search_file(
+    path: &Path,
+    query: &str,
+    options: &SearchOptions,
+) -> Result<Vec<OwnedMatch>, SearchError> {
+    let bytes = fs::read(path).map_err(|source| SearchError::Io {
+        action: "read",
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let contents = String::from_utf8_lossy(&bytes);
+    let searcher = Searcher::new(query, options.case, options.whole_word);
+    Ok(searcher
+        .search_matches(&contents)
+        .iter()
+        .map(|found| found.to_owned())
+        .collect())
+}
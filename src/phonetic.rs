@@ -0,0 +1,137 @@
+//! Matching words by how they sound, with the classic Soundex algorithm.
+//!
+//! Soundex was designed for English surnames: it only looks at the ASCII
+//! letters of a word, and its groups of similar consonants only make sense
+//! for English spelling.
+
+use std::ops::Range;
+
+/// A word that matches every whitespace-separated token of a line with the
+/// same Soundex code.
+#[derive(Debug, Clone)]
+pub(crate) struct Soundex {
+    code: [u8; 4],
+    /// The length of the word, for ordering patterns by cost.
+    len: usize,
+}
+
+impl Soundex {
+    /// Prepares `word`, or returns `None` if it has no ASCII letters to
+    /// encode.
+    pub(crate) fn new(word: &str) -> Option<Soundex> {
+        Some(Soundex {
+            code: soundex(word)?,
+            len: word.len(),
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_match(&self, line: &str) -> bool {
+        tokens(line).any(|(_, token)| soundex(token) == Some(self.code))
+    }
+
+    /// Returns the byte range of every matching token in `line`, leaving out
+    /// punctuation around it.
+    pub(crate) fn find_ranges(&self, line: &str) -> Vec<Range<usize>> {
+        tokens(line)
+            .filter(|(_, token)| soundex(token) == Some(self.code))
+            .map(|(start, token)| {
+                let word = token.trim_matches(|c: char| !c.is_alphanumeric());
+                let start = start + (word.as_ptr() as usize - token.as_ptr() as usize);
+                start..start + word.len()
+            })
+            .collect()
+    }
+}
+
+/// Returns the whitespace-separated tokens of `line` with their byte offsets.
+fn tokens(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split_whitespace()
+        .map(move |token| (token.as_ptr() as usize - line.as_ptr() as usize, token))
+}
+
+/// Returns the four-character Soundex code of `word`, such as `R163` for
+/// "Robert", or `None` if it has no ASCII letters.
+///
+/// The first letter is kept and the rest become digits for their consonant
+/// group, with adjacent letters of the same group coded once, even when an `h`
+/// or `w` comes between them. A vowel does separate them. Anything that isn't
+/// an ASCII letter is skipped.
+pub(crate) fn soundex(word: &str) -> Option<[u8; 4]> {
+    let mut letters = word
+        .bytes()
+        .filter(u8::is_ascii_alphabetic)
+        .map(|b| b.to_ascii_uppercase());
+    let first = letters.next()?;
+
+    let mut code = [first, b'0', b'0', b'0'];
+    let mut len = 1;
+    let mut last = digit(first);
+    for letter in letters {
+        if len == code.len() {
+            break;
+        }
+        let current = digit(letter);
+        if let Some(group) = current
+            && current != last
+        {
+            code[len] = group;
+            len += 1;
+        }
+        if !matches!(letter, b'H' | b'W') {
+            last = current;
+        }
+    }
+
+    Some(code)
+}
+
+/// Returns the digit of an uppercase consonant's group, or `None` for vowels,
+/// `H`, `W` and `Y`.
+fn digit(letter: u8) -> Option<u8> {
+    match letter {
+        b'B' | b'F' | b'P' | b'V' => Some(b'1'),
+        b'C' | b'G' | b'J' | b'K' | b'Q' | b'S' | b'X' | b'Z' => Some(b'2'),
+        b'D' | b'T' => Some(b'3'),
+        b'L' => Some(b'4'),
+        b'M' | b'N' => Some(b'5'),
+        b'R' => Some(b'6'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(word: &str) -> String {
+        String::from_utf8(soundex(word).unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn classic_codes() {
+        assert_eq!(code("Robert"), "R163");
+        assert_eq!(code("Rupert"), "R163");
+        assert_eq!(code("Rubin"), "R150");
+        assert_eq!(code("Ashcraft"), "A261");
+        assert_eq!(code("Tymczak"), "T522");
+        assert_eq!(code("Pfister"), "P236");
+        assert_eq!(code("Honeyman"), "H555");
+        assert_eq!(code("Lee"), "L000");
+        assert_eq!(code("o'brien"), "O165");
+        assert_eq!(soundex("1234"), None);
+        assert_eq!(soundex(""), None);
+    }
+
+    #[test]
+    fn tokens_match_by_code() {
+        let robert = Soundex::new("Robert").unwrap();
+        assert!(robert.is_match("signed by Rupert Smith"));
+        assert!(!robert.is_match("signed by Richard Smith"));
+        assert_eq!(robert.find_ranges("(Rupert), robert"), [1..7, 10..16]);
+        assert!(Soundex::new("42").is_none());
+    }
+}
@@ -0,0 +1,163 @@
+//! Searching the output of a preprocessor command, for `--pre`.
+//!
+//! Each file goes through the command, given its path as the only argument,
+//! and what the command prints is searched in place of the file. That is how
+//! formats such as PDF can be searched, with a converter like `pdftotext`.
+
+use std::{
+    ffi::OsStr,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    process::{Child, ChildStdout, Command, Stdio},
+};
+
+/// Whether a file named `name` goes through the preprocessor, given the
+/// `--pre-glob` pattern if there is one.
+///
+/// In the pattern, `*` stands for any run of characters and `?` for any one.
+pub(crate) fn applies(glob: Option<&str>, path: &Path) -> bool {
+    let Some(glob) = glob else {
+        return true;
+    };
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    glob_matches(glob, &name.to_string_lossy())
+}
+
+fn glob_matches(glob: &str, name: &str) -> bool {
+    let (glob, name): (Vec<char>, Vec<char>) = (glob.chars().collect(), name.chars().collect());
+    let (mut g, mut n) = (0, 0);
+    // Where the last `*` was, and where in `name` it next tries to stop.
+    let mut retry = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                retry = Some((g, n + 1));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match retry {
+                Some((star, next)) => {
+                    (g, n) = (star + 1, next);
+                    retry = Some((star, next + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Starts `command` on `path` and returns its output as it is printed.
+///
+/// The command's stderr goes to ours. Reading fails at the end of the output
+/// if the command didn't succeed, so its failure is reported like any other
+/// file that couldn't be read.
+///
+/// # Errors
+///
+/// Returns an error if the command can't be started.
+pub(crate) fn open(command: &OsStr, path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut child = Command::new(command)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("couldn't run '{}': {err}", command.to_string_lossy()),
+            )
+        })?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    Ok(Box::new(Output {
+        command: command.to_string_lossy().into_owned(),
+        stdout: BufReader::new(stdout),
+        child,
+        finished: false,
+    }))
+}
+
+/// What a preprocessor prints, read as it goes.
+struct Output {
+    command: String,
+    stdout: BufReader<ChildStdout>,
+    child: Child,
+    /// Whether the command has been waited for.
+    finished: bool,
+}
+
+impl Output {
+    /// Waits for the command once its output has ended, failing if it did.
+    fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "'{}' failed: {status}",
+                self.command
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Read for Output {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            self.finish()?;
+        }
+        Ok(read)
+    }
+}
+
+impl BufRead for Output {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.stdout.fill_buf()?.is_empty() {
+            self.finish()?;
+        }
+        self.stdout.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.stdout.consume(amount);
+    }
+}
+
+impl Drop for Output {
+    /// Stops a command whose output wasn't read to the end, as after `-m`,
+    /// so it doesn't linger.
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_match_file_names() {
+        assert!(glob_matches("*.pdf", "report.pdf"));
+        assert!(glob_matches("*.pdf", ".pdf"));
+        assert!(!glob_matches("*.pdf", "report.pdf.txt"));
+        assert!(glob_matches("re?ort*", "report.pdf"));
+        assert!(glob_matches("*a*b*", "xxaxxbxx"));
+        assert!(!glob_matches("*a*b", "xxaxxbxx"));
+        assert!(glob_matches("", ""));
+        assert!(!glob_matches("", "a"));
+
+        assert!(applies(Some("*.pdf"), Path::new("docs/report.pdf")));
+        assert!(!applies(Some("*.pdf"), Path::new("docs/notes.txt")));
+        assert!(applies(None, Path::new("docs/notes.txt")));
+    }
+}
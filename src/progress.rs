@@ -0,0 +1,119 @@
+//! Progress shown on stderr while searching.
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// How often the progress line is redrawn.
+const INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many characters of the current path are shown; longer paths keep
+/// their end, so the line doesn't wrap and break the `\r` redraw.
+const PATH_WIDTH: usize = 40;
+
+/// A progress line on stderr, redrawn in place with `\r`.
+///
+/// Nothing is ever written to stdout, so results stay clean when piped.
+pub(crate) struct Progress {
+    started: Instant,
+    last_drawn: Option<Instant>,
+    searched: usize,
+    matched_lines: usize,
+    /// How many files there are to search, if known.
+    total: Option<usize>,
+    /// The length of the line last drawn, so it can be cleared.
+    width: usize,
+}
+
+impl Progress {
+    /// Starts timing a search over `total` files, or over files still being
+    /// found when that isn't known.
+    pub(crate) fn new(total: Option<usize>) -> Progress {
+        Progress {
+            started: Instant::now(),
+            last_drawn: None,
+            searched: 0,
+            matched_lines: 0,
+            total,
+            width: 0,
+        }
+    }
+
+    /// Records that `path` has been searched and had `matched_lines` matching
+    /// lines, redrawing the line if it hasn't been for a while.
+    pub(crate) fn file_done(&mut self, path: &str, matched_lines: usize) {
+        self.searched += 1;
+        self.matched_lines += matched_lines;
+
+        let now = Instant::now();
+        if self
+            .last_drawn
+            .is_some_and(|drawn| now.duration_since(drawn) < INTERVAL)
+        {
+            return;
+        }
+        self.last_drawn = Some(now);
+
+        let line = self.status(path, self.started.elapsed());
+        // Pad with spaces in case the previous line was longer.
+        let padding = self.width.saturating_sub(line.len());
+        self.width = line.len();
+        let _ = write!(io::stderr(), "\r{line}{:padding$}", "");
+    }
+
+    /// Returns the line to draw after searching `path`.
+    fn status(&self, path: &str, elapsed: Duration) -> String {
+        let searched = match self.total {
+            Some(total) => format!("{}/{total}", self.searched),
+            None => self.searched.to_string(),
+        };
+        let chars = path.chars().count();
+        let path = if chars > PATH_WIDTH {
+            let tail: String = path.chars().skip(chars - (PATH_WIDTH - 1)).collect();
+            format!("…{tail}")
+        } else {
+            path.to_string()
+        };
+        format!(
+            "searched {searched} files, {} matching lines in {:.1}s: {path}",
+            self.matched_lines,
+            elapsed.as_secs_f64()
+        )
+    }
+
+    /// Clears the progress line.
+    pub(crate) fn finish(&mut self) {
+        if self.width > 0 {
+            let _ = write!(io::stderr(), "\r{:width$}\r", "", width = self.width);
+            self.width = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_shows_counts_and_the_end_of_long_paths() {
+        let mut progress = Progress::new(Some(10));
+        progress.searched = 3;
+        progress.matched_lines = 7;
+        let elapsed = Duration::from_millis(1500);
+        assert_eq!(
+            progress.status("src/main.rs", elapsed),
+            "searched 3/10 files, 7 matching lines in 1.5s: src/main.rs"
+        );
+
+        progress.total = None;
+        let long = format!("{}/end.rs", "dir".repeat(20));
+        let status = progress.status(&long, elapsed);
+        assert!(status.starts_with("searched 3 files, 7 matching lines in 1.5s: …"));
+        assert!(status.ends_with("dir/end.rs"));
+        assert_eq!(
+            status.rsplit(": ").next().unwrap().chars().count(),
+            PATH_WIDTH
+        );
+    }
+}
@@ -0,0 +1,1119 @@
+//! Finding queries in lines and texts, the core every other part builds on.
+//!
+//! Nothing here reads files or the environment, or starts threads, so this
+//! part of the crate can be used wherever there is text to search.
+
+use std::{
+    collections::BTreeMap,
+    ops::{ControlFlow, Range, RangeInclusive},
+};
+
+use crate::{Matcher, SortOrder, phonetic::Soundex};
+
+/// Whether a query is compared case-sensitively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseMode {
+    /// Characters must match exactly.
+    Sensitive,
+    /// Characters are compared by their lowercase forms.
+    Insensitive,
+}
+
+/// A matching line, borrowed from the text that was searched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'a> {
+    /// The 1-based number of the line.
+    pub line_number: usize,
+    /// The line, without its ending.
+    pub line: &'a str,
+    /// The `(start, end)` byte offsets in `line` of every occurrence of the
+    /// query, as `match_positions` gives them.
+    pub matches: Vec<(usize, usize)>,
+}
+
+impl Match<'_> {
+    /// Copies the line out, so the match no longer borrows the text.
+    pub fn to_owned(&self) -> OwnedMatch {
+        OwnedMatch {
+            line_number: self.line_number,
+            line: self.line.to_string(),
+            matches: self.matches.clone(),
+        }
+    }
+}
+
+/// A matching line that owns its text, as `search_file` returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedMatch {
+    /// The 1-based number of the line.
+    pub line_number: usize,
+    /// The line, without its ending.
+    pub line: String,
+    /// The `(start, end)` byte offsets in `line` of every occurrence.
+    pub matches: Vec<(usize, usize)>,
+}
+
+/// A query prepared once for searching any number of lines or texts.
+///
+/// Building a `Searcher` does the per-query work (such as case-folding the
+/// query) up front, so `run` builds a single one and reuses it for every file.
+/// The enabled options are layered: the query must occur in the line, compared
+/// according to the `CaseMode`, and with `whole_word` one of its occurrences
+/// must also be delimited by word boundaries. Word boundaries are checked on
+/// the case-folded line, so both options compose.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::{CaseMode, Searcher};
+///
+/// let searcher = Searcher::new("error", CaseMode::Insensitive, true);
+/// assert!(searcher.is_match("Fatal ERROR: disk full"));
+/// assert!(!searcher.is_match("no errors here"));
+/// assert_eq!(searcher.find_ranges("Error, error"), vec![0..5, 7..12]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Searcher {
+    pub(crate) needle: String,
+    case: CaseMode,
+    whole_word: bool,
+}
+
+impl Searcher {
+    /// Prepares `query` for searching.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The string to search for.
+    /// * `case` - Whether the query is compared case-sensitively.
+    /// * `whole_word` - Whether the query must occur as a whole word.
+    pub fn new(query: &str, case: CaseMode, whole_word: bool) -> Searcher {
+        let needle = match case {
+            CaseMode::Sensitive => query.to_owned(),
+            CaseMode::Insensitive => query.to_lowercase(),
+        };
+
+        Searcher {
+            needle,
+            case,
+            whole_word,
+        }
+    }
+
+    /// Returns the lines of `contents` that match, in order.
+    pub fn search<'a>(&self, contents: &'a str) -> Vec<&'a str> {
+        if self.case == CaseMode::Sensitive && !self.whole_word {
+            return search_iter(&self.needle, contents).collect();
+        }
+
+        let mut scratch = String::new();
+        contents
+            .lines()
+            .filter(|line| self.matches(line, &mut scratch))
+            .collect()
+    }
+
+    /// Returns the lines of `contents` that match along with their numbers,
+    /// in order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lilgrep::{CaseMode, Searcher};
+    ///
+    /// let searcher = Searcher::new("fish", CaseMode::Sensitive, false);
+    /// let matches = searcher.search_matches("one fish\ntwo\nred fish");
+    /// assert_eq!(matches[1].line_number, 3);
+    /// assert_eq!(matches[1].line, "red fish");
+    /// assert_eq!(matches[1].matches, [(4, 8)]);
+    /// ```
+    pub fn search_matches<'a>(&self, contents: &'a str) -> Vec<Match<'a>> {
+        let mut scratch = String::new();
+        (1..)
+            .zip(contents.lines())
+            .filter(|(_, line)| self.matches(line, &mut scratch))
+            .map(|(line_number, line)| Match {
+                line_number,
+                line,
+                // As `match_positions`, but with this searcher's options.
+                matches: self
+                    .find_ranges(line)
+                    .into_iter()
+                    .map(|range| (range.start, range.end))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Counts the lines of `contents` that match, as `--count` does.
+    ///
+    /// A line counts once however many times it contains the query; to count
+    /// each occurrence, use `count_matches`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lilgrep::{CaseMode, Searcher};
+    ///
+    /// let searcher = Searcher::new("error", CaseMode::Insensitive, false);
+    /// assert_eq!(searcher.count_lines("error: ERROR\nok\nerror"), 2);
+    /// ```
+    pub fn count_lines(&self, contents: &str) -> usize {
+        self.search(contents).len()
+    }
+
+    /// Counts every occurrence of the query in `contents`, as
+    /// `--count-matches` does.
+    ///
+    /// Occurrences are counted the way `find_ranges` finds them, so a line
+    /// containing the query twice counts twice and `aa` occurs twice in `aaaa`.
+    /// To count matching lines instead, use `count_lines`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lilgrep::{CaseMode, Searcher};
+    ///
+    /// let searcher = Searcher::new("error", CaseMode::Insensitive, false);
+    /// assert_eq!(searcher.count_matches("error: ERROR\nok\nerror"), 3);
+    /// ```
+    pub fn count_matches(&self, contents: &str) -> usize {
+        self.search(contents)
+            .into_iter()
+            .map(|line| self.find_ranges(line).len())
+            .sum()
+    }
+
+    /// Returns `line` with every match, as found by `find_ranges`, replaced by
+    /// `replacement`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lilgrep::{CaseMode, Searcher};
+    ///
+    /// let searcher = Searcher::new("cat", CaseMode::Insensitive, false);
+    /// assert_eq!(searcher.replace("Cat sat on a cat", "dog"), "dog sat on a dog");
+    /// ```
+    pub fn replace(&self, line: &str, replacement: &str) -> String {
+        replace_ranges(line, self.find_ranges(line), replacement)
+    }
+
+    /// Checks whether a single line matches.
+    pub fn is_match(&self, line: &str) -> bool {
+        self.matches(line, &mut String::new())
+    }
+
+    /// Checks whether `line` as a whole is the query, as `-x` does, compared
+    /// according to the `CaseMode`; `whole_word` makes no difference.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lilgrep::{CaseMode, Searcher};
+    ///
+    /// let searcher = Searcher::new("active", CaseMode::Insensitive, false);
+    /// assert!(searcher.is_line_match("Active"));
+    /// assert!(!searcher.is_line_match("Inactive"));
+    /// ```
+    pub fn is_line_match(&self, line: &str) -> bool {
+        match self.case {
+            CaseMode::Sensitive => line == self.needle,
+            CaseMode::Insensitive => line.to_lowercase() == self.needle,
+        }
+    }
+
+    /// Returns the byte ranges of every match in `line`.
+    ///
+    /// Matches are found leftmost first and never overlap. The ranges always
+    /// index `line` itself, even when case-folding changed the length of some
+    /// characters; a match starting or ending inside the lowercase form of a
+    /// character covers that whole character. An empty query has no ranges.
+    pub fn find_ranges(&self, line: &str) -> Vec<Range<usize>> {
+        self.ranges(line, false)
+    }
+
+    /// Returns the byte ranges of every match in `line` starting at any
+    /// position, so matches may overlap.
+    ///
+    /// After each match the search resumes one character after where it
+    /// started, rather than where it ended as in `find_ranges`. With
+    /// `CaseMode::Insensitive` that is a character of the lowercased line, and
+    /// matches that cover the same characters of `line` are only given once.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use lilgrep::{CaseMode, Searcher};
+    ///
+    /// let searcher = Searcher::new("aba", CaseMode::Sensitive, false);
+    /// assert_eq!(searcher.find_ranges("ababab"), vec![0..3]);
+    /// assert_eq!(searcher.find_overlapping_ranges("ababab"), vec![0..3, 2..5]);
+    /// ```
+    pub fn find_overlapping_ranges(&self, line: &str) -> Vec<Range<usize>> {
+        self.ranges(line, true)
+    }
+
+    fn ranges(&self, line: &str, overlapping: bool) -> Vec<Range<usize>> {
+        if self.needle.is_empty() {
+            return Vec::new();
+        }
+
+        match self.case {
+            CaseMode::Sensitive => occurrences(line, &self.needle, self.whole_word, overlapping),
+            CaseMode::Insensitive if line.is_ascii() => occurrences(
+                &line.to_ascii_lowercase(),
+                &self.needle,
+                self.whole_word,
+                overlapping,
+            ),
+            CaseMode::Insensitive => {
+                let (folded, origins) = fold_with_origins(line);
+                let mut ranges: Vec<_> =
+                    occurrences(&folded, &self.needle, self.whole_word, overlapping)
+                        .into_iter()
+                        .map(|range| {
+                            let last = origins[range.end - 1];
+                            let last_len = line[last..].chars().next().map_or(0, char::len_utf8);
+                            origins[range.start]..last + last_len
+                        })
+                        .collect();
+                ranges.dedup();
+                ranges
+            }
+        }
+    }
+
+    pub(crate) fn matches(&self, line: &str, scratch: &mut String) -> bool {
+        let found = match self.case {
+            CaseMode::Sensitive => line.contains(self.needle.as_str()),
+            CaseMode::Insensitive => contains_ignore_case(line, &self.needle, scratch),
+        };
+
+        found
+            && (!self.whole_word
+                || match self.case {
+                    CaseMode::Sensitive => contains_word(line, &self.needle),
+                    CaseMode::Insensitive => contains_word(&line.to_lowercase(), &self.needle),
+                })
+    }
+}
+
+/// Lowercases `line`, recording for every byte of the result the offset in
+/// `line` of the character it came from.
+///
+/// `str::to_lowercase` only differs from lowercasing each character on its own
+/// for the final sigma, whose two forms are the same length, so the recorded
+/// offsets line up with its output.
+fn fold_with_origins(line: &str) -> (String, Vec<usize>) {
+    let folded = line.to_lowercase();
+    let mut origins = Vec::with_capacity(folded.len());
+
+    for (offset, c) in line.char_indices() {
+        let len: usize = c.to_lowercase().map(char::len_utf8).sum();
+        origins.extend(std::iter::repeat_n(offset, len));
+    }
+
+    (folded, origins)
+}
+
+/// Finds the leftmost occurrence of `needle` in `haystack` starting at byte
+/// `from`.
+///
+/// With `whole_word`, only occurrences whose neighbouring characters, if any,
+/// are not word characters (alphanumerics or `_`) count. Every starting
+/// position is tried, so an occurrence overlapping a rejected one is still
+/// found.
+fn next_occurrence(
+    haystack: &str,
+    needle: &str,
+    mut from: usize,
+    whole_word: bool,
+) -> Option<Range<usize>> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    while let Some(offset) = haystack[from..].find(needle) {
+        let start = from + offset;
+        let end = start + needle.len();
+
+        let before = haystack[..start].chars().next_back();
+        let after = haystack[end..].chars().next();
+        if !whole_word || !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char) {
+            return Some(start..end);
+        }
+
+        from = start + haystack[start..].chars().next()?.len_utf8();
+    }
+
+    None
+}
+
+/// Collects the leftmost, non-overlapping occurrences of a non-empty `needle`,
+/// or with `overlapping` the occurrences starting at every position.
+fn occurrences(
+    haystack: &str,
+    needle: &str,
+    whole_word: bool,
+    overlapping: bool,
+) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut from = 0;
+
+    while let Some(range) = next_occurrence(haystack, needle, from, whole_word) {
+        from = if overlapping {
+            range.start
+                + haystack[range.start..]
+                    .chars()
+                    .next()
+                    .map_or(1, char::len_utf8)
+        } else {
+            range.end
+        };
+        ranges.push(range);
+    }
+
+    ranges
+}
+
+/// Checks whether `query` occurs in `line` as a whole word.
+fn contains_word(line: &str, query: &str) -> bool {
+    next_occurrence(line, query, 0, true).is_some()
+}
+
+/// Searches for a query string in the given contents.
+///
+/// This search is case-sensitive. Rather than checking every line, the query
+/// is searched for across the whole of `contents` and each hit is mapped back
+/// to the line containing it, so lines without a match are skipped entirely.
+/// Lines are split exactly as `str::lines` does.
+///
+/// # Arguments
+///
+/// * `query` - The string to search for.
+/// * `contents` - The text to search within.
+///
+/// # Returns
+///
+/// A vector of lines that contain the query string, each appearing once.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search;
+///
+/// let contents = "Rust:
+/// safe, fast, productive.
+/// Pick three.";
+/// let results = search("duct", contents);
+/// assert_eq!(results, vec!["safe, fast, productive."]);
+/// ```
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    search_iter(query, contents).collect()
+}
+
+/// Searches lines that are already split, returning those containing `query`.
+///
+/// This is the case-sensitive comparison `search` makes, for callers that keep
+/// their own line buffers. Each line is checked on its own, so a line may
+/// contain newlines, but a query can't match across two lines.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_lines;
+///
+/// let lines = ["Rust:", "safe, fast, productive.", "Pick three."];
+/// assert_eq!(search_lines("duct", &lines), ["safe, fast, productive."]);
+/// ```
+pub fn search_lines<'a>(query: &str, lines: &[&'a str]) -> Vec<&'a str> {
+    lines
+        .iter()
+        .copied()
+        .filter(|line| line.contains(query))
+        .collect()
+}
+
+/// Returns the lines of `contents` that are exactly `query`, as `grep -x`
+/// finds them, rather than merely containing it.
+///
+/// The comparison is case-sensitive; `Searcher::is_line_match` can ignore case.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_whole_line;
+///
+/// let contents = "Active\nInactive\nActive\r\nActive now";
+/// assert_eq!(search_whole_line("Active", contents), ["Active", "Active"]);
+/// ```
+pub fn search_whole_line<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    contents.lines().filter(|line| *line == query).collect()
+}
+
+/// Searches raw bytes for lines containing `query`, for input that isn't
+/// valid UTF-8.
+///
+/// Lines end with `\n`, and a `\r` before it is left out, as with
+/// `str::lines`. The comparison is byte for byte, so it is case-sensitive.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_bytes;
+///
+/// let contents = b"\xff\xfe\nfound it\r\n\x80 found\n";
+/// assert_eq!(search_bytes(b"found", contents), [&b"found it"[..], b"\x80 found"]);
+/// ```
+pub fn search_bytes<'a>(query: &[u8], contents: &'a [u8]) -> Vec<&'a [u8]> {
+    let contents = contents.strip_suffix(b"\n").unwrap_or(contents);
+    if contents.is_empty() {
+        return Vec::new();
+    }
+    contents
+        .split(|&byte| byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| query.is_empty() || line.windows(query.len()).any(|window| window == query))
+        .collect()
+}
+
+/// Searches `contents` with `matcher`, returning the matching lines in order.
+///
+/// This is how `search` and the other functions here work, but with any
+/// `Matcher`, including one of your own.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::{CaseMode, Searcher, search_with_matcher};
+///
+/// let searcher = Searcher::new("fish", CaseMode::Sensitive, true);
+/// let contents = "one fish\ncatfish\nno fish here";
+/// assert_eq!(
+///     search_with_matcher(&searcher, contents),
+///     ["one fish", "no fish here"]
+/// );
+/// ```
+pub fn search_with_matcher<'a, M: Matcher + ?Sized>(
+    matcher: &M,
+    contents: &'a str,
+) -> Vec<&'a str> {
+    matcher.search(contents)
+}
+
+/// Lazily yields the lines of `contents` that contain `query`.
+///
+/// This is the case-sensitive search performed by `search`, one line at a time,
+/// so callers can stop early or `.take(n)` without collecting every match.
+///
+/// The returned iterator borrows both arguments: `query` is consulted again on
+/// every call to `next` and the yielded lines are slices of `contents`, so the
+/// iterator can't outlive either of them. The lines themselves only borrow
+/// `contents` (for `'a`), and remain usable once the iterator and `query` are
+/// gone.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_iter;
+///
+/// let contents = "one fish\ntwo fish\nred fish\nblue fish";
+/// let first_two: Vec<&str> = search_iter("fish", contents).take(2).collect();
+/// assert_eq!(first_two, vec!["one fish", "two fish"]);
+/// ```
+pub fn search_iter<'q, 'a>(
+    query: &'q str,
+    contents: &'a str,
+) -> impl Iterator<Item = &'a str> + use<'q, 'a> {
+    // An empty query matches every line, and a query containing a newline can
+    // never match a single line; neither benefits from the whole-text scan.
+    let whole_text = !query.is_empty() && !query.contains('\n');
+
+    let hits = whole_text.then(|| LiteralLines {
+        query,
+        contents,
+        hits: Hits::new(query, contents),
+        next_line: 0,
+    });
+    let by_line = (!whole_text).then(|| contents.lines().filter(move |line| line.contains(query)));
+
+    hits.into_iter()
+        .flatten()
+        .chain(by_line.into_iter().flatten())
+}
+
+/// Case-sensitive search that scans the whole of `contents` for `query` and
+/// maps each hit back to its line.
+struct LiteralLines<'q, 'a> {
+    query: &'q str,
+    contents: &'a str,
+    hits: Hits<'q, 'a>,
+    next_line: usize,
+}
+
+/// Where `query` occurs in `contents`, without overlaps, as byte offsets.
+///
+/// Queries of `HORSPOOL_MIN` bytes or more are found with Boyer-Moore-Horspool,
+/// which skips ahead by up to the query's length after each mismatch. Shorter
+/// ones use `str::match_indices`, whose vectorised search beats it up to 32
+/// bytes.
+enum Hits<'q, 'a> {
+    Std(std::str::MatchIndices<'a, &'q str>),
+    Horspool(Box<Horspool<'q, 'a>>),
+}
+
+/// The shortest query that `Hits` searches for with Boyer-Moore-Horspool.
+const HORSPOOL_MIN: usize = 33;
+
+impl<'q, 'a> Hits<'q, 'a> {
+    fn new(query: &'q str, contents: &'a str) -> Hits<'q, 'a> {
+        if query.len() < HORSPOOL_MIN {
+            return Hits::Std(contents.match_indices(query));
+        }
+        let needle = query.as_bytes();
+        let last = needle.len() - 1;
+        let mut skip = [needle.len(); 256];
+        for (i, &byte) in needle[..last].iter().enumerate() {
+            skip[usize::from(byte)] = last - i;
+        }
+        Hits::Horspool(Box::new(Horspool {
+            needle,
+            haystack: contents.as_bytes(),
+            skip,
+            pos: 0,
+        }))
+    }
+}
+
+impl Iterator for Hits<'_, '_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        match self {
+            Hits::Std(hits) => hits.next().map(|(hit, _)| hit),
+            Hits::Horspool(horspool) => horspool.next(),
+        }
+    }
+}
+
+struct Horspool<'q, 'a> {
+    needle: &'q [u8],
+    haystack: &'a [u8],
+    /// How far the window may move when its last byte is the index.
+    skip: [usize; 256],
+    /// Where the next window starts.
+    pos: usize,
+}
+
+impl Iterator for Horspool<'_, '_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let (needle, haystack) = (self.needle, self.haystack);
+        let last = needle.len() - 1;
+
+        while self.pos + needle.len() <= haystack.len() {
+            let pos = self.pos;
+            let byte = haystack[pos + last];
+            if byte == needle[last] && haystack[pos..pos + last] == needle[..last] {
+                self.pos = pos + needle.len();
+                // The query is UTF-8, so a byte-wise hit starts on a character.
+                return Some(pos);
+            }
+            self.pos = pos + self.skip[usize::from(byte)];
+        }
+        None
+    }
+}
+
+impl<'a> Iterator for LiteralLines<'_, 'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let contents = self.contents;
+
+        loop {
+            let hit = self.hits.next()?;
+
+            // Further hits on a line that has already been reported.
+            if hit < self.next_line {
+                continue;
+            }
+
+            let start = contents[self.next_line..hit]
+                .rfind('\n')
+                .map_or(self.next_line, |i| self.next_line + i + 1);
+            let (end, next) = match contents[hit..].find('\n') {
+                Some(i) => (hit + i, hit + i + 1),
+                None => (contents.len(), contents.len()),
+            };
+            self.next_line = next;
+
+            let line = &contents[start..end];
+            let line = match line.strip_suffix('\r') {
+                Some(stripped) if next > end => stripped,
+                _ => line,
+            };
+
+            // A query ending in `\r` can hit the carriage return that `lines`
+            // strips from a `\r\n` ending, so such hits are confirmed on the line.
+            if hit + self.query.len() <= start + line.len() || line.contains(self.query) {
+                return Some(line);
+            }
+        }
+    }
+}
+
+/// Calls `on_match` with each line of `contents` that contains `query`, as
+/// soon as it is found, for callers that show matches as they come.
+///
+/// The comparison is that of `search`, and nothing is collected. When
+/// `on_match` returns `ControlFlow::Break`, the search stops there and the
+/// break is returned; otherwise every line is searched and
+/// `ControlFlow::Continue` is returned.
+///
+/// # Example
+///
+/// ```
+/// use std::ops::ControlFlow;
+///
+/// use lilgrep::search_callback;
+///
+/// let contents = "one fish\ntwo fish\nred fish\nblue fish";
+/// let mut seen = Vec::new();
+/// let stopped = search_callback("fish", contents, |found| {
+///     seen.push(found.line_number);
+///     if found.line.starts_with("red") {
+///         return ControlFlow::Break(found.line);
+///     }
+///     ControlFlow::Continue(())
+/// });
+/// assert_eq!(stopped, ControlFlow::Break("red fish"));
+/// assert_eq!(seen, [1, 2, 3]);
+/// ```
+pub fn search_callback<'a, B>(
+    query: &str,
+    contents: &'a str,
+    mut on_match: impl FnMut(Match<'a>) -> ControlFlow<B>,
+) -> ControlFlow<B> {
+    for (line_number, line) in (1..).zip(contents.lines()) {
+        if line.contains(query) {
+            on_match(Match {
+                line_number,
+                line,
+                matches: match_positions(query, line, false),
+            })?;
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+/// Searches for a query string in the given contents, ignoring case.
+///
+/// # Arguments
+///
+/// * `query` - The string to search for.
+/// * `contents` - The text to search within.
+///
+/// # Returns
+///
+/// A vector of lines that contain the query string, case-insensitively.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_case_insensitive;
+///
+/// let contents = "Rust:
+/// safe, fast, productive.
+/// Pick three.";
+/// let results = search_case_insensitive("rUsT", contents);
+/// assert_eq!(results, vec!["Rust:"]);
+/// ```
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    Searcher::new(query, CaseMode::Insensitive, false).search(contents)
+}
+
+/// Searches for lines with a word that sounds like `query`, as judged by
+/// Soundex: a whitespace-separated word matches when it has the same code,
+/// ignoring case and any punctuation in it.
+///
+/// Soundex was made for English names and only looks at ASCII letters, so
+/// this is no use for other languages. A query without ASCII letters matches
+/// nothing.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_soundex;
+///
+/// let contents = "Robert Smith, 1902\nRichard Smyth, 1911\nRupert Smith, 1930";
+/// assert_eq!(
+///     search_soundex("Robert", contents),
+///     ["Robert Smith, 1902", "Rupert Smith, 1930"]
+/// );
+/// ```
+pub fn search_soundex<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    let Some(soundex) = Soundex::new(query) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .filter(|line| soundex.is_match(line))
+        .collect()
+}
+
+/// Searches for lines containing at least one of `queries`.
+///
+/// This search is case-sensitive; `-e` and `--any` do the same for the command
+/// line, where `ignore_case` applies too. Empty queries are ignored, so with no
+/// others no line matches.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_any;
+///
+/// let contents = "timeout, will retry\nretry succeeded\ndisk full";
+/// assert_eq!(
+///     search_any(&["timeout", "disk", ""], contents),
+///     ["timeout, will retry", "disk full"]
+/// );
+/// ```
+pub fn search_any<'a>(queries: &[&str], contents: &'a str) -> Vec<&'a str> {
+    contents
+        .lines()
+        .filter(|line| {
+            queries
+                .iter()
+                .any(|query| !query.is_empty() && line.contains(query))
+        })
+        .collect()
+}
+
+/// Searches for lines containing every one of `queries`, in any order.
+///
+/// This search is case-sensitive; `Config::all_of` does the same for the
+/// command line, where `ignore_case` applies too. With no queries, every line
+/// matches.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_all;
+///
+/// let contents = "timeout, will retry\nretry succeeded\nretry after timeout";
+/// assert_eq!(
+///     search_all(&["timeout", "retry"], contents),
+///     ["timeout, will retry", "retry after timeout"]
+/// );
+/// ```
+pub fn search_all<'a>(queries: &[&str], contents: &'a str) -> Vec<&'a str> {
+    contents
+        .lines()
+        .filter(|line| queries.iter().all(|query| line.contains(query)))
+        .collect()
+}
+
+/// Returns the byte range of every occurrence of `query` in `line`.
+///
+/// Occurrences are found leftmost first and never overlap: after each one the
+/// search resumes where it ended, so `aa` occurs twice in `aaaa` and once in
+/// `aaa`, and adjacent occurrences give adjacent ranges. Every range is a valid
+/// slice of `line`, even when `ignore_case` meets characters whose lowercase
+/// form has a different length; a match covering part of such a form covers
+/// the whole character. An empty query has no occurrences.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::find_all_ranges;
+///
+/// assert_eq!(find_all_ranges("ab", "abab ab", false), vec![0..2, 2..4, 5..7]);
+/// assert_eq!(find_all_ranges("straße", "STRASSE Straße", true), vec![8..15]);
+/// ```
+pub fn find_all_ranges(query: &str, line: &str, ignore_case: bool) -> Vec<Range<usize>> {
+    let case = if ignore_case {
+        CaseMode::Insensitive
+    } else {
+        CaseMode::Sensitive
+    };
+    Searcher::new(query, case, false).find_ranges(line)
+}
+
+/// Returns the byte range of every occurrence of `query` in `line`, wherever
+/// it starts, so occurrences may overlap.
+///
+/// This is `find_all_ranges` resuming one character after the start of each
+/// occurrence instead of at its end; see `Searcher::find_overlapping_ranges`.
+/// Offsets are into `line` itself with `ignore_case` too.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::find_overlapping_ranges;
+///
+/// assert_eq!(find_overlapping_ranges("aa", "aaaa", false), vec![0..2, 1..3, 2..4]);
+/// assert_eq!(find_overlapping_ranges("ABA", "ababab", true), vec![0..3, 2..5]);
+/// ```
+pub fn find_overlapping_ranges(query: &str, line: &str, ignore_case: bool) -> Vec<Range<usize>> {
+    let case = if ignore_case {
+        CaseMode::Insensitive
+    } else {
+        CaseMode::Sensitive
+    };
+    Searcher::new(query, case, false).find_overlapping_ranges(line)
+}
+
+/// Returns the `(start, end)` byte offsets of every occurrence of `query` in
+/// `line`.
+///
+/// This is `find_all_ranges` with the ranges as pairs: occurrences are matched
+/// left to right without overlapping, so `aa` occurs at `(0, 2)` and `(2, 4)`
+/// in `aaaa`, never at `(1, 3)`.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::match_positions;
+///
+/// assert_eq!(match_positions("aa", "aaaa", false), vec![(0, 2), (2, 4)]);
+/// assert_eq!(match_positions("ab", "Ab ab", true), vec![(0, 2), (3, 5)]);
+/// ```
+pub fn match_positions(query: &str, line: &str, ignore_case: bool) -> Vec<(usize, usize)> {
+    find_all_ranges(query, line, ignore_case)
+        .into_iter()
+        .map(|range| (range.start, range.end))
+        .collect()
+}
+
+/// Maps the number of each line of `contents` containing `query`, counting
+/// from 1, to the `(start, end)` byte offsets `match_positions` gives for it.
+///
+/// Iterating the map visits the lines in order, and lines without a match are
+/// left out. The search is case-sensitive.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_grouped;
+///
+/// let grouped = search_grouped("to", "to be\nor not\nto go to");
+/// assert_eq!(grouped[&1], [(0, 2)]);
+/// assert_eq!(grouped[&3], [(0, 2), (6, 8)]);
+/// assert!(!grouped.contains_key(&2));
+/// ```
+pub fn search_grouped(query: &str, contents: &str) -> BTreeMap<usize, Vec<(usize, usize)>> {
+    (1..)
+        .zip(contents.lines())
+        .map(|(number, line)| (number, match_positions(query, line, false)))
+        .filter(|(_, positions)| !positions.is_empty())
+        .collect()
+}
+
+/// Returns each line of `contents` containing `query`, along with the ranges
+/// `find_all_ranges` gives for it.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::search_with_spans;
+///
+/// let contents = "Rust:\nsafe, fast, productive.\nTrust me.";
+/// assert_eq!(
+///     search_with_spans("rust", contents, true),
+///     vec![("Rust:", vec![0..4]), ("Trust me.", vec![1..5])]
+/// );
+/// ```
+pub fn search_with_spans<'a>(
+    query: &str,
+    contents: &'a str,
+    ignore_case: bool,
+) -> Vec<(&'a str, Vec<Range<usize>>)> {
+    let case = if ignore_case {
+        CaseMode::Insensitive
+    } else {
+        CaseMode::Sensitive
+    };
+    let searcher = Searcher::new(query, case, false);
+    searcher
+        .search(contents)
+        .into_iter()
+        .map(|line| (line, searcher.find_ranges(line)))
+        .collect()
+}
+
+/// Checks whether `line` contains `folded_query`, ignoring case.
+///
+/// `folded_query` must already be lowercased. For ASCII queries the line is
+/// ASCII-lowercased into `scratch`, a buffer reused across lines, so no `String`
+/// is allocated per line. The line is only fully lowercased into a new `String`
+/// when the query is non-ASCII, or when no match was found in a line containing
+/// non-ASCII characters (whose lowercase form may still contain the query, e.g.
+/// the Kelvin sign lowercases to `k`).
+fn contains_ignore_case(line: &str, folded_query: &str, scratch: &mut String) -> bool {
+    if folded_query.is_ascii() {
+        scratch.clear();
+        scratch.push_str(line);
+        scratch.make_ascii_lowercase();
+
+        if scratch.contains(folded_query) {
+            return true;
+        }
+        if line.is_ascii() {
+            return false;
+        }
+    }
+
+    line.to_lowercase().contains(folded_query)
+}
+
+/// Returns the part of `contents` covering the given 1-based, inclusive line range.
+///
+/// Lines past the end of `contents` are ignored, so a range that starts beyond
+/// the last line yields an empty string.
+///
+/// # Arguments
+///
+/// * `contents` - The text to slice.
+/// * `range` - The lines to keep.
+///
+/// # Example
+///
+/// ```
+/// use lilgrep::slice_lines;
+///
+/// let contents = "one\ntwo\nthree\nfour";
+/// assert_eq!(slice_lines(contents, &(2..=3)), "two\nthree\n");
+/// ```
+pub fn slice_lines<'a>(contents: &'a str, range: &RangeInclusive<usize>) -> &'a str {
+    slice_records(contents, range, '\n')
+}
+
+/// Like `slice_lines`, with lines ending with `separator`.
+pub(crate) fn slice_records<'a>(
+    contents: &'a str,
+    range: &RangeInclusive<usize>,
+    separator: char,
+) -> &'a str {
+    let mut lines = contents.split_inclusive(separator);
+    let begin: usize = lines
+        .by_ref()
+        .take(range.start().saturating_sub(1))
+        .map(str::len)
+        .sum();
+    // Not `range.count()`, which would walk an open-ended range to its end.
+    let count = if range.is_empty() {
+        0
+    } else {
+        (range.end() - range.start()).saturating_add(1)
+    };
+    let len: usize = lines.take(count).map(str::len).sum();
+
+    &contents[begin..begin + len]
+}
+
+/// Returns `line` with each of `ranges`, in order and never overlapping,
+/// replaced by `replacement`.
+pub(crate) fn replace_ranges(line: &str, ranges: Vec<Range<usize>>, replacement: &str) -> String {
+    let mut replaced = String::with_capacity(line.len());
+    let mut last = 0;
+
+    for range in ranges {
+        replaced.push_str(&line[last..range.start]);
+        replaced.push_str(replacement);
+        last = range.end;
+    }
+    replaced.push_str(&line[last..]);
+
+    replaced
+}
+
+/// Sorts matching lines lexicographically in the given order.
+///
+/// Ordering is plain `str` comparison, so `"10"` sorts before `"9"`.
+///
+/// # Arguments
+///
+/// * `lines` - The matching lines to sort in place.
+/// * `order` - Whether to sort ascending or descending.
+pub fn sort_lines(lines: &mut [&str], order: SortOrder) {
+    match order {
+        SortOrder::Ascending => lines.sort_unstable(),
+        SortOrder::Descending => lines.sort_unstable_by(|a, b| b.cmp(a)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_word_tries_overlapping_occurrences() {
+        assert!(contains_word("ab-ab ab", "ab ab"));
+        assert!(contains_word("aa a", "a"));
+        assert!(!contains_word("aaa", "aa"));
+        assert!(contains_word("", ""));
+    }
+
+    #[test]
+    fn search_callback_sees_what_search_returns() {
+        let contents = "Rust:\nsafe, fast, productive.\nPick three.\nTrust me.\r\n";
+        for query in ["", "ust", "st, fa", "e.", "nowhere"] {
+            let mut lines = Vec::new();
+            let flow = search_callback(query, contents, |found| {
+                lines.push(found.line);
+                ControlFlow::<()>::Continue(())
+            });
+            assert_eq!(flow, ControlFlow::Continue(()));
+            assert_eq!(lines, search(query, contents), "query {query:?}");
+        }
+    }
+
+    #[test]
+    fn long_queries_find_the_same_hits_as_match_indices() {
+        let word = "ab\u{e9}cdefghij";
+        let contents =
+            format!("{word}{word}{word}{word}\nxx {word}{word}{word}{word}\r\n{word}{word}{word}");
+        let queries = [
+            format!("{word}{word}{word}"),
+            format!("{word}{word}{word}\r"),
+            format!("j\r\n{word}{word}{word}"),
+            "z".repeat(40),
+        ];
+        for query in &queries {
+            assert!(query.len() >= HORSPOOL_MIN);
+            let expected: Vec<_> = contents.match_indices(query).map(|(hit, _)| hit).collect();
+            assert_eq!(Hits::new(query, &contents).collect::<Vec<_>>(), expected);
+            let by_line: Vec<_> = contents
+                .lines()
+                .filter(|line| line.contains(query.as_str()))
+                .collect();
+            assert_eq!(search(query, &contents), by_line);
+        }
+    }
+
+    #[test]
+    fn grouped_matches_are_keyed_by_line_number() {
+        let contents = "ERROR disk full, ERROR again\nall good\nlast ERROR\nno error";
+        let expected = BTreeMap::from([(1, vec![(0, 5), (17, 22)]), (3, vec![(5, 10)])]);
+        assert_eq!(search_grouped("ERROR", contents), expected);
+        assert!(search_grouped("missing", contents).is_empty());
+    }
+
+    #[test]
+    fn matches_carry_every_occurrence_on_the_line() {
+        let searcher = Searcher::new("fish", CaseMode::Insensitive, false);
+        let found = searcher.search_matches("no\nFish and fish\none fish");
+        assert_eq!(found[0].line_number, 2);
+        assert_eq!(found[0].matches, [(0, 4), (9, 13)]);
+        assert_eq!(found[1].matches, [(4, 8)]);
+        assert_eq!(found[0].to_owned().matches, found[0].matches);
+
+        let mut seen = Vec::new();
+        let _ = search_callback::<()>("ab", "abab\nb", |found| {
+            seen.push(found.matches);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(seen, [vec![(0, 2), (2, 4)]]);
+    }
+}
@@ -0,0 +1,322 @@
+//! Expanding the paths given on the command line into the files to search.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use crate::{Config, FileSort, Input, SearchError, Summary, warn};
+
+/// Adds `path` to `files`, or with `recursive` every file below it when it is a
+/// directory.
+///
+/// Symbolic links found while walking are followed into directories only with
+/// `follow`, in which case every directory's canonical path is remembered so a
+/// link cycle is walked once. Linked files are searched either way, as ripgrep
+/// does. Paths named on the command line are always followed.
+///
+/// With `max_depth`, directories are only descended into while their entries
+/// stay within that depth, where the directory's own entries are at depth 0.
+///
+/// Directories that cannot be read, for lack of permission or because they
+/// vanished, are reported on stderr and counted in `summary`; the rest of the
+/// walk, their siblings included, carries on.
+pub(crate) fn collect_files(
+    path: &Path,
+    config: &Config,
+    files: &mut Vec<Input>,
+    summary: &mut Summary,
+) {
+    if !config.recursive || !path.is_dir() {
+        add_named(path.to_path_buf(), config, files, summary);
+        return;
+    }
+
+    walk_dir(path, 0, config, &mut HashSet::new(), files, summary);
+}
+
+/// Separates the configured paths into those searched as they are and the
+/// directories to walk, as `collect_files` would.
+pub(crate) fn split_roots(config: &Config, summary: &mut Summary) -> (Vec<Input>, Vec<PathBuf>) {
+    let (named, dirs): (Vec<PathBuf>, _) = config
+        .file_paths
+        .iter()
+        .cloned()
+        .partition(|path| !config.recursive || !path.is_dir());
+    let mut files = Vec::with_capacity(named.len());
+    for path in named {
+        add_named(path, config, &mut files, summary);
+    }
+    (files, dirs)
+}
+
+/// Adds a file named on the command line to `files`, or with the `zip`
+/// feature the text entries of a zip archive in its place.
+///
+/// An archive that can't be read is reported on stderr and recorded in
+/// `summary`.
+fn add_named(path: PathBuf, config: &Config, files: &mut Vec<Input>, summary: &mut Summary) {
+    #[cfg(feature = "zip")]
+    match crate::zip::entries(&path) {
+        Some(Ok(entries)) => {
+            files.extend(entries.into_iter().map(|entry| Input {
+                path: path.clone(),
+                entry: Some(std::sync::Arc::new(entry)),
+            }));
+            return;
+        }
+        Some(Err(err)) => {
+            warn(config, &path, &SearchError::io("read", &path, err));
+            summary.had_errors = true;
+            summary.unreadable += 1;
+            return;
+        }
+        None => {}
+    }
+    #[cfg(not(feature = "zip"))]
+    let _ = (config, summary);
+    files.push(path.into());
+}
+
+/// Walks `dir`, whose entries are at `depth`.
+fn walk_dir(
+    dir: &Path,
+    depth: usize,
+    config: &Config,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<Input>,
+    summary: &mut Summary,
+) {
+    let listing = list_dir(dir, depth, config, |canonical| visited.insert(canonical));
+    summary.had_errors |= !listing.errors.is_empty();
+    summary.unreadable += listing.errors.len();
+    files.extend(listing.files.into_iter().map(Input::from));
+    for subdir in listing.dirs {
+        walk_dir(&subdir, depth + 1, config, visited, files, summary);
+    }
+}
+
+/// Walks every one of `dirs` on `threads` threads, passing each file to
+/// `found` as soon as it is found, on whichever thread found it.
+///
+/// Every directory is a separate piece of work on a shared stack, so a
+/// thread that runs out picks up directories discovered by the others. The
+/// rules are those of `collect_files`: each directory carries its own depth
+/// for `max_depth`, and with `follow` the canonical paths seen so far are
+/// shared, so each directory is still walked once however the threads
+/// interleave. The order files are found in is not deterministic.
+///
+/// When `found` returns `false` the walk stops early.
+pub(crate) fn walk_parallel<F>(
+    dirs: Vec<PathBuf>,
+    config: &Config,
+    threads: usize,
+    found: F,
+    summary: &mut Summary,
+) where
+    F: Fn(PathBuf) -> bool + Sync,
+{
+    let queue = Queue {
+        state: Mutex::new(QueueState {
+            dirs: dirs.into_iter().map(|dir| (dir, 0)).collect(),
+            busy: 0,
+            stopped: false,
+        }),
+        changed: Condvar::new(),
+    };
+    let visited = Mutex::new(HashSet::new());
+    let unreadable = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while let Some((dir, depth)) = queue.next() {
+                    let listing = list_dir(&dir, depth, config, |canonical| {
+                        visited.lock().unwrap().insert(canonical)
+                    });
+                    unreadable.fetch_add(listing.errors.len(), Ordering::Relaxed);
+                    let stopped = !listing.files.into_iter().all(&found);
+                    queue.finish(listing.dirs, depth + 1, stopped);
+                }
+            });
+        }
+    });
+
+    let unreadable = unreadable.into_inner();
+    summary.had_errors |= unreadable > 0;
+    summary.unreadable += unreadable;
+}
+
+/// The directories waiting to be walked by `walk_parallel`.
+struct Queue {
+    state: Mutex<QueueState>,
+    /// Signalled whenever directories are added or the walk ends.
+    changed: Condvar,
+}
+
+struct QueueState {
+    /// Each directory waiting to be walked, and the depth of its entries.
+    dirs: Vec<(PathBuf, usize)>,
+    /// How many directories are being walked right now.
+    busy: usize,
+    stopped: bool,
+}
+
+impl Queue {
+    /// Waits for a directory to walk, or returns `None` once every directory
+    /// has been walked.
+    fn next(&self) -> Option<(PathBuf, usize)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stopped {
+                return None;
+            }
+            if let Some(dir) = state.dirs.pop() {
+                state.busy += 1;
+                return Some(dir);
+            }
+            if state.busy == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Records that a directory given out by `next` has been walked, adding
+    /// the directories found in it, whose entries are at `depth`.
+    fn finish(&self, dirs: Vec<PathBuf>, depth: usize, stopped: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.busy -= 1;
+        state.stopped |= stopped;
+        state.dirs.extend(dirs.into_iter().map(|dir| (dir, depth)));
+        self.changed.notify_all();
+    }
+}
+
+/// What was found in a single directory.
+#[derive(Default)]
+struct Listing {
+    files: Vec<PathBuf>,
+    /// The directories to descend into next.
+    dirs: Vec<PathBuf>,
+    /// What couldn't be read, already reported.
+    errors: Vec<SearchError>,
+}
+
+/// Lists `dir`, whose entries are at `depth`, reporting anything that can't
+/// be read on stderr.
+///
+/// With `follow`, `first_visit` is given the directory's canonical path and
+/// decides whether it still needs walking.
+fn list_dir(
+    dir: &Path,
+    depth: usize,
+    config: &Config,
+    first_visit: impl FnOnce(PathBuf) -> bool,
+) -> Listing {
+    let mut listing = Listing::default();
+    let failed = |listing: &mut Listing, action, source| {
+        let err = SearchError::Io {
+            action,
+            path: dir.to_path_buf(),
+            source,
+        };
+        warn(config, dir, &err);
+        listing.errors.push(err);
+    };
+
+    if config.follow {
+        match fs::canonicalize(dir) {
+            Ok(canonical) => {
+                if !first_visit(canonical) {
+                    return listing;
+                }
+            }
+            Err(err) => {
+                failed(&mut listing, "resolve", err);
+                return listing;
+            }
+        }
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            failed(&mut listing, "list", err);
+            return listing;
+        }
+    };
+
+    for entry in entries {
+        let (path, file_type) = match entry.and_then(|entry| Ok((entry.path(), entry.file_type()?)))
+        {
+            Ok(entry) => entry,
+            Err(err) => {
+                failed(&mut listing, "list", err);
+                continue;
+            }
+        };
+
+        let is_dir = if file_type.is_symlink() {
+            path.is_dir()
+        } else {
+            file_type.is_dir()
+        };
+
+        if !is_dir {
+            listing.files.push(path);
+        } else if (!file_type.is_symlink() || config.follow)
+            && config.max_depth.is_none_or(|max| depth < max)
+        {
+            listing.dirs.push(path);
+        }
+    }
+
+    listing
+}
+
+/// Puts `files` into the order given by `sort`.
+///
+/// Sorting by modification time or size reads every file's metadata first;
+/// files whose metadata can't be read sort before the rest, and ties are
+/// broken by path. The entries of an archive keep their order in it.
+pub(crate) fn sort_files(files: &mut [Input], sort: FileSort) {
+    match sort {
+        FileSort::Path => files.sort_by(|a, b| a.path.cmp(&b.path)),
+        FileSort::Modified => files.sort_by_cached_key(|input| {
+            let modified = fs::metadata(&input.path)
+                .and_then(|meta| meta.modified())
+                .ok();
+            (modified, input.path.clone())
+        }),
+        FileSort::Size => files.sort_by_cached_key(|input| {
+            let size = fs::metadata(&input.path).map(|meta| meta.len()).ok();
+            (size, input.path.clone())
+        }),
+        FileSort::None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing_errors_name_the_directory() {
+        let dir = std::env::temp_dir().join(format!("lilgrep-{}-gone", std::process::id()));
+        let listing = list_dir(&dir, 0, &Config::default(), |_| true);
+        let [err] = &listing.errors[..] else {
+            panic!("expected one error");
+        };
+        assert!(
+            err.to_string()
+                .starts_with(&format!("failed to list '{}': ", dir.display()))
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Repeating a search whenever the searched files change.
+//!
+//! Changes are found by polling metadata, which keeps the crate free of
+//! platform notification APIs. A file counts as changed when its length,
+//! modification time or (on Unix) inode differs, so truncation and replacement
+//! by a new file are noticed as well as ordinary writes. With `recursive`,
+//! the directories walked are checked too, as their modification time changes
+//! whenever a file is created, removed or renamed in them, so files that
+//! didn't exist when the search began are found by the next one.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{Config, Files, Summary, matcher::Patterns};
+
+/// How often the files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the files must stay unchanged before the search is repeated, so a
+/// burst of writes triggers a single search.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Printed between the results of successive searches.
+const SEPARATOR: &str = "==> files changed, searching again <==";
+
+/// Searches, then searches again after every change, until an error occurs.
+///
+/// Output is flushed after each search, so interrupting the process with
+/// Ctrl-C never leaves a partial set of results buffered.
+pub(crate) fn watch(config: &Config, mut out: impl Write) -> Result<Summary, Box<dyn Error>> {
+    if config.debug_config {
+        eprintln!("{}", config.explain());
+    }
+
+    let matcher = Patterns::new(config)?;
+    let mut summary = Summary::default();
+
+    loop {
+        // Files too large to search are still watched, in case they shrink.
+        let mut files = crate::collect_files(config, &mut summary);
+        let mut paths: Vec<_> = files.iter().map(|input| input.path.clone()).collect();
+        files.retain(|input| !crate::skipped(config, &input.path));
+        let listed = Files::Listed(&files);
+        crate::search_files(config, &matcher, listed, &mut out, &mut summary)?;
+        out.flush()?;
+
+        paths.dedup();
+        if config.recursive {
+            for root in config.file_paths.iter().filter(|path| path.is_dir()) {
+                add_directories(root, 0, config, &mut HashSet::new(), &mut paths);
+            }
+        }
+        wait_for_change(&paths);
+        writeln!(out, "{SEPARATOR}")?;
+    }
+}
+
+/// Adds `dir` and every directory below it that the walk would descend into,
+/// whose entries are at `depth`, to `dirs`.
+///
+/// Directories that can't be listed are left out quietly; the search itself
+/// warns about them.
+fn add_directories(
+    dir: &Path,
+    depth: usize,
+    config: &Config,
+    visited: &mut HashSet<PathBuf>,
+    dirs: &mut Vec<PathBuf>,
+) {
+    if config.follow && !fs::canonicalize(dir).is_ok_and(|canonical| visited.insert(canonical)) {
+        return;
+    }
+    dirs.push(dir.to_path_buf());
+    if config.max_depth.is_some_and(|max| depth >= max) {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let descend = entry.file_type().is_ok_and(|file_type| {
+            file_type.is_dir() || (file_type.is_symlink() && config.follow && path.is_dir())
+        });
+        if descend {
+            add_directories(&path, depth + 1, config, visited, dirs);
+        }
+    }
+}
+
+/// Blocks until any of `files` changes and then stays unchanged for a while.
+fn wait_for_change(files: &[PathBuf]) {
+    let initial = fingerprints(files);
+
+    let mut latest = loop {
+        thread::sleep(POLL_INTERVAL);
+        let current = fingerprints(files);
+        if current != initial {
+            break current;
+        }
+    };
+
+    loop {
+        thread::sleep(DEBOUNCE);
+        let current = fingerprints(files);
+        if current == latest {
+            return;
+        }
+        latest = current;
+    }
+}
+
+/// What a file looked like when last checked; `None` when it doesn't exist.
+#[derive(Debug, PartialEq, Eq)]
+struct Fingerprint {
+    len: u64,
+    modified: Option<SystemTime>,
+    inode: u64,
+}
+
+fn fingerprints(files: &[PathBuf]) -> Vec<Option<Fingerprint>> {
+    files.iter().map(|path| fingerprint(path)).collect()
+}
+
+fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let metadata = fs::metadata(path).ok()?;
+
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+    #[cfg(not(unix))]
+    let inode = 0;
+
+    Some(Fingerprint {
+        len: metadata.len(),
+        modified: metadata.modified().ok(),
+        inode,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncation_and_replacement_change_the_fingerprint() {
+        let dir = std::env::temp_dir().join(format!("lilgrep-{}-watch", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log.txt");
+
+        fs::write(&path, "needle\n").unwrap();
+        let original = fingerprint(&path);
+        assert!(original.is_some());
+        assert_eq!(original, fingerprint(&path));
+
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        let truncated = fingerprint(&path);
+        assert_ne!(original, truncated);
+
+        // Only the inode tells an identical replacement apart.
+        #[cfg(unix)]
+        {
+            let replacement = dir.join("log.txt.new");
+            fs::write(&replacement, "").unwrap();
+            fs::rename(&replacement, &path).unwrap();
+            assert_ne!(truncated, fingerprint(&path));
+        }
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(None, fingerprint(&path));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn new_files_change_the_directories_watched() {
+        let root = std::env::temp_dir().join(format!("lilgrep-{}-watch-dirs", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::create_dir_all(root.join("deep").join("deeper")).unwrap();
+
+        let config = Config {
+            recursive: true,
+            max_depth: Some(1),
+            ..Config::default()
+        };
+        let mut dirs = Vec::new();
+        add_directories(&root, 0, &config, &mut HashSet::new(), &mut dirs);
+        dirs.sort();
+        let deep = root.join("deep");
+        assert_eq!(dirs, [root.clone(), deep, root.join("empty")]);
+
+        let before = fingerprints(&dirs);
+        // Modification times can be coarse.
+        thread::sleep(Duration::from_millis(50));
+        fs::write(root.join("empty").join("new.txt"), "needle\n").unwrap();
+        assert_ne!(before, fingerprints(&dirs));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! Searching the entries of zip archives, with the `zip` feature.
+//!
+//! The archive's central directory is read from its end, and each entry is
+//! inflated with the decoder in `gzip`, so the crate keeps having no
+//! dependencies. Only stored and deflated entries are read; zip64 archives and
+//! encrypted entries are not.
+//!
+//! The archive is read and its entries decoded once, when the paths to search
+//! are expanded; each text entry is then searched from memory, and shown as
+//! `archive.zip:name`. The entry's name is never made into a path, so an entry
+//! named like a file elsewhere can't lead to that file being read.
+
+use std::{fs, io, path::Path};
+
+use crate::gzip;
+
+const END_SIGNATURE: [u8; 4] = *b"PK\x05\x06";
+const CENTRAL_SIGNATURE: [u8; 4] = *b"PK\x01\x02";
+const LOCAL_SIGNATURE: [u8; 4] = *b"PK\x03\x04";
+
+/// Whether `path` is named like a zip archive.
+pub(crate) fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+/// A text entry of an archive, decoded by `entries`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct TextEntry {
+    /// The entry's name, as the archive gives it.
+    pub(crate) name: String,
+    pub(crate) contents: Vec<u8>,
+}
+
+/// Returns the entries of the archive at `path` that hold text, or `None` if
+/// `path` isn't a zip archive.
+///
+/// As with grep, an entry holds text unless it has a NUL byte. Directories,
+/// and entries stored in a way this can't read, are left out too, as are
+/// entries whose names are absolute or climb out with `..`, which no archive
+/// made in good faith has.
+pub(crate) fn entries(path: &Path) -> Option<io::Result<Vec<TextEntry>>> {
+    if !is_archive(path) || !path.is_file() {
+        return None;
+    }
+    let list = || {
+        let data = fs::read(path)?;
+        let mut found = Vec::new();
+        for entry in directory(&data).map_err(invalid)? {
+            if !entry.is_readable() || !is_safe_name(&entry.name) {
+                continue;
+            }
+            let contents = entry.contents(&data).map_err(invalid)?;
+            if !contents.contains(&0) {
+                found.push(TextEntry {
+                    name: entry.name,
+                    contents,
+                });
+            }
+        }
+        Ok(found)
+    };
+    Some(list())
+}
+
+/// Whether `name` stays inside the archive: it isn't absolute, on any system,
+/// and has no `..` among its parts.
+fn is_safe_name(name: &str) -> bool {
+    let absolute = name.starts_with(['/', '\\'])
+        || name.as_bytes().get(1) == Some(&b':')
+        || Path::new(name).is_absolute();
+    !absolute && !name.split(['/', '\\']).any(|part| part == "..")
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid zip archive: {message}"),
+    )
+}
+
+/// An entry as the central directory describes it.
+struct Entry {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    /// Where its local header starts.
+    offset: usize,
+}
+
+impl Entry {
+    /// Whether this is a file whose contents `contents` can decode.
+    fn is_readable(&self) -> bool {
+        const ENCRYPTED: u16 = 0x01;
+        !self.name.ends_with('/') && self.flags & ENCRYPTED == 0 && matches!(self.method, 0 | 8)
+    }
+
+    /// Decodes the entry's contents out of the archive `data`, checking their
+    /// length and CRC-32.
+    fn contents(&self, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+        if data.get(self.offset..self.offset + 4) != Some(&LOCAL_SIGNATURE[..]) {
+            return Err("missing local header");
+        }
+        let name_len = usize::from(u16_at(data, self.offset + 26)?);
+        let extra_len = usize::from(u16_at(data, self.offset + 28)?);
+        let start = self.offset + 30 + name_len + extra_len;
+        let raw = data
+            .get(start..start + self.compressed_size)
+            .ok_or("truncated entry")?;
+
+        let contents = match self.method {
+            0 => raw.to_vec(),
+            8 => {
+                // The recorded size is only believed as far as deflate
+                // allows, at most 1032 bytes out for each byte in.
+                let mut output = Vec::with_capacity(self.size.min(raw.len().saturating_mul(1032)));
+                gzip::inflate(raw, &mut output, self.size)?;
+                output
+            }
+            _ => return Err("unknown compression method"),
+        };
+        if contents.len() != self.size || gzip::crc32(&contents) != self.crc {
+            return Err("checksum mismatch");
+        }
+        Ok(contents)
+    }
+}
+
+/// Reads the central directory of the archive `data`.
+fn directory(data: &[u8]) -> Result<Vec<Entry>, &'static str> {
+    let end = (0..=data.len().saturating_sub(22))
+        .rev()
+        .find(|&pos| data[pos..].starts_with(&END_SIGNATURE))
+        .ok_or("no central directory")?;
+    let count = u16_at(data, end + 10)?;
+    let offset = u32_at(data, end + 16)?;
+    if offset == u32::MAX {
+        return Err("zip64 archives aren't supported");
+    }
+    let mut pos = offset as usize;
+
+    let mut entries = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        if data.get(pos..pos + 4) != Some(&CENTRAL_SIGNATURE[..]) {
+            return Err("corrupt central directory");
+        }
+        let name_len = usize::from(u16_at(data, pos + 28)?);
+        let extra_len = usize::from(u16_at(data, pos + 30)?);
+        let comment_len = usize::from(u16_at(data, pos + 32)?);
+        let name = data
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or("truncated central directory")?;
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            flags: u16_at(data, pos + 8)?,
+            method: u16_at(data, pos + 10)?,
+            crc: u32_at(data, pos + 16)?,
+            compressed_size: u32_at(data, pos + 20)? as usize,
+            size: u32_at(data, pos + 24)? as usize,
+            offset: u32_at(data, pos + 42)? as usize,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, &'static str> {
+    let bytes = data.get(pos..pos + 2).ok_or("truncated archive")?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, &'static str> {
+    let bytes = data.get(pos..pos + 4).ok_or("truncated archive")?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A deflated `app.log`, a `nested/` directory holding a stored
+    /// `notes.txt`, and a deflated `logo.png` with NUL bytes in it.
+    const LOGS: &[u8] = include_bytes!("../tests/fixtures/logs.zip");
+
+    #[test]
+    fn reads_stored_and_deflated_entries() {
+        let entries = directory(LOGS).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["app.log", "nested/", "nested/notes.txt", "logo.png"]
+        );
+        assert_eq!(
+            entries[0].contents(LOGS).unwrap(),
+            b"started\nerror: disk full\nstopped\n"
+        );
+        assert_eq!(
+            entries[2].contents(LOGS).unwrap(),
+            b"no errors here\nall fine\n"
+        );
+    }
+
+    #[test]
+    fn lists_only_text_entries() {
+        let path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/logs.zip"
+        ));
+        let found = entries(path).unwrap().unwrap();
+        let names: Vec<_> = found.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["app.log", "nested/notes.txt"]);
+        assert_eq!(found[1].contents, b"no errors here\nall fine\n");
+        assert!(entries(Path::new("poem.txt")).is_none());
+    }
+
+    #[test]
+    fn entry_names_must_stay_inside_the_archive() {
+        for name in [
+            "/tmp/zt/outside.txt",
+            "../outside.txt",
+            "a/../../b",
+            "\\x",
+            "C:\\x",
+        ] {
+            assert!(!is_safe_name(name), "{name}");
+        }
+        for name in ["a//b.txt", "notes..txt", "a/b/c"] {
+            assert!(is_safe_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_archives() {
+        assert!(directory(b"not a zip archive").is_err());
+        assert!(directory(&LOGS[..LOGS.len() - 22]).is_err());
+
+        let mut corrupt = LOGS.to_vec();
+        // Inside the stored contents of `nested/notes.txt`.
+        let at = corrupt
+            .windows(8)
+            .position(|window| window == b"all fine")
+            .unwrap();
+        corrupt[at] ^= 0x20;
+        let entries = directory(&corrupt).unwrap();
+        assert_eq!(entries[2].contents(&corrupt), Err("checksum mismatch"));
+    }
+
+    #[test]
+    fn recorded_sizes_bound_what_is_inflated() {
+        let mut entries = directory(LOGS).unwrap();
+        entries[0].size = u32::MAX as usize;
+        assert_eq!(entries[0].contents(LOGS), Err("checksum mismatch"));
+        entries[0].size = 4;
+        assert_eq!(entries[0].contents(LOGS), Err("more data than expected"));
+    }
+}
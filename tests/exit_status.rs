@@ -0,0 +1,77 @@
+use std::{env, fs, path::PathBuf, process::Command};
+
+fn lilgrep(args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_lilgrep"))
+        .arg("--no-config")
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("lilgrep-exit-{}-{name}", std::process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn a_match_exits_with_0() {
+    let file = temp_file("match.txt", "needle\n");
+    assert_eq!(lilgrep(&["needle", file.to_str().unwrap()]), Some(0));
+    assert_eq!(lilgrep(&["--help"]), Some(0));
+    assert_eq!(lilgrep(&["--files", file.to_str().unwrap()]), Some(0));
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn invert_files_exits_by_the_paths_it_prints() {
+    let none = temp_file("invert-none.txt", "haystack\n");
+    let some = temp_file("invert-some.txt", "needle\n");
+    let (none, some) = (none.to_str().unwrap(), some.to_str().unwrap());
+    // A file without the needle is printed, so that is a success.
+    assert_eq!(lilgrep(&["--invert-files", "needle", none]), Some(0));
+    assert_eq!(lilgrep(&["--invert-files", "needle", none, some]), Some(0));
+    assert_eq!(lilgrep(&["--invert-files", "needle", some]), Some(1));
+    // -L follows links here; it doesn't list files either way.
+    assert_eq!(lilgrep(&["-L", "needle", none]), Some(1));
+    for file in [none, some] {
+        fs::remove_file(file).unwrap();
+    }
+}
+
+#[test]
+fn no_match_exits_with_1() {
+    let file = temp_file("none.txt", "haystack\n");
+    assert_eq!(lilgrep(&["needle", file.to_str().unwrap()]), Some(1));
+    assert_eq!(lilgrep(&["-c", "needle", file.to_str().unwrap()]), Some(1));
+    fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn errors_exit_with_2() {
+    let file = temp_file("error.txt", "needle\n");
+    let file = file.to_str().unwrap();
+    // A bad argument, a missing query, and a file that can't be read.
+    assert_eq!(lilgrep(&["--no-such-flag", "needle", file]), Some(2));
+    assert_eq!(lilgrep(&["--max-count", "many", "needle", file]), Some(2));
+    assert_eq!(lilgrep(&[]), Some(2));
+    let missing = env::temp_dir().join("lilgrep-exit-missing.txt");
+    assert_eq!(
+        lilgrep(&["needle", file, missing.to_str().unwrap()]),
+        Some(2)
+    );
+    // The warning says which file, and what couldn't be done with it.
+    let output = Command::new(env!("CARGO_BIN_EXE_lilgrep"))
+        .args(["--no-config", "needle"])
+        .arg(&missing)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with(&format!(
+        "warning: failed to read '{}': ",
+        missing.display()
+    )));
+    fs::remove_file(file).unwrap();
+}
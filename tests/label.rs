@@ -0,0 +1,33 @@
+use std::{
+    env, fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[test]
+fn label_names_standard_input() {
+    let other = env::temp_dir().join(format!("lilgrep-label-{}.log", std::process::id()));
+    fs::write(&other, "ok\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lilgrep"))
+        .args(["--no-config", "--label", "build.log", "error", "-"])
+        .arg(&other)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"compiling\nerror: missing semicolon\nerror: aborting\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    fs::remove_file(&other).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "build.log:error: missing semicolon\nbuild.log:error: aborting\n"
+    );
+}
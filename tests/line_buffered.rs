@@ -0,0 +1,103 @@
+#![cfg(unix)]
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn line_buffered_output_arrives_before_eof() {
+    let dir = env::temp_dir().join(format!("lilgrep-line-buffered-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let ready = dir.join("a.txt");
+    let fifo = dir.join("b.fifo");
+    fs::write(&ready, "needle\n").unwrap();
+    assert!(
+        Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    // The second file is a FIFO that stays open until the first match has been
+    // seen, so the search can't reach EOF and flush on exit before then.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lilgrep"))
+        .args(["--line-buffered", "--threads", "1", "needle"])
+        .arg(&ready)
+        .arg(&fifo)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut line = String::new();
+        BufReader::new(stdout).read_line(&mut line).unwrap();
+        sender.send(line).unwrap();
+    });
+
+    let line = receiver.recv_timeout(Duration::from_secs(10));
+
+    drop(fs::OpenOptions::new().write(true).open(&fifo).unwrap());
+    assert!(child.wait().unwrap().success());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(format!("{}:needle\n", ready.display()), line.unwrap());
+}
+
+#[test]
+fn passthru_prints_each_line_as_it_is_read() {
+    let dir = env::temp_dir().join(format!("lilgrep-passthru-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let fifo = dir.join("log.fifo");
+    assert!(
+        Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap()
+            .success()
+    );
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lilgrep"))
+        .args(["--line-buffered", "--passthru", "needle"])
+        .arg(&fifo)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdout = BufReader::new(stdout);
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        sender.send(line).unwrap();
+        let mut rest = String::new();
+        stdout.read_to_string(&mut rest).unwrap();
+        sender.send(rest).unwrap();
+    });
+
+    // The FIFO stays open, so the file isn't done when the first line is read.
+    let mut writer = fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+    writer.write_all(b"no match yet\r\n").unwrap();
+    let line = receiver.recv_timeout(Duration::from_secs(10));
+
+    writer.write_all(b"needle\n").unwrap();
+    drop(writer);
+    assert!(child.wait().unwrap().success());
+    let rest = receiver.recv_timeout(Duration::from_secs(10));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!("no match yet\r\n", line.unwrap());
+    assert_eq!("needle\n", rest.unwrap());
+}
@@ -0,0 +1,24 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+#[test]
+fn skip_bytes_reads_past_the_start_of_standard_input() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lilgrep"))
+        .args(["--no-config", "--skip-bytes", "6", "o", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"one\ntwo\nthree\nfour\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "four\n");
+}